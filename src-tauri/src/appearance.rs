@@ -0,0 +1,362 @@
+use base64::prelude::*;
+use lopdf::{Dictionary, Document, Object, Stream};
+use serde::Deserialize;
+use std::error::Error;
+use std::io::Cursor;
+
+/// Text shown when the request gives no template.
+const DEFAULT_TEMPLATE: &str =
+    "Digitally signed by {name}\nDate: {date}\nReason: {reason}\nLocation: {location}";
+
+/// Largest font size the stamp text is set in; smaller if it does not fit.
+const MAX_FONT_SIZE: f64 = 12.0;
+
+/// Space kept free along the edges of the stamp, in points.
+const PADDING: f64 = 2.0;
+
+/// Helvetica advance widths for ASCII 32 to 126, in thousandths of the
+/// font size.
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556,
+    556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, 1015, 667, 667, 722, 722, 667,
+    611, 778, 722, 278, 500, 667, 556, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667,
+    667, 611, 278, 278, 278, 469, 556, 333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500,
+    222, 833, 556, 556, 556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+/// A visible signature stamp: where it goes and what it shows.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SignatureAppearance {
+    /// 1-based page number.
+    #[serde(default = "first_page")]
+    pub page: u32,
+    /// Lower-left and upper-right corners in PDF points, `[x1, y1, x2, y2]`.
+    pub rect: [f64; 4],
+    /// Text with `{name}`, `{date}`, `{reason}` and `{location}`
+    /// placeholders. Lines whose placeholders are all empty are left out.
+    pub text: Option<String>,
+    /// Base64-encoded JPEG or PNG, such as a scanned signature or a seal.
+    pub image: Option<String>,
+}
+
+fn first_page() -> u32 {
+    1
+}
+
+/// What the placeholders of a text template are filled with.
+pub struct StampText<'a> {
+    pub name: &'a str,
+    pub date: &'a str,
+    pub reason: Option<&'a str>,
+    pub location: Option<&'a str>,
+}
+
+impl SignatureAppearance {
+    pub fn width(&self) -> f64 {
+        self.rect[2] - self.rect[0]
+    }
+
+    pub fn height(&self) -> f64 {
+        self.rect[3] - self.rect[1]
+    }
+
+    /// Adds the appearance stream and the fonts and images it uses to
+    /// `document`, returning the stream to set as the widget's `/AP /N`.
+    pub fn render(
+        &self,
+        document: &mut Document,
+        text: &StampText,
+    ) -> Result<Stream, Box<dyn Error>> {
+        let (width, height) = (self.width(), self.height());
+        if width <= 0.0 || height <= 0.0 {
+            return Err("The signature rectangle is empty".into());
+        }
+
+        let lines = self.lines(text);
+        let mut content = Vec::new();
+        let mut resources = Dictionary::new();
+        let mut text_left = PADDING;
+
+        if let Some(image) = &self.image {
+            let image = Image::decode(&BASE64_STANDARD.decode(image)?)?;
+            let aspect = image.width as f64 / image.height as f64;
+            let area_width = if lines.is_empty() {
+                width
+            } else {
+                (width * 0.4).min(height * aspect)
+            };
+            let (draw_width, draw_height) =
+                fit(aspect, area_width - PADDING * 2.0, height - PADDING * 2.0);
+            let x = PADDING + (area_width - PADDING * 2.0 - draw_width) / 2.0;
+            let y = (height - draw_height) / 2.0;
+            content.extend(
+                format!(
+                    "q {:.2} 0 0 {:.2} {:.2} {:.2} cm /Im1 Do Q\n",
+                    draw_width, draw_height, x, y
+                )
+                .into_bytes(),
+            );
+            let image_id = image.add_to(document)?;
+            let mut xobjects = Dictionary::new();
+            xobjects.set("Im1", image_id);
+            resources.set("XObject", xobjects);
+            text_left = area_width + PADDING;
+        }
+
+        if !lines.is_empty() {
+            let encoded: Vec<Vec<u8>> = lines.iter().map(|line| win_ansi(line)).collect();
+            let text_width = width - text_left - PADDING;
+            let widest = encoded
+                .iter()
+                .map(|line| text_units(line))
+                .fold(0.0, f64::max);
+            let mut size =
+                ((height - PADDING * 2.0) / (encoded.len() as f64 * 1.2)).min(MAX_FONT_SIZE);
+            if widest > 0.0 {
+                size = size.min(text_width / widest);
+            }
+            let leading = size * 1.2;
+            let top = height - PADDING - size;
+            content.extend(
+                format!(
+                    "BT /F1 {:.2} Tf {:.2} TL {:.2} {:.2} Td\n",
+                    size, leading, text_left, top
+                )
+                .into_bytes(),
+            );
+            for (index, line) in encoded.iter().enumerate() {
+                if index > 0 {
+                    content.extend(b"T*\n");
+                }
+                content.extend(literal(line));
+                content.extend(b" Tj\n");
+            }
+            content.extend(b"ET\n");
+
+            let mut font = Dictionary::new();
+            font.set("Type", "Font");
+            font.set("Subtype", "Type1");
+            font.set("BaseFont", "Helvetica");
+            font.set("Encoding", "WinAnsiEncoding");
+            let mut fonts = Dictionary::new();
+            fonts.set("F1", document.add_object(font));
+            resources.set("Font", fonts);
+        }
+
+        let mut form = Dictionary::new();
+        form.set("Type", "XObject");
+        form.set("Subtype", "Form");
+        form.set(
+            "BBox",
+            vec![0.into(), 0.into(), width.into(), height.into()],
+        );
+        form.set("Resources", resources);
+        let mut stream = Stream::new(form, content);
+        stream.compress()?;
+        Ok(stream)
+    }
+
+    /// The template with its placeholders filled in, one entry per line.
+    fn lines(&self, text: &StampText) -> Vec<String> {
+        let template = self.text.as_deref().unwrap_or(DEFAULT_TEMPLATE);
+        let values = [
+            ("{name}", text.name),
+            ("{date}", text.date),
+            ("{reason}", text.reason.unwrap_or_default()),
+            ("{location}", text.location.unwrap_or_default()),
+        ];
+        template
+            .lines()
+            .filter(|line| {
+                let placeholders: Vec<_> = values
+                    .iter()
+                    .filter(|(key, _)| line.contains(key))
+                    .collect();
+                placeholders.is_empty() || placeholders.iter().any(|(_, value)| !value.is_empty())
+            })
+            .map(|line| {
+                values.iter().fold(line.to_string(), |line, (key, value)| {
+                    line.replace(key, value)
+                })
+            })
+            .collect()
+    }
+}
+
+/// The largest size with the given aspect ratio that fits in the box.
+fn fit(aspect: f64, width: f64, height: f64) -> (f64, f64) {
+    if width / height > aspect {
+        (height * aspect, height)
+    } else {
+        (width, width / aspect)
+    }
+}
+
+/// Encodes `text` for a WinAnsiEncoding font, replacing the Romanian
+/// letters the encoding lacks with their base letters.
+fn win_ansi(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c {
+            ' '..='~' => c as u8,
+            '\u{a0}'..='\u{ff}' => c as u32 as u8,
+            'ă' => b'a',
+            'Ă' => b'A',
+            'ș' | 'ş' => b's',
+            'Ș' | 'Ş' => b'S',
+            'ț' | 'ţ' => b't',
+            'Ț' | 'Ţ' => b'T',
+            _ => b'?',
+        })
+        .collect()
+}
+
+/// Width of a WinAnsi line in Helvetica at size 1.
+fn text_units(line: &[u8]) -> f64 {
+    line.iter()
+        .map(|&byte| match byte {
+            32..=126 => HELVETICA_WIDTHS[(byte - 32) as usize],
+            _ => 556,
+        } as f64)
+        .sum::<f64>()
+        / 1000.0
+}
+
+/// A PDF literal string for content streams.
+fn literal(text: &[u8]) -> Vec<u8> {
+    let mut out = vec![b'('];
+    for &byte in text {
+        if matches!(byte, b'(' | b')' | b'\\') {
+            out.push(b'\\');
+        }
+        out.push(byte);
+    }
+    out.push(b')');
+    out
+}
+
+/// An image ready to become an image XObject.
+struct Image {
+    width: u32,
+    height: u32,
+    color_space: &'static str,
+    /// JPEG data, embedded as is.
+    dct: Option<Vec<u8>>,
+    /// 8-bit samples without alpha.
+    samples: Vec<u8>,
+    /// 8-bit alpha samples, if the image has transparency.
+    alpha: Option<Vec<u8>>,
+}
+
+impl Image {
+    fn decode(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if data.starts_with(b"\xff\xd8") {
+            Self::jpeg(data)
+        } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Self::png(data)
+        } else {
+            Err("The signature image must be a JPEG or PNG".into())
+        }
+    }
+
+    /// Reads the size and components from the JPEG frame header.
+    fn jpeg(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut position = 2;
+        while position + 4 <= data.len() {
+            if data[position] != 0xff {
+                return Err("Malformed JPEG".into());
+            }
+            let marker = data[position + 1];
+            let length = u16::from_be_bytes([data[position + 2], data[position + 3]]) as usize;
+            let is_frame = matches!(marker, 0xc0..=0xcf) && !matches!(marker, 0xc4 | 0xc8 | 0xcc);
+            if is_frame && position + 10 <= data.len() {
+                let height = u16::from_be_bytes([data[position + 5], data[position + 6]]) as u32;
+                let width = u16::from_be_bytes([data[position + 7], data[position + 8]]) as u32;
+                let color_space = match data[position + 9] {
+                    1 => "DeviceGray",
+                    3 => "DeviceRGB",
+                    4 => "DeviceCMYK",
+                    _ => return Err("Unsupported JPEG color components".into()),
+                };
+                return Ok(Image {
+                    width,
+                    height,
+                    color_space,
+                    dct: Some(data.to_vec()),
+                    samples: Vec::new(),
+                    alpha: None,
+                });
+            }
+            position += 2 + length;
+        }
+        Err("The JPEG has no frame header".into())
+    }
+
+    fn png(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut decoder = png::Decoder::new(Cursor::new(data));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0; reader.output_buffer_size().ok_or("The PNG is too large")?];
+        let frame = reader.next_frame(&mut buffer)?;
+        buffer.truncate(frame.buffer_size());
+
+        let (channels, color_space) = match frame.color_type {
+            png::ColorType::Grayscale => (1, "DeviceGray"),
+            png::ColorType::GrayscaleAlpha => (2, "DeviceGray"),
+            png::ColorType::Rgb => (3, "DeviceRGB"),
+            png::ColorType::Rgba => (4, "DeviceRGB"),
+            png::ColorType::Indexed => return Err("Unsupported PNG color type".into()),
+        };
+        let (samples, alpha) = if channels % 2 == 0 {
+            let color = channels - 1;
+            let mut samples = Vec::with_capacity(buffer.len() / channels * color);
+            let mut alpha = Vec::with_capacity(buffer.len() / channels);
+            for pixel in buffer.chunks_exact(channels) {
+                samples.extend_from_slice(&pixel[..color]);
+                alpha.push(pixel[color]);
+            }
+            (samples, Some(alpha))
+        } else {
+            (buffer, None)
+        };
+        Ok(Image {
+            width: frame.width,
+            height: frame.height,
+            color_space,
+            dct: None,
+            samples,
+            alpha,
+        })
+    }
+
+    fn add_to(self, document: &mut Document) -> Result<lopdf::ObjectId, Box<dyn Error>> {
+        let dictionary = |color_space: &str| {
+            let mut dict = Dictionary::new();
+            dict.set("Type", "XObject");
+            dict.set("Subtype", "Image");
+            dict.set("Width", self.width);
+            dict.set("Height", self.height);
+            dict.set("ColorSpace", color_space);
+            dict.set("BitsPerComponent", 8);
+            dict
+        };
+
+        let mut image = dictionary(self.color_space);
+        if let Some(alpha) = &self.alpha {
+            let mut mask = Stream::new(dictionary("DeviceGray"), alpha.clone());
+            mask.compress()?;
+            image.set("SMask", document.add_object(mask));
+        }
+        let stream = match self.dct {
+            Some(jpeg) => {
+                image.set("Filter", "DCTDecode");
+                Stream::new(image, jpeg)
+            }
+            None => {
+                let mut stream = Stream::new(image, self.samples);
+                stream.compress()?;
+                stream
+            }
+        };
+        Ok(document.add_object(Object::Stream(stream)))
+    }
+}
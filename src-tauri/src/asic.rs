@@ -0,0 +1,545 @@
+use base64::prelude::*;
+use chrono::{Datelike, Local, Timelike};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::name::{Namespace, ResolveResult};
+use quick_xml::NsReader;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::io::{Cursor, Read, Seek, Write};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// Media type of an ASiC-E container, stored uncompressed as its first entry.
+pub const MIMETYPE: &str = "application/vnd.etsi.asic-e+zip";
+
+/// Where the CAdES signature over the manifest lives inside the container.
+pub const SIGNATURE_PATH: &str = "META-INF/signature001.p7s";
+
+const MANIFEST_PATH: &str = "META-INF/ASiCManifest.xml";
+
+/// XML Encryption identifier of SHA-256, the only manifest digest used.
+const SHA256_URI: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+
+/// A file to be placed in the container, with its SHA-256 digest.
+pub struct DataObject {
+    pub name: String,
+    pub digest: Vec<u8>,
+}
+
+/// Media types for the usual document formats; anything else is declared as
+/// opaque binary data.
+fn media_type(name: &str) -> &'static str {
+    let extension = name
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("pdf") => "application/pdf",
+        Some("xml") => "application/xml",
+        Some("txt") => "text/plain",
+        Some("json") => "application/json",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("doc") => "application/msword",
+        Some("docx") => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        Some("xls") => "application/vnd.ms-excel",
+        Some("xlsx") => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        Some("odt") => "application/vnd.oasis.opendocument.text",
+        Some("ods") => "application/vnd.oasis.opendocument.spreadsheet",
+        Some("zip") => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Rejects names that would clash with the container's own entries or could
+/// not be referenced from the manifest.
+fn check_names(objects: &[DataObject]) -> Result<(), Box<dyn Error>> {
+    if objects.is_empty() {
+        return Err("An ASiC container needs at least one file".into());
+    }
+    for (index, object) in objects.iter().enumerate() {
+        let name = &object.name;
+        if name.is_empty() || name.contains(['/', '\\']) {
+            return Err(format!("Invalid file name {:?}", name).into());
+        }
+        if name == "mimetype" || name.eq_ignore_ascii_case("META-INF") {
+            return Err(format!("{} is reserved in ASiC containers", name).into());
+        }
+        if objects[..index].iter().any(|other| &other.name == name) {
+            return Err(format!("More than one file is named {}", name).into());
+        }
+    }
+    Ok(())
+}
+
+/// Builds the ASiCManifest (ETSI EN 319 162-1, annex A) that the CAdES
+/// signature covers. The manifest lists the files in the order given, so
+/// the same files always produce the same manifest.
+pub fn manifest(objects: &[DataObject]) -> Result<Vec<u8>, Box<dyn Error>> {
+    check_names(objects)?;
+    let mut xml = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n",
+        "<asic:ASiCManifest xmlns:asic=\"http://uri.etsi.org/02918/v1.2.1#\"",
+        " xmlns:ds=\"http://www.w3.org/2000/09/xmldsig#\">\n",
+    ));
+    xml.push_str(&format!(
+        "  <asic:SigReference URI=\"{}\" MimeType=\"application/pkcs7-signature\"/>\n",
+        SIGNATURE_PATH
+    ));
+    for object in objects {
+        xml.push_str(&format!(
+            concat!(
+                "  <asic:DataObjectReference URI=\"{}\" MimeType=\"{}\">\n",
+                "    <ds:DigestMethod Algorithm=\"{}\"/>\n",
+                "    <ds:DigestValue>{}</ds:DigestValue>\n",
+                "  </asic:DataObjectReference>\n",
+            ),
+            urlencoding::encode(&object.name),
+            media_type(&object.name),
+            SHA256_URI,
+            BASE64_STANDARD.encode(&object.digest),
+        ));
+    }
+    xml.push_str("</asic:ASiCManifest>\n");
+    Ok(xml.into_bytes())
+}
+
+/// Writes an ASiC-E container: the `mimetype` entry first, then the signed
+/// files, then the manifest and its signature under `META-INF/`.
+pub struct ContainerWriter<W: Write + Seek> {
+    zip: ZipWriter<W>,
+    options: SimpleFileOptions,
+}
+
+impl<W: Write + Seek> ContainerWriter<W> {
+    pub fn new(writer: W) -> Result<Self, Box<dyn Error>> {
+        let now = Local::now();
+        let modified = zip::DateTime::from_date_and_time(
+            u16::try_from(now.year())?,
+            now.month() as u8,
+            now.day() as u8,
+            now.hour() as u8,
+            now.minute() as u8,
+            now.second() as u8,
+        )
+        .unwrap_or_default();
+        let options = SimpleFileOptions::default().last_modified_time(modified);
+
+        let mut zip = ZipWriter::new(writer);
+        zip.set_comment(format!("mimetype={}", MIMETYPE));
+        // The mimetype entry must be stored so readers can identify the
+        // container from its first bytes.
+        zip.start_file(
+            "mimetype",
+            options.compression_method(CompressionMethod::Stored),
+        )?;
+        zip.write_all(MIMETYPE.as_bytes())?;
+        Ok(ContainerWriter { zip, options })
+    }
+
+    /// Stores `content` as `name` and returns its SHA-256 digest, so a file
+    /// only has to be read once to be both listed and packed.
+    pub fn add_file(
+        &mut self,
+        name: &str,
+        content: &mut impl Read,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.zip.start_file(name, self.options)?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = content.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+            self.zip.write_all(&buffer[..read])?;
+        }
+        Ok(hasher.finalize().to_vec())
+    }
+
+    pub fn finish(mut self, manifest: &[u8], signature: &[u8]) -> Result<W, Box<dyn Error>> {
+        self.zip.start_file(MANIFEST_PATH, self.options)?;
+        self.zip.write_all(manifest)?;
+        self.zip.start_file(SIGNATURE_PATH, self.options)?;
+        self.zip.write_all(signature)?;
+        Ok(self.zip.finish()?)
+    }
+}
+
+/// The manifest and CAdES signature of a container written by
+/// `ContainerWriter`.
+pub fn read_signature(container: &[u8]) -> Result<(Vec<u8>, Vec<u8>), Box<dyn Error>> {
+    let mut archive = ZipArchive::new(Cursor::new(container))?;
+    let mut read_entry = |name: &str| -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut entry = archive
+            .by_name(name)
+            .map_err(|_| format!("The container has no {}", name))?;
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        Ok(data)
+    };
+    Ok((read_entry(MANIFEST_PATH)?, read_entry(SIGNATURE_PATH)?))
+}
+
+/// Namespace of the ASiC elements in a manifest.
+const ASIC_NAMESPACE: &[u8] = b"http://uri.etsi.org/02918/v1.2.1#";
+
+/// Namespace of the XML Signature elements in a manifest.
+const DSIG_NAMESPACE: &[u8] = b"http://www.w3.org/2000/09/xmldsig#";
+
+/// Where the element being read sits in an ASiCManifest.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ManifestElement {
+    Root,
+    Reference,
+    DigestMethod,
+    DigestValue,
+    /// Anything else, including extensions, whose content is skipped.
+    Other,
+}
+
+/// A `DataObjectReference` as it is read.
+#[derive(Default)]
+struct PartialReference {
+    uri: String,
+    algorithm: Option<String>,
+    digest: Option<String>,
+}
+
+fn unescaped_attribute(start: &BytesStart, name: &[u8]) -> Result<Option<String>, Box<dyn Error>> {
+    for attribute in start.attributes() {
+        let attribute = attribute?;
+        if attribute.key.as_ref() == name {
+            return Ok(Some(attribute.unescape_value()?.into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+/// The files an ASiCManifest references, with their SHA-256 digests. Only
+/// `DataObjectReference` elements directly under the root count, and only
+/// the `DigestMethod` and `DigestValue` directly under each of them.
+fn manifest_references(manifest: &[u8]) -> Result<Vec<DataObject>, Box<dyn Error>> {
+    let mut reader = NsReader::from_reader(manifest);
+    let mut path: Vec<ManifestElement> = Vec::new();
+    let mut current: Option<PartialReference> = None;
+    let mut objects = Vec::new();
+    let mut buffer = Vec::new();
+    loop {
+        let (namespace, event) = reader.read_resolved_event_into(&mut buffer)?;
+        let namespace = match namespace {
+            ResolveResult::Bound(Namespace(namespace)) => Some(namespace.to_vec()),
+            _ => None,
+        };
+        match event {
+            Event::Start(ref start) | Event::Empty(ref start) => {
+                let local_name = start.local_name();
+                let in_namespace = |expected: &[u8], name: &[u8]| {
+                    namespace.as_deref() == Some(expected) && local_name.as_ref() == name
+                };
+                let element = match path.last() {
+                    None if in_namespace(ASIC_NAMESPACE, b"ASiCManifest") => ManifestElement::Root,
+                    None => return Err("The manifest is not an ASiCManifest".into()),
+                    Some(ManifestElement::Root)
+                        if in_namespace(ASIC_NAMESPACE, b"DataObjectReference") =>
+                    {
+                        let uri = unescaped_attribute(start, b"URI")?
+                            .ok_or("A manifest reference has no URI")?;
+                        current = Some(PartialReference {
+                            uri,
+                            ..Default::default()
+                        });
+                        ManifestElement::Reference
+                    }
+                    Some(ManifestElement::Reference)
+                        if in_namespace(DSIG_NAMESPACE, b"DigestMethod") =>
+                    {
+                        let reference = current.as_mut().ok_or("Unexpected DigestMethod")?;
+                        if reference.algorithm.is_some() {
+                            return Err(format!("{} has two digest methods", reference.uri).into());
+                        }
+                        reference.algorithm =
+                            Some(unescaped_attribute(start, b"Algorithm")?.unwrap_or_default());
+                        ManifestElement::DigestMethod
+                    }
+                    Some(ManifestElement::Reference)
+                        if in_namespace(DSIG_NAMESPACE, b"DigestValue") =>
+                    {
+                        let reference = current.as_mut().ok_or("Unexpected DigestValue")?;
+                        if reference.digest.is_some() {
+                            return Err(format!("{} has two digest values", reference.uri).into());
+                        }
+                        reference.digest = Some(String::new());
+                        ManifestElement::DigestValue
+                    }
+                    Some(_) => ManifestElement::Other,
+                };
+                path.push(element);
+                if matches!(event, Event::Empty(_)) {
+                    finish_element(&mut path, &mut current, &mut objects)?;
+                }
+            }
+            Event::End(_) => finish_element(&mut path, &mut current, &mut objects)?,
+            Event::Text(ref text) if path.last() == Some(&ManifestElement::DigestValue) => {
+                if let Some(digest) = current.as_mut().and_then(|r| r.digest.as_mut()) {
+                    digest.push_str(&text.unescape()?);
+                }
+            }
+            Event::CData(ref data) if path.last() == Some(&ManifestElement::DigestValue) => {
+                if let Some(digest) = current.as_mut().and_then(|r| r.digest.as_mut()) {
+                    digest.push_str(std::str::from_utf8(data)?);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buffer.clear();
+    }
+    if !path.is_empty() {
+        return Err("The manifest ends inside an element".into());
+    }
+    if objects.is_empty() {
+        return Err("The manifest references no files".into());
+    }
+    Ok(objects)
+}
+
+/// Closes the innermost element, turning a finished `DataObjectReference`
+/// into a `DataObject`.
+fn finish_element(
+    path: &mut Vec<ManifestElement>,
+    current: &mut Option<PartialReference>,
+    objects: &mut Vec<DataObject>,
+) -> Result<(), Box<dyn Error>> {
+    if path.pop() != Some(ManifestElement::Reference) {
+        return Ok(());
+    }
+    let reference = current
+        .take()
+        .ok_or("Unexpected end of a manifest reference")?;
+    let name = urlencoding::decode(&reference.uri)?.into_owned();
+    if reference.algorithm.as_deref() != Some(SHA256_URI) {
+        return Err(format!("{} is not referenced with a SHA-256 digest", name).into());
+    }
+    let digest = reference
+        .digest
+        .ok_or_else(|| format!("The manifest has no digest for {}", name))?;
+    let digest = BASE64_STANDARD.decode(digest.split_whitespace().collect::<String>())?;
+    objects.push(DataObject { name, digest });
+    Ok(())
+}
+
+/// Checks that every file `manifest` references is in `container` with
+/// the digest the manifest gives it.
+pub fn check_manifest(container: &[u8], manifest: &[u8]) -> Result<(), Box<dyn Error>> {
+    let mut archive = ZipArchive::new(Cursor::new(container))?;
+    for object in manifest_references(manifest)? {
+        let mut entry = archive
+            .by_name(&object.name)
+            .map_err(|_| format!("The container has no {}", object.name))?;
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut entry, &mut hasher)?;
+        if hasher.finalize().as_slice() != object.digest {
+            return Err(format!("{} does not match the manifest's digest", object.name).into());
+        }
+    }
+    Ok(())
+}
+
+/// A copy of `container` with its CAdES signature replaced, compressed the
+/// way the old one was. Every other entry is copied unchanged.
+pub fn replace_signature(container: &[u8], signature: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut archive = ZipArchive::new(Cursor::new(container))?;
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    zip.set_raw_comment(archive.comment().into());
+    for index in 0..archive.len() {
+        let entry = archive.by_index_raw(index)?;
+        if entry.name() == SIGNATURE_PATH {
+            let options = SimpleFileOptions::default()
+                .compression_method(entry.compression())
+                .last_modified_time(entry.last_modified().unwrap_or_default());
+            drop(entry);
+            zip.start_file(SIGNATURE_PATH, options)?;
+            zip.write_all(signature)?;
+        } else {
+            zip.raw_copy_file(entry)?;
+        }
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(name: &str, content: &[u8]) -> DataObject {
+        DataObject {
+            name: name.into(),
+            digest: Sha256::digest(content).to_vec(),
+        }
+    }
+
+    /// A container holding `files`, with a manifest that lists them and a
+    /// dummy signature.
+    fn container(files: &[(&str, &[u8])]) -> (Vec<u8>, Vec<u8>) {
+        let mut writer = ContainerWriter::new(Cursor::new(Vec::new())).unwrap();
+        let mut objects = Vec::new();
+        for (name, content) in files {
+            let digest = writer.add_file(name, &mut &content[..]).unwrap();
+            objects.push(DataObject {
+                name: name.to_string(),
+                digest,
+            });
+        }
+        let manifest = manifest(&objects).unwrap();
+        let container = writer.finish(&manifest, b"signature").unwrap();
+        (container.into_inner(), manifest)
+    }
+
+    /// A manifest with one reference whose children are `body`.
+    fn manifest_with(uri: &str, body: &str) -> Vec<u8> {
+        format!(
+            concat!(
+                "<asic:ASiCManifest xmlns:asic=\"http://uri.etsi.org/02918/v1.2.1#\"",
+                " xmlns:ds=\"http://www.w3.org/2000/09/xmldsig#\">",
+                "<asic:DataObjectReference URI=\"{}\">{}</asic:DataObjectReference>",
+                "</asic:ASiCManifest>",
+            ),
+            uri, body
+        )
+        .into_bytes()
+    }
+
+    fn digest_elements(content: &[u8]) -> String {
+        format!(
+            "<ds:DigestMethod Algorithm=\"{}\"/><ds:DigestValue>{}</ds:DigestValue>",
+            SHA256_URI,
+            BASE64_STANDARD.encode(Sha256::digest(content))
+        )
+    }
+
+    #[test]
+    fn manifest_round_trips() {
+        let objects = [object("a b.pdf", b"first"), object("c&d.txt", b"second")];
+        let references = manifest_references(&manifest(&objects).unwrap()).unwrap();
+        assert_eq!(references.len(), 2);
+        for (reference, object) in references.iter().zip(&objects) {
+            assert_eq!(reference.name, object.name);
+            assert_eq!(reference.digest, object.digest);
+        }
+    }
+
+    #[test]
+    fn reserved_and_duplicate_names_are_rejected() {
+        assert!(manifest(&[]).is_err());
+        for name in ["", "dir/file.txt", "dir\\file.txt", "mimetype", "meta-inf"] {
+            assert!(manifest(&[object(name, b"x")]).is_err(), "{:?}", name);
+        }
+        assert!(manifest(&[object("a.txt", b"x"), object("a.txt", b"y")]).is_err());
+    }
+
+    #[test]
+    fn untouched_container_matches_its_manifest() {
+        let (container, manifest) = container(&[("a.txt", b"first"), ("b.txt", b"second")]);
+        check_manifest(&container, &manifest).unwrap();
+        let (read_manifest, signature) = read_signature(&container).unwrap();
+        assert_eq!(read_manifest, manifest);
+        assert_eq!(signature, b"signature");
+    }
+
+    #[test]
+    fn tampered_file_is_detected() {
+        let (_, manifest) = container(&[("a.txt", b"first")]);
+        let (tampered, _) = container(&[("a.txt", b"forged")]);
+        assert!(check_manifest(&tampered, &manifest).is_err());
+    }
+
+    #[test]
+    fn missing_file_is_detected() {
+        let (_, manifest) = container(&[("a.txt", b"first"), ("b.txt", b"second")]);
+        let (partial, _) = container(&[("a.txt", b"first")]);
+        assert!(check_manifest(&partial, &manifest).is_err());
+    }
+
+    #[test]
+    fn comments_and_cdata_are_read_as_xml() {
+        let body = format!(
+            "<!-- <ds:DigestValue>AAAA</ds:DigestValue> --><ds:DigestMethod Algorithm=\"{}\"/><ds:DigestValue><![CDATA[{}]]></ds:DigestValue>",
+            SHA256_URI,
+            BASE64_STANDARD.encode(Sha256::digest(b"first"))
+        );
+        let references = manifest_references(&manifest_with("a.txt", &body)).unwrap();
+        assert_eq!(references[0].digest, Sha256::digest(b"first").to_vec());
+    }
+
+    #[test]
+    fn nested_digest_is_ignored() {
+        let body = format!(
+            "<ext><ds:DigestMethod Algorithm=\"{}\"/></ext><ds:DigestMethod Algorithm=\"http://www.w3.org/2000/09/xmldsig#sha1\"/><ds:DigestValue>AAAA</ds:DigestValue>",
+            SHA256_URI
+        );
+        assert!(manifest_references(&manifest_with("a.txt", &body)).is_err());
+    }
+
+    #[test]
+    fn attribute_names_must_match_exactly() {
+        let manifest = String::from_utf8(manifest_with("a.txt", &digest_elements(b"x")))
+            .unwrap()
+            .replace("URI=", "DataURI=\"b.txt\" URI=");
+        let references = manifest_references(manifest.as_bytes()).unwrap();
+        assert_eq!(references[0].name, "a.txt");
+    }
+
+    #[test]
+    fn elements_in_other_namespaces_are_not_references() {
+        let manifest = String::from_utf8(manifest_with("a.txt", &digest_elements(b"x")))
+            .unwrap()
+            .replace(
+                "xmlns:ds=\"http://www.w3.org/2000/09/xmldsig#\"",
+                "xmlns:ds=\"urn:other\"",
+            );
+        assert!(manifest_references(manifest.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn duplicate_digests_are_rejected() {
+        let body = format!("{}{}", digest_elements(b"x"), digest_elements(b"y"));
+        assert!(manifest_references(&manifest_with("a.txt", &body)).is_err());
+    }
+
+    #[test]
+    fn replaced_signature_keeps_compression() {
+        let (container, manifest) = container(&[("a.txt", b"first")]);
+        let replaced = replace_signature(&container, b"new signature").unwrap();
+        let (read_manifest, signature) = read_signature(&replaced).unwrap();
+        assert_eq!(read_manifest, manifest);
+        assert_eq!(signature, b"new signature");
+
+        let mut before = ZipArchive::new(Cursor::new(&container)).unwrap();
+        let mut after = ZipArchive::new(Cursor::new(&replaced)).unwrap();
+        assert_eq!(after.comment(), before.comment());
+        for index in 0..before.len() {
+            let old = before.by_index_raw(index).unwrap();
+            let (name, compression) = (old.name().to_string(), old.compression());
+            drop(old);
+            assert_eq!(after.by_index_raw(index).unwrap().name(), name);
+            assert_eq!(after.by_name(&name).unwrap().compression(), compression);
+        }
+    }
+
+    #[test]
+    fn stored_signature_stays_stored() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        zip.start_file("mimetype", stored).unwrap();
+        zip.write_all(MIMETYPE.as_bytes()).unwrap();
+        zip.start_file(SIGNATURE_PATH, stored).unwrap();
+        zip.write_all(b"old").unwrap();
+        let container = zip.finish().unwrap().into_inner();
+
+        let replaced = replace_signature(&container, b"new").unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(replaced)).unwrap();
+        let entry = archive.by_name(SIGNATURE_PATH).unwrap();
+        assert_eq!(entry.compression(), CompressionMethod::Stored);
+    }
+}
@@ -0,0 +1,399 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const AUDIT_FILE: &str = "audit.log";
+/// Sequence number and hash of the last entry, kept apart from the log so
+/// that removing trailing entries is detected.
+const HEAD_FILE: &str = "audit.head";
+
+/// `prev_hash` of the first entry in a log.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    /// A signature was produced and returned to the caller.
+    Signed,
+    /// The request was refused before the user was asked for a PIN.
+    Rejected,
+    /// The user was prompted but no signature was produced.
+    Failed,
+}
+
+/// The caller-supplied part of an audit entry.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub origin: Option<String>,
+    /// Hex SHA-256 of the DER signing certificate.
+    pub cert_fingerprint: Option<String>,
+    pub doc_hash: String,
+    pub algorithm: String,
+    pub outcome: AuditOutcome,
+    pub error: Option<String>,
+}
+
+/// One line of the audit log. `hash` covers every other field, including
+/// `prev_hash`, so editing or removing an entry breaks the chain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub seq: u64,
+    /// RFC 3339 time the entry was written.
+    pub time: String,
+    #[serde(flatten)]
+    pub record: AuditRecord,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    fn compute_hash(&self) -> Result<String, Box<dyn Error>> {
+        let mut value = serde_json::to_value(self)?;
+        if let Some(fields) = value.as_object_mut() {
+            fields.remove("hash");
+        }
+        Ok(hex::encode(Sha256::digest(serde_json::to_vec(&value)?)))
+    }
+}
+
+/// Contents of the head file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AuditHead {
+    seq: u64,
+    hash: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditVerification {
+    pub valid: bool,
+    pub entries: u64,
+    /// Sequence number of the first entry that does not chain correctly.
+    pub broken_at: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// Append-only, hash-chained log of signing operations, stored as JSON lines
+/// in the app data directory. The head of the chain is also written to a
+/// separate file, so truncating or deleting the log does not verify unless
+/// the head file is rewritten too.
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    head_path: PathBuf,
+    /// `(seq, hash)` of the last entry written, loaded lazily from disk.
+    head: Mutex<Option<(u64, String)>>,
+}
+
+impl AuditLog {
+    pub fn new(data_dir: &Path) -> Self {
+        AuditLog {
+            path: data_dir.join(AUDIT_FILE),
+            head_path: data_dir.join(HEAD_FILE),
+            head: Mutex::new(None),
+        }
+    }
+
+    pub fn append(&self, record: AuditRecord) -> Result<AuditEntry, Box<dyn Error>> {
+        let mut head = self.head.lock().map_err(|_| "Audit log lock poisoned")?;
+        let (last_seq, prev_hash) = match head.as_ref() {
+            Some(head) => head.clone(),
+            None => match self.read_entries()?.last() {
+                Some(last) => (last.seq, last.hash.clone()),
+                None => (0, GENESIS_HASH.to_string()),
+            },
+        };
+
+        let mut entry = AuditEntry {
+            seq: last_seq + 1,
+            time: chrono::Utc::now().to_rfc3339(),
+            record,
+            prev_hash,
+            hash: String::new(),
+        };
+        entry.hash = entry.compute_hash()?;
+
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        file.sync_data()?;
+        self.write_head(&AuditHead {
+            seq: entry.seq,
+            hash: entry.hash.clone(),
+        })?;
+
+        *head = Some((entry.seq, entry.hash.clone()));
+        Ok(entry)
+    }
+
+    /// Replaces the head file through a rename, so a crash leaves either
+    /// the old head or the new one.
+    fn write_head(&self, head: &AuditHead) -> Result<(), Box<dyn Error>> {
+        let temp = self.head_path.with_extension("head.tmp");
+        let mut file = std::fs::File::create(&temp)?;
+        file.write_all(&serde_json::to_vec(head)?)?;
+        file.sync_data()?;
+        std::fs::rename(&temp, &self.head_path)?;
+        Ok(())
+    }
+
+    fn read_head(&self) -> Result<Option<AuditHead>, Box<dyn Error>> {
+        if !self.head_path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&std::fs::read(
+            &self.head_path,
+        )?)?))
+    }
+
+    fn read_lines(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let file = std::fs::File::open(&self.path)?;
+        let mut lines = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                lines.push(line);
+            }
+        }
+        Ok(lines)
+    }
+
+    pub fn read_entries(&self) -> Result<Vec<AuditEntry>, Box<dyn Error>> {
+        self.read_lines()?
+            .iter()
+            .map(|line| Ok(serde_json::from_str(line)?))
+            .collect()
+    }
+
+    /// Walks the chain and reports the first entry whose hash, sequence
+    /// number or back-link does not match, then checks that the chain ends
+    /// at the recorded head.
+    pub fn verify(&self) -> Result<AuditVerification, Box<dyn Error>> {
+        let lines = self.read_lines()?;
+        let total = lines.len() as u64;
+        let head = match self.read_head() {
+            Ok(head) => head,
+            Err(e) => {
+                return Ok(AuditVerification {
+                    valid: false,
+                    entries: total,
+                    broken_at: None,
+                    error: Some(format!("head file is unreadable: {}", e)),
+                })
+            }
+        };
+        let mut prev_hash = GENESIS_HASH.to_string();
+        for (index, line) in lines.iter().enumerate() {
+            let expected_seq = index as u64 + 1;
+            let error = match serde_json::from_str::<AuditEntry>(line) {
+                Err(e) => Some(format!("entry is not valid JSON: {}", e)),
+                Ok(entry) if entry.seq != expected_seq => {
+                    Some(format!("expected sequence number {}", expected_seq))
+                }
+                Ok(entry) if entry.prev_hash != prev_hash => {
+                    Some("entry does not link to the previous entry".to_string())
+                }
+                Ok(entry) if entry.compute_hash()? != entry.hash => {
+                    Some("entry contents do not match its hash".to_string())
+                }
+                Ok(entry) => {
+                    prev_hash = entry.hash;
+                    None
+                }
+            };
+            if error.is_some() {
+                return Ok(AuditVerification {
+                    valid: false,
+                    entries: total,
+                    broken_at: Some(expected_seq),
+                    error,
+                });
+            }
+        }
+        let (head_seq, head_hash) = match head {
+            Some(head) => (head.seq, head.hash),
+            None => (0, GENESIS_HASH.to_string()),
+        };
+        let mismatch = if head_seq != total {
+            Some((
+                total.min(head_seq) + 1,
+                format!(
+                    "log ends at entry {} but the head file records entry {}",
+                    total, head_seq
+                ),
+            ))
+        } else if head_hash != prev_hash {
+            Some((total, "last entry does not match the head file".to_string()))
+        } else {
+            None
+        };
+        if let Some((broken_at, error)) = mismatch {
+            return Ok(AuditVerification {
+                valid: false,
+                entries: total,
+                broken_at: Some(broken_at),
+                error: Some(error),
+            });
+        }
+        Ok(AuditVerification {
+            valid: true,
+            entries: total,
+            broken_at: None,
+            error: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(doc: &str) -> AuditRecord {
+        AuditRecord {
+            origin: Some("https://app.gov-smart.example".into()),
+            cert_fingerprint: Some("ab".repeat(32)),
+            doc_hash: doc.into(),
+            algorithm: "SHA-256".into(),
+            outcome: AuditOutcome::Signed,
+            error: None,
+        }
+    }
+
+    /// A log with three entries, and its lines as written.
+    fn written_log(dir: &Path) -> (AuditLog, Vec<String>) {
+        let log = AuditLog::new(dir);
+        for doc in ["one", "two", "three"] {
+            log.append(record(doc)).unwrap();
+        }
+        let lines = log.read_lines().unwrap();
+        (log, lines)
+    }
+
+    fn rewrite(dir: &Path, lines: &[String]) -> AuditVerification {
+        let mut contents = lines.join("\n");
+        contents.push('\n');
+        std::fs::write(dir.join(AUDIT_FILE), contents).unwrap();
+        AuditLog::new(dir).verify().unwrap()
+    }
+
+    #[test]
+    fn appended_entries_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let (log, _) = written_log(dir.path());
+        let verification = log.verify().unwrap();
+        assert!(verification.valid, "{:?}", verification.error);
+        assert_eq!(verification.entries, 3);
+
+        let entries = log.read_entries().unwrap();
+        assert_eq!(entries[0].prev_hash, GENESIS_HASH);
+        assert_eq!(entries[2].prev_hash, entries[1].hash);
+    }
+
+    #[test]
+    fn reopened_log_continues_the_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        written_log(dir.path());
+        let log = AuditLog::new(dir.path());
+        assert_eq!(log.append(record("four")).unwrap().seq, 4);
+        assert!(log.verify().unwrap().valid);
+    }
+
+    #[test]
+    fn empty_log_verifies() {
+        let dir = tempfile::tempdir().unwrap();
+        let verification = AuditLog::new(dir.path()).verify().unwrap();
+        assert!(verification.valid);
+        assert_eq!(verification.entries, 0);
+    }
+
+    #[test]
+    fn edited_entry_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, mut lines) = written_log(dir.path());
+        lines[1] = lines[1].replace("\"two\"", "\"forged\"");
+        let verification = rewrite(dir.path(), &lines);
+        assert!(!verification.valid);
+        assert_eq!(verification.broken_at, Some(2));
+    }
+
+    #[test]
+    fn rehashed_entry_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, mut lines) = written_log(dir.path());
+        let mut entry: AuditEntry = serde_json::from_str(&lines[1]).unwrap();
+        entry.record.doc_hash = "forged".into();
+        entry.hash = entry.compute_hash().unwrap();
+        lines[1] = serde_json::to_string(&entry).unwrap();
+        let verification = rewrite(dir.path(), &lines);
+        assert!(!verification.valid);
+        assert_eq!(verification.broken_at, Some(3));
+    }
+
+    #[test]
+    fn deleted_entry_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, mut lines) = written_log(dir.path());
+        lines.remove(1);
+        let verification = rewrite(dir.path(), &lines);
+        assert!(!verification.valid);
+        assert_eq!(verification.broken_at, Some(2));
+    }
+
+    #[test]
+    fn reordered_entries_are_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, mut lines) = written_log(dir.path());
+        lines.swap(1, 2);
+        let verification = rewrite(dir.path(), &lines);
+        assert!(!verification.valid);
+        assert_eq!(verification.broken_at, Some(2));
+    }
+
+    #[test]
+    fn truncated_log_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, lines) = written_log(dir.path());
+        let verification = rewrite(dir.path(), &lines[..2]);
+        assert!(!verification.valid);
+        assert_eq!(verification.broken_at, Some(3));
+
+        let verification = rewrite(dir.path(), &[]);
+        assert!(!verification.valid);
+        assert_eq!(verification.broken_at, Some(1));
+    }
+
+    #[test]
+    fn head_mismatch_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let (log, _) = written_log(dir.path());
+        log.write_head(&AuditHead {
+            seq: 3,
+            hash: GENESIS_HASH.into(),
+        })
+        .unwrap();
+        let verification = log.verify().unwrap();
+        assert!(!verification.valid);
+        assert_eq!(verification.broken_at, Some(3));
+    }
+
+    #[test]
+    fn unreadable_head_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let (log, _) = written_log(dir.path());
+        std::fs::write(dir.path().join(HEAD_FILE), "not json").unwrap();
+        let verification = log.verify().unwrap();
+        assert!(!verification.valid);
+        assert!(verification.error.unwrap().contains("head file"));
+    }
+}
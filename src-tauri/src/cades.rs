@@ -0,0 +1,698 @@
+use chrono::{DateTime, Utc};
+use cms::cert::{CertificateChoices, IssuerAndSerialNumber};
+use cms::content_info::{CmsVersion, ContentInfo};
+use cms::signed_data::{
+    CertificateSet, EncapsulatedContentInfo, SignedAttributes, SignedData, SignerIdentifier,
+    SignerInfo, SignerInfos,
+};
+use der::asn1::{OctetString, SetOfVec, UintRef, UtcTime};
+use der::oid::db::{rfc5911, rfc5912};
+use der::oid::ObjectIdentifier;
+use der::{Any, Decode, Encode, Length, Reader, Sequence, SliceReader};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::time::Duration;
+use x509_cert::attr::Attribute;
+use x509_cert::spki::AlgorithmIdentifierOwned;
+use x509_cert::time::Time;
+use x509_cert::Certificate;
+
+/// `id-aa-signatureTimeStampToken` from RFC 3161 appendix A.
+const ID_AA_SIGNATURE_TIME_STAMP_TOKEN: ObjectIdentifier =
+    ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.16.2.14");
+
+/// Key types the token can sign CMS structures with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAlgorithm {
+    Rsa,
+    Ec,
+}
+
+impl KeyAlgorithm {
+    fn of_certificate(cert: &Certificate) -> Result<Self, Box<dyn Error>> {
+        let oid = cert.tbs_certificate.subject_public_key_info.algorithm.oid;
+        if oid == rfc5912::RSA_ENCRYPTION {
+            Ok(KeyAlgorithm::Rsa)
+        } else if oid == rfc5912::ID_EC_PUBLIC_KEY {
+            Ok(KeyAlgorithm::Ec)
+        } else {
+            Err(format!("Unsupported certificate key algorithm {}", oid).into())
+        }
+    }
+
+    /// What to hand to `sign_hash_with_cert` to sign `tbs` with SHA-256:
+    /// the RSA mechanism hashes its input, the ECDSA one does not.
+    pub fn token_input(self, tbs: &[u8]) -> Vec<u8> {
+        match self {
+            KeyAlgorithm::Rsa => tbs.to_vec(),
+            KeyAlgorithm::Ec => Sha256::digest(tbs).to_vec(),
+        }
+    }
+
+    /// Converts a token signature into its CMS encoding. PKCS#11 returns
+    /// ECDSA signatures as `r || s`, CMS wants an `Ecdsa-Sig-Value`.
+    pub fn encode_signature(self, raw: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        match self {
+            KeyAlgorithm::Rsa => Ok(raw.to_vec()),
+            KeyAlgorithm::Ec => {
+                if raw.is_empty() || !raw.len().is_multiple_of(2) {
+                    return Err("Malformed ECDSA signature from token".into());
+                }
+                let (r, s) = raw.split_at(raw.len() / 2);
+                let signature = EcdsaSignature {
+                    r: UintRef::new(r)?,
+                    s: UintRef::new(s)?,
+                };
+                Ok(signature.to_der()?)
+            }
+        }
+    }
+
+    fn signature_algorithm(self) -> AlgorithmIdentifierOwned {
+        match self {
+            KeyAlgorithm::Rsa => AlgorithmIdentifierOwned {
+                oid: rfc5912::RSA_ENCRYPTION,
+                parameters: Some(Any::null()),
+            },
+            KeyAlgorithm::Ec => AlgorithmIdentifierOwned {
+                oid: rfc5912::ECDSA_WITH_SHA_256,
+                parameters: None,
+            },
+        }
+    }
+}
+
+#[derive(Sequence)]
+struct EcdsaSignature<'a> {
+    r: UintRef<'a>,
+    s: UintRef<'a>,
+}
+
+/// `ESSCertIDv2` with the default SHA-256 hash algorithm.
+#[derive(Sequence)]
+struct EssCertIdV2 {
+    cert_hash: OctetString,
+}
+
+#[derive(Sequence)]
+struct SigningCertificateV2 {
+    certs: Vec<EssCertIdV2>,
+}
+
+fn sha256_algorithm() -> AlgorithmIdentifierOwned {
+    AlgorithmIdentifierOwned {
+        oid: rfc5912::ID_SHA_256,
+        parameters: None,
+    }
+}
+
+fn attribute(oid: ObjectIdentifier, value: &impl Encode) -> Result<Attribute, Box<dyn Error>> {
+    let mut values = SetOfVec::new();
+    values.insert(Any::from_der(&value.to_der()?)?)?;
+    Ok(Attribute { oid, values })
+}
+
+/// Builds SHA-256 detached CMS `SignedData` for a token certificate.
+///
+/// Signing is split in two so the token can be driven by the caller: build
+/// the signed attributes, sign `to_be_signed(&attrs)` on the token, then
+/// wrap the result with `signed_data`.
+pub struct CmsSigner {
+    certificate: Certificate,
+    key_algorithm: KeyAlgorithm,
+}
+
+impl CmsSigner {
+    pub fn new(cert_der: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let certificate = Certificate::from_der(cert_der)?;
+        let key_algorithm = KeyAlgorithm::of_certificate(&certificate)?;
+        Ok(CmsSigner {
+            certificate,
+            key_algorithm,
+        })
+    }
+
+    pub fn key_algorithm(&self) -> KeyAlgorithm {
+        self.key_algorithm
+    }
+
+    /// Content type, message digest, signing time and signing certificate
+    /// attributes for a document whose SHA-256 digest is `content_digest`.
+    /// PAdES signatures leave the signing time out and carry it in the PDF.
+    pub fn signed_attributes(
+        &self,
+        content_digest: &[u8],
+        signing_time: Option<DateTime<Utc>>,
+    ) -> Result<SignedAttributes, Box<dyn Error>> {
+        self.attributes(Some(rfc5911::ID_DATA), content_digest, signing_time)
+    }
+
+    /// Signed attributes for a signer added next to those of an existing
+    /// `SignedData`, whose content type they must repeat.
+    pub fn parallel_attributes(
+        &self,
+        content_type: ObjectIdentifier,
+        content_digest: &[u8],
+        signing_time: Option<DateTime<Utc>>,
+    ) -> Result<SignedAttributes, Box<dyn Error>> {
+        self.attributes(Some(content_type), content_digest, signing_time)
+    }
+
+    /// Signed attributes for a countersignature over `signature_value`.
+    /// RFC 5652 section 11.4 rules out the content type attribute here.
+    pub fn countersignature_attributes(
+        &self,
+        signature_value: &[u8],
+        signing_time: Option<DateTime<Utc>>,
+    ) -> Result<SignedAttributes, Box<dyn Error>> {
+        self.attributes(None, &Sha256::digest(signature_value), signing_time)
+    }
+
+    fn attributes(
+        &self,
+        content_type: Option<ObjectIdentifier>,
+        digest: &[u8],
+        signing_time: Option<DateTime<Utc>>,
+    ) -> Result<SignedAttributes, Box<dyn Error>> {
+        let signing_certificate = SigningCertificateV2 {
+            certs: vec![EssCertIdV2 {
+                cert_hash: OctetString::new(Sha256::digest(self.certificate.to_der()?).to_vec())?,
+            }],
+        };
+
+        let mut attrs = SignedAttributes::new();
+        if let Some(content_type) = content_type {
+            attrs.insert(attribute(rfc5911::ID_CONTENT_TYPE, &content_type)?)?;
+        }
+        attrs.insert(attribute(
+            rfc5911::ID_MESSAGE_DIGEST,
+            &OctetString::new(digest)?,
+        )?)?;
+        if let Some(signing_time) = signing_time {
+            let seconds = u64::try_from(signing_time.timestamp())?;
+            let signing_time =
+                Time::UtcTime(UtcTime::from_unix_duration(Duration::from_secs(seconds))?);
+            attrs.insert(attribute(rfc5911::ID_SIGNING_TIME, &signing_time)?)?;
+        }
+        attrs.insert(attribute(
+            rfc5911::ID_AA_SIGNING_CERTIFICATE_V_2,
+            &signing_certificate,
+        )?)?;
+        Ok(attrs)
+    }
+
+    /// Wraps signed attributes and the token's raw signature over
+    /// `to_be_signed(&attrs)` into a DER `ContentInfo` without the content.
+    ///
+    /// A DER `TimeStampToken` over the encoded signature value, if given, is
+    /// added as the signature timestamp unsigned attribute (CAdES-T).
+    pub fn signed_data(
+        &self,
+        signed_attrs: SignedAttributes,
+        raw_signature: &[u8],
+        signature_timestamp: Option<&[u8]>,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let signer_info = self.signer_info(signed_attrs, raw_signature, signature_timestamp)?;
+
+        let mut digest_algorithms = SetOfVec::new();
+        digest_algorithms.insert(sha256_algorithm())?;
+        let mut certificates = SetOfVec::new();
+        certificates.insert(CertificateChoices::Certificate(self.certificate.clone()))?;
+        let mut signer_infos = SetOfVec::new();
+        signer_infos.insert(signer_info)?;
+
+        let signed_data = SignedData {
+            version: CmsVersion::V1,
+            digest_algorithms,
+            encap_content_info: EncapsulatedContentInfo {
+                econtent_type: rfc5911::ID_DATA,
+                econtent: None,
+            },
+            certificates: Some(CertificateSet(certificates)),
+            crls: None,
+            signer_infos: SignerInfos(signer_infos),
+        };
+        encode_signed_data(&signed_data)
+    }
+
+    /// The signer info `signed_data` wraps, for adding to an existing
+    /// `SignedData`.
+    pub fn signer_info(
+        &self,
+        signed_attrs: SignedAttributes,
+        raw_signature: &[u8],
+        signature_timestamp: Option<&[u8]>,
+    ) -> Result<SignerInfo, Box<dyn Error>> {
+        let tbs = &self.certificate.tbs_certificate;
+        let unsigned_attrs = match signature_timestamp {
+            Some(token) => {
+                let mut attrs = SetOfVec::new();
+                attrs.insert(attribute(
+                    ID_AA_SIGNATURE_TIME_STAMP_TOKEN,
+                    &Any::from_der(token)?,
+                )?)?;
+                Some(attrs)
+            }
+            None => None,
+        };
+        Ok(SignerInfo {
+            version: CmsVersion::V1,
+            sid: SignerIdentifier::IssuerAndSerialNumber(IssuerAndSerialNumber {
+                issuer: tbs.issuer.clone(),
+                serial_number: tbs.serial_number.clone(),
+            }),
+            digest_alg: sha256_algorithm(),
+            signed_attrs: Some(signed_attrs),
+            signature_algorithm: self.key_algorithm.signature_algorithm(),
+            signature: OctetString::new(self.key_algorithm.encode_signature(raw_signature)?)?,
+            unsigned_attrs,
+        })
+    }
+}
+
+fn encode_signed_data(signed_data: &SignedData) -> Result<Vec<u8>, Box<dyn Error>> {
+    let content_info = ContentInfo {
+        content_type: rfc5911::ID_SIGNED_DATA,
+        content: Any::encode_from(signed_data)?,
+    };
+    Ok(content_info.to_der()?)
+}
+
+/// A CMS `SignedData` made elsewhere, to which a parallel signer or a
+/// countersignature is added.
+pub struct ExistingSignedData {
+    signed_data: SignedData,
+}
+
+impl ExistingSignedData {
+    /// Reads a DER `ContentInfo`. Trailing zero bytes, such as the padding
+    /// of a PDF `/Contents`, are ignored.
+    pub fn parse(der: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = SliceReader::new(der)?;
+        let content_info = ContentInfo::decode(&mut reader)?;
+        if reader.remaining_len() > Length::ZERO
+            && reader
+                .read_slice(reader.remaining_len())?
+                .iter()
+                .any(|&byte| byte != 0)
+        {
+            return Err("Unexpected data after the CMS signature".into());
+        }
+        if content_info.content_type != rfc5911::ID_SIGNED_DATA {
+            return Err("The CMS structure is not SignedData".into());
+        }
+        Ok(ExistingSignedData {
+            signed_data: content_info.content.decode_as()?,
+        })
+    }
+
+    pub fn content_type(&self) -> ObjectIdentifier {
+        self.signed_data.encap_content_info.econtent_type
+    }
+
+    /// The encapsulated content, unless the signature is detached.
+    pub fn content(&self) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        match &self.signed_data.encap_content_info.econtent {
+            Some(content) => Ok(Some(content.decode_as::<OctetString>()?.into_bytes())),
+            None => Ok(None),
+        }
+    }
+
+    fn signer(&self, index: usize) -> Result<&SignerInfo, Box<dyn Error>> {
+        let signers = &self.signed_data.signer_infos.0;
+        signers.get(index).ok_or_else(|| {
+            format!(
+                "There is no signer {}, the signature has {}",
+                index,
+                signers.len()
+            )
+            .into()
+        })
+    }
+
+    /// The subject of each signer's certificate, in the order signers are
+    /// indexed. Signers whose certificate is not included are named by
+    /// serial number.
+    pub fn signer_names(&self) -> Vec<String> {
+        let certificates: Vec<&Certificate> = self
+            .signed_data
+            .certificates
+            .iter()
+            .flat_map(|set| set.0.iter())
+            .filter_map(|choice| match choice {
+                CertificateChoices::Certificate(cert) => Some(cert),
+                _ => None,
+            })
+            .collect();
+        self.signed_data
+            .signer_infos
+            .0
+            .iter()
+            .map(|signer| {
+                let SignerIdentifier::IssuerAndSerialNumber(sid) = &signer.sid else {
+                    return "Unknown signer".to_string();
+                };
+                certificates
+                    .iter()
+                    .find(|cert| {
+                        cert.tbs_certificate.issuer == sid.issuer
+                            && cert.tbs_certificate.serial_number == sid.serial_number
+                    })
+                    .and_then(|cert| cert.to_der().ok())
+                    .and_then(|der| crate::certificate::subject_name(&der).ok())
+                    .unwrap_or_else(|| {
+                        format!(
+                            "Serial number {}",
+                            hex::encode(sid.serial_number.as_bytes())
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    /// The signature value of signer `index`, which a countersignature
+    /// covers.
+    pub fn signature_value(&self, index: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(self.signer(index)?.signature.as_bytes().to_vec())
+    }
+
+    /// Fails unless every signer's message digest is that of `content`, so a
+    /// parallel signer signs exactly what the others did.
+    pub fn check_content(&self, content: &[u8]) -> Result<(), Box<dyn Error>> {
+        for (index, signer) in self.signed_data.signer_infos.0.iter().enumerate() {
+            let message_digest = signer
+                .signed_attrs
+                .iter()
+                .flat_map(|attrs| attrs.iter())
+                .find(|attr| attr.oid == rfc5911::ID_MESSAGE_DIGEST)
+                .and_then(|attr| attr.values.get(0))
+                .ok_or_else(|| format!("Signer {} has no message digest", index))?
+                .decode_as::<OctetString>()?;
+            let digest = crate::certificate::digest(signer.digest_alg.oid, content)?;
+            if message_digest.as_bytes() != digest.as_slice() {
+                return Err(format!("Signer {} signed different content", index).into());
+            }
+        }
+        Ok(())
+    }
+
+    fn add_certificate(&mut self, signer: &CmsSigner) -> Result<(), Box<dyn Error>> {
+        let certificate = CertificateChoices::Certificate(signer.certificate.clone());
+        let certificates = self
+            .signed_data
+            .certificates
+            .get_or_insert_with(|| CertificateSet(SetOfVec::new()));
+        if !certificates.0.iter().any(|cert| *cert == certificate) {
+            certificates.0.insert(certificate)?;
+        }
+        Ok(())
+    }
+
+    /// Adds `signer_info` as one more signer of the content and returns the
+    /// DER `ContentInfo`.
+    pub fn add_signer(
+        mut self,
+        signer: &CmsSigner,
+        signer_info: SignerInfo,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let digest_algorithms = &mut self.signed_data.digest_algorithms;
+        if !digest_algorithms
+            .iter()
+            .any(|algorithm| algorithm.oid == rfc5912::ID_SHA_256)
+        {
+            digest_algorithms.insert(sha256_algorithm())?;
+        }
+        self.add_certificate(signer)?;
+        self.signed_data.signer_infos.0.insert(signer_info)?;
+        encode_signed_data(&self.signed_data)
+    }
+
+    /// Adds `countersignature` to the unsigned attributes of signer `index`
+    /// and returns the DER `ContentInfo`.
+    pub fn add_countersignature(
+        mut self,
+        index: usize,
+        signer: &CmsSigner,
+        countersignature: SignerInfo,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.signer(index)?;
+        let value = Any::encode_from(&countersignature)?;
+        let mut signers = std::mem::take(&mut self.signed_data.signer_infos.0).into_vec();
+        let unsigned_attrs = signers[index]
+            .unsigned_attrs
+            .take()
+            .unwrap_or_default()
+            .into_vec();
+        let (mut countersignatures, mut others): (Vec<Attribute>, Vec<Attribute>) = unsigned_attrs
+            .into_iter()
+            .partition(|attr| attr.oid == rfc5911::ID_COUNTERSIGNATURE);
+        // An attribute type appears once, with every countersignature as
+        // one of its values.
+        let mut attr = countersignatures.pop().unwrap_or(Attribute {
+            oid: rfc5911::ID_COUNTERSIGNATURE,
+            values: SetOfVec::new(),
+        });
+        if attr.values.iter().any(|existing| *existing == value) {
+            return Err("The signer already has this countersignature".into());
+        }
+        attr.values.insert(value)?;
+        others.push(attr);
+        signers[index].unsigned_attrs = Some(others.try_into()?);
+        self.signed_data.signer_infos.0 = signers.try_into()?;
+        self.add_certificate(signer)?;
+        encode_signed_data(&self.signed_data)
+    }
+}
+
+/// The DER bytes the signer info's signature covers.
+pub fn to_be_signed(signed_attrs: &SignedAttributes) -> Result<Vec<u8>, Box<dyn Error>> {
+    Ok(signed_attrs.to_der()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_certs;
+    use p256::ecdsa::signature::hazmat::PrehashSigner;
+    use p256::ecdsa::signature::Verifier;
+
+    const CONTENT: &[u8] = b"contract text";
+
+    struct TestSigner {
+        key: p256::ecdsa::SigningKey,
+        cms: CmsSigner,
+    }
+
+    impl TestSigner {
+        fn new() -> Self {
+            let key = test_certs::p256_key();
+            let cms = CmsSigner::new(&test_certs::p256_certificate(&key)).unwrap();
+            TestSigner { key, cms }
+        }
+
+        /// Signs like the token does: ECDSA over the digest from
+        /// `token_input`, returning `r || s`.
+        fn sign(&self, attrs: &SignedAttributes) -> Vec<u8> {
+            let input = self
+                .cms
+                .key_algorithm()
+                .token_input(&to_be_signed(attrs).unwrap());
+            let signature: p256::ecdsa::Signature = self.key.sign_prehash(&input).unwrap();
+            signature.to_bytes().to_vec()
+        }
+
+        fn detached(&self, content: &[u8]) -> Vec<u8> {
+            let attrs = self
+                .cms
+                .signed_attributes(&Sha256::digest(content), Some(Utc::now()))
+                .unwrap();
+            let signature = self.sign(&attrs);
+            self.cms.signed_data(attrs, &signature, None).unwrap()
+        }
+
+        fn parallel(&self, existing: &ExistingSignedData, content: &[u8]) -> SignerInfo {
+            let attrs = self
+                .cms
+                .parallel_attributes(existing.content_type(), &Sha256::digest(content), None)
+                .unwrap();
+            let signature = self.sign(&attrs);
+            self.cms.signer_info(attrs, &signature, None).unwrap()
+        }
+
+        fn countersignature(&self, signature_value: &[u8]) -> SignerInfo {
+            let attrs = self
+                .cms
+                .countersignature_attributes(signature_value, None)
+                .unwrap();
+            let signature = self.sign(&attrs);
+            self.cms.signer_info(attrs, &signature, None).unwrap()
+        }
+
+        fn verifies(&self, signer_info: &SignerInfo) -> bool {
+            let tbs = to_be_signed(signer_info.signed_attrs.as_ref().unwrap()).unwrap();
+            let signature =
+                p256::ecdsa::Signature::from_der(signer_info.signature.as_bytes()).unwrap();
+            self.key.verifying_key().verify(&tbs, &signature).is_ok()
+        }
+    }
+
+    fn signed_data(der: &[u8]) -> SignedData {
+        ExistingSignedData::parse(der).unwrap().signed_data
+    }
+
+    fn message_digest(signer_info: &SignerInfo) -> Vec<u8> {
+        signer_info
+            .signed_attrs
+            .iter()
+            .flat_map(|attrs| attrs.iter())
+            .find(|attr| attr.oid == rfc5911::ID_MESSAGE_DIGEST)
+            .unwrap()
+            .values
+            .get(0)
+            .unwrap()
+            .decode_as::<OctetString>()
+            .unwrap()
+            .into_bytes()
+    }
+
+    #[test]
+    fn detached_signature_verifies() {
+        let signer = TestSigner::new();
+        let existing = ExistingSignedData::parse(&signer.detached(CONTENT)).unwrap();
+        assert_eq!(existing.content().unwrap(), None);
+        assert_eq!(existing.content_type(), rfc5911::ID_DATA);
+        existing.check_content(CONTENT).unwrap();
+        assert!(existing.check_content(b"other text").is_err());
+        assert!(signer.verifies(&existing.signed_data.signer_infos.0.as_slice()[0]));
+    }
+
+    #[test]
+    fn parallel_signer_is_added() {
+        let first = TestSigner::new();
+        let second = TestSigner::new();
+        let existing = ExistingSignedData::parse(&first.detached(CONTENT)).unwrap();
+        let signer_info = second.parallel(&existing, CONTENT);
+        let cosigned = existing.add_signer(&second.cms, signer_info).unwrap();
+
+        let existing = ExistingSignedData::parse(&cosigned).unwrap();
+        existing.check_content(CONTENT).unwrap();
+        assert_eq!(existing.signer_names().len(), 2);
+        let signed_data = &existing.signed_data;
+        assert_eq!(signed_data.digest_algorithms.len(), 1);
+        assert_eq!(signed_data.certificates.as_ref().unwrap().0.len(), 2);
+        let signers = signed_data.signer_infos.0.as_slice();
+        assert!(
+            (first.verifies(&signers[0]) && second.verifies(&signers[1]))
+                || (first.verifies(&signers[1]) && second.verifies(&signers[0]))
+        );
+    }
+
+    #[test]
+    fn signer_of_other_content_is_detected() {
+        let first = TestSigner::new();
+        let second = TestSigner::new();
+        let existing = ExistingSignedData::parse(&first.detached(CONTENT)).unwrap();
+        let signer_info = second.parallel(&existing, b"other text");
+        let cosigned = existing.add_signer(&second.cms, signer_info).unwrap();
+        let existing = ExistingSignedData::parse(&cosigned).unwrap();
+        assert!(existing.check_content(CONTENT).is_err());
+        assert!(existing.check_content(b"other text").is_err());
+    }
+
+    #[test]
+    fn countersignature_covers_the_signature_value() {
+        let signer = TestSigner::new();
+        let countersigner = TestSigner::new();
+        let existing = ExistingSignedData::parse(&signer.detached(CONTENT)).unwrap();
+        let value = existing.signature_value(0).unwrap();
+        let countersignature = countersigner.countersignature(&value);
+        assert!(countersigner.verifies(&countersignature));
+        assert_eq!(
+            message_digest(&countersignature),
+            Sha256::digest(&value).to_vec()
+        );
+        assert!(!countersignature
+            .signed_attrs
+            .iter()
+            .flat_map(|attrs| attrs.iter())
+            .any(|attr| attr.oid == rfc5911::ID_CONTENT_TYPE));
+
+        let countersigned = existing
+            .add_countersignature(0, &countersigner.cms, countersignature.clone())
+            .unwrap();
+        let signed_data = signed_data(&countersigned);
+        assert_eq!(signed_data.certificates.as_ref().unwrap().0.len(), 2);
+        let unsigned_attrs = signed_data.signer_infos.0.as_slice()[0]
+            .unsigned_attrs
+            .clone()
+            .unwrap();
+        let attr = unsigned_attrs
+            .iter()
+            .find(|attr| attr.oid == rfc5911::ID_COUNTERSIGNATURE)
+            .unwrap();
+        assert_eq!(attr.values.len(), 1);
+        let stored: SignerInfo = attr.values.get(0).unwrap().decode_as().unwrap();
+        assert!(countersigner.verifies(&stored));
+
+        let existing = ExistingSignedData::parse(&countersigned).unwrap();
+        assert!(existing
+            .add_countersignature(0, &countersigner.cms, countersignature)
+            .is_err());
+    }
+
+    #[test]
+    fn countersignatures_share_one_attribute() {
+        let signer = TestSigner::new();
+        let mut der = signer.detached(CONTENT);
+        for _ in 0..2 {
+            let existing = ExistingSignedData::parse(&der).unwrap();
+            let countersigner = TestSigner::new();
+            let countersignature =
+                countersigner.countersignature(&existing.signature_value(0).unwrap());
+            der = existing
+                .add_countersignature(0, &countersigner.cms, countersignature)
+                .unwrap();
+        }
+        let unsigned_attrs = signed_data(&der).signer_infos.0.as_slice()[0]
+            .unsigned_attrs
+            .clone()
+            .unwrap();
+        assert_eq!(unsigned_attrs.len(), 1);
+        assert_eq!(unsigned_attrs.get(0).unwrap().values.len(), 2);
+    }
+
+    #[test]
+    fn missing_signer_is_an_error() {
+        let signer = TestSigner::new();
+        let existing = ExistingSignedData::parse(&signer.detached(CONTENT)).unwrap();
+        assert!(existing.signature_value(1).is_err());
+        let countersignature = signer.countersignature(b"anything");
+        assert!(existing
+            .add_countersignature(1, &signer.cms, countersignature)
+            .is_err());
+    }
+
+    #[test]
+    fn parse_allows_only_zero_padding() {
+        let mut der = TestSigner::new().detached(CONTENT);
+        der.extend_from_slice(&[0; 64]);
+        ExistingSignedData::parse(&der).unwrap();
+        der.push(1);
+        assert!(ExistingSignedData::parse(&der).is_err());
+
+        let data = ContentInfo {
+            content_type: rfc5911::ID_DATA,
+            content: Any::encode_from(&OctetString::new(CONTENT).unwrap()).unwrap(),
+        };
+        assert!(ExistingSignedData::parse(&data.to_der().unwrap()).is_err());
+    }
+
+    #[test]
+    fn ecdsa_signature_must_have_two_halves() {
+        assert!(KeyAlgorithm::Ec.encode_signature(&[]).is_err());
+        assert!(KeyAlgorithm::Ec.encode_signature(&[1; 63]).is_err());
+        let der = KeyAlgorithm::Ec.encode_signature(&[0x80; 64]).unwrap();
+        let signature = p256::ecdsa::Signature::from_der(&der).unwrap();
+        assert_eq!(signature.to_bytes().as_slice(), &[0x80; 64]);
+    }
+}
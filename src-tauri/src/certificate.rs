@@ -0,0 +1,219 @@
+use chrono::{DateTime, Utc};
+use p256::ecdsa::signature::hazmat::PrehashVerifier;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::signature::Verifier;
+use serde::Serialize;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::error::Error;
+use x509_cert::der::asn1::{BitString, PrintableStringRef, Utf8StringRef};
+use x509_cert::der::oid::db::{rfc4519, rfc5912};
+use x509_cert::der::oid::ObjectIdentifier;
+use x509_cert::der::{Decode, Encode};
+use x509_cert::spki::SubjectPublicKeyInfoOwned;
+use x509_cert::time::Time;
+use x509_cert::Certificate;
+
+/// The parts of a signing certificate shown to the user before they enter
+/// their PIN.
+#[derive(Debug, Clone, Serialize)]
+pub struct CertificateSummary {
+    pub subject: String,
+    pub issuer: String,
+    pub serial_number: String,
+    /// RFC 3339 start of the validity period.
+    pub not_before: String,
+    /// RFC 3339 end of the validity period.
+    pub not_after: String,
+}
+
+impl CertificateSummary {
+    pub fn from_der(cert_der: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let cert = Certificate::from_der(cert_der)?;
+        let tbs = &cert.tbs_certificate;
+        Ok(CertificateSummary {
+            subject: tbs.subject.to_string(),
+            issuer: tbs.issuer.to_string(),
+            serial_number: hex::encode(tbs.serial_number.as_bytes()),
+            not_before: to_utc(tbs.validity.not_before)?.to_rfc3339(),
+            not_after: to_utc(tbs.validity.not_after)?.to_rfc3339(),
+        })
+    }
+}
+
+/// The subject's common name, or the whole subject if it has none.
+pub fn subject_name(cert_der: &[u8]) -> Result<String, Box<dyn Error>> {
+    let subject = Certificate::from_der(cert_der)?.tbs_certificate.subject;
+    let common_name = subject
+        .0
+        .iter()
+        .flat_map(|rdn| rdn.0.iter())
+        .filter(|attribute| attribute.oid == rfc4519::CN)
+        .find_map(|attribute| {
+            let value = &attribute.value;
+            value
+                .decode_as::<Utf8StringRef>()
+                .map(|name| name.to_string())
+                .or_else(|_| {
+                    value
+                        .decode_as::<PrintableStringRef>()
+                        .map(|name| name.to_string())
+                })
+                .ok()
+        });
+    Ok(common_name.unwrap_or_else(|| subject.to_string()))
+}
+
+fn to_utc(time: Time) -> Result<DateTime<Utc>, Box<dyn Error>> {
+    let seconds = time.to_unix_duration().as_secs();
+    DateTime::from_timestamp(seconds as i64, 0)
+        .ok_or_else(|| "Certificate time out of range".into())
+}
+
+/// Checks a signature `sign_hash_with_cert` made over `message` with the
+/// certificate's key: RSA PKCS#1 v1.5 with SHA-256, or ECDSA on P-256 or
+/// P-384 with `message` taken as the digest and the signature as `r || s`.
+pub fn verify_token_signature(
+    cert_der: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    let cert = Certificate::from_der(cert_der)?;
+    let spki = &cert.tbs_certificate.subject_public_key_info;
+    let spki_der = spki.to_der()?;
+
+    if spki.algorithm.oid == rfc5912::RSA_ENCRYPTION {
+        let key = rsa::RsaPublicKey::from_public_key_der(&spki_der)?;
+        let signature = rsa::pkcs1v15::Signature::try_from(signature)?;
+        rsa::pkcs1v15::VerifyingKey::<Sha256>::new(key).verify(message, &signature)?;
+        return Ok(());
+    }
+
+    if spki.algorithm.oid == rfc5912::ID_EC_PUBLIC_KEY {
+        let curve = ec_curve(spki)?;
+        if curve == rfc5912::SECP_256_R_1 {
+            let key = p256::ecdsa::VerifyingKey::from_public_key_der(&spki_der)?;
+            let signature = p256::ecdsa::Signature::from_slice(signature)?;
+            key.verify_prehash(message, &signature)?;
+            return Ok(());
+        }
+        if curve == rfc5912::SECP_384_R_1 {
+            let key = p384::ecdsa::VerifyingKey::from_public_key_der(&spki_der)?;
+            let signature = p384::ecdsa::Signature::from_slice(signature)?;
+            key.verify_prehash(message, &signature)?;
+            return Ok(());
+        }
+        return Err(format!("Unsupported elliptic curve {}", curve).into());
+    }
+
+    Err(format!(
+        "Unsupported certificate key algorithm {}",
+        spki.algorithm.oid
+    )
+    .into())
+}
+
+fn ec_curve(spki: &SubjectPublicKeyInfoOwned) -> Result<ObjectIdentifier, Box<dyn Error>> {
+    Ok(spki
+        .algorithm
+        .parameters
+        .as_ref()
+        .ok_or("Elliptic curve parameters are missing")?
+        .decode_as()?)
+}
+
+/// Digest of `data` under the SHA-2 algorithm identified by `algorithm`.
+pub fn digest(algorithm: ObjectIdentifier, data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    match algorithm {
+        rfc5912::ID_SHA_256 => Ok(Sha256::digest(data).to_vec()),
+        rfc5912::ID_SHA_384 => Ok(Sha384::digest(data).to_vec()),
+        rfc5912::ID_SHA_512 => Ok(Sha512::digest(data).to_vec()),
+        _ => Err(format!("Unsupported digest algorithm {}", algorithm).into()),
+    }
+}
+
+/// Checks a signature as it appears in a CMS signer info made by a third
+/// party such as a TSA: RSA PKCS#1 v1.5 or DER-encoded ECDSA over `message`
+/// hashed with `digest_algorithm`.
+pub fn verify_cms_signature(
+    cert: &Certificate,
+    digest_algorithm: ObjectIdentifier,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    let spki = &cert.tbs_certificate.subject_public_key_info;
+    let spki_der = spki.to_der()?;
+
+    if spki.algorithm.oid == rfc5912::RSA_ENCRYPTION {
+        let key = rsa::RsaPublicKey::from_public_key_der(&spki_der)?;
+        let signature = rsa::pkcs1v15::Signature::try_from(signature)?;
+        match digest_algorithm {
+            rfc5912::ID_SHA_256 => {
+                rsa::pkcs1v15::VerifyingKey::<Sha256>::new(key).verify(message, &signature)?
+            }
+            rfc5912::ID_SHA_384 => {
+                rsa::pkcs1v15::VerifyingKey::<Sha384>::new(key).verify(message, &signature)?
+            }
+            rfc5912::ID_SHA_512 => {
+                rsa::pkcs1v15::VerifyingKey::<Sha512>::new(key).verify(message, &signature)?
+            }
+            _ => return Err(format!("Unsupported digest algorithm {}", digest_algorithm).into()),
+        }
+        return Ok(());
+    }
+
+    if spki.algorithm.oid == rfc5912::ID_EC_PUBLIC_KEY {
+        let prehash = digest(digest_algorithm, message)?;
+        let curve = ec_curve(spki)?;
+        if curve == rfc5912::SECP_256_R_1 {
+            let key = p256::ecdsa::VerifyingKey::from_public_key_der(&spki_der)?;
+            let signature = p256::ecdsa::Signature::from_der(signature)?;
+            key.verify_prehash(&prehash, &signature)?;
+            return Ok(());
+        }
+        if curve == rfc5912::SECP_384_R_1 {
+            let key = p384::ecdsa::VerifyingKey::from_public_key_der(&spki_der)?;
+            let signature = p384::ecdsa::Signature::from_der(signature)?;
+            key.verify_prehash(&prehash, &signature)?;
+            return Ok(());
+        }
+        return Err(format!("Unsupported elliptic curve {}", curve).into());
+    }
+
+    Err(format!(
+        "Unsupported certificate key algorithm {}",
+        spki.algorithm.oid
+    )
+    .into())
+}
+
+/// The digest a certificate signature algorithm hashes with.
+fn signature_digest(algorithm: ObjectIdentifier) -> Option<ObjectIdentifier> {
+    match algorithm {
+        rfc5912::SHA_256_WITH_RSA_ENCRYPTION | rfc5912::ECDSA_WITH_SHA_256 => {
+            Some(rfc5912::ID_SHA_256)
+        }
+        rfc5912::SHA_384_WITH_RSA_ENCRYPTION | rfc5912::ECDSA_WITH_SHA_384 => {
+            Some(rfc5912::ID_SHA_384)
+        }
+        rfc5912::SHA_512_WITH_RSA_ENCRYPTION | rfc5912::ECDSA_WITH_SHA_512 => {
+            Some(rfc5912::ID_SHA_512)
+        }
+        _ => None,
+    }
+}
+
+/// Checks a signature in an X.509 structure (certificate, CRL or OCSP
+/// response) over `tbs` with `signer`'s key.
+pub fn verify_signed_by(
+    signer: &Certificate,
+    algorithm: ObjectIdentifier,
+    tbs: &[u8],
+    signature: &BitString,
+) -> Result<(), Box<dyn Error>> {
+    let digest = signature_digest(algorithm)
+        .ok_or_else(|| format!("Unsupported signature algorithm {}", algorithm))?;
+    let signature = signature
+        .as_bytes()
+        .ok_or("Signature is not a whole number of bytes")?;
+    verify_cms_signature(signer, digest, tbs, signature)
+}
@@ -0,0 +1,263 @@
+use crate::certificate::{self, CertificateSummary};
+use crate::revocation::RevocationStatus;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::path::Path;
+use x509_cert::der::{Decode, Encode};
+use x509_cert::ext::pkix::{
+    AuthorityKeyIdentifier, BasicConstraints, KeyUsage, SubjectKeyIdentifier,
+};
+use x509_cert::Certificate;
+
+/// Longest chain, leaf and root included, the agent will build.
+const MAX_CHAIN_LENGTH: usize = 8;
+
+/// Certificates a chain may be built from: trusted roots, and intermediates
+/// that only count if they lead to one.
+#[derive(Clone, Default)]
+pub struct CertificatePool {
+    anchors: Vec<Certificate>,
+    intermediates: Vec<Certificate>,
+}
+
+impl CertificatePool {
+    pub fn add_anchor(&mut self, cert: Certificate) {
+        if !self.anchors.contains(&cert) {
+            self.anchors.push(cert);
+        }
+    }
+
+    pub fn add_intermediate(&mut self, cert: Certificate) {
+        if !self.intermediates.contains(&cert) {
+            self.intermediates.push(cert);
+        }
+    }
+
+    /// Adds every DER or PEM certificate found in `dir`, skipping files that
+    /// do not parse. A missing directory adds nothing.
+    pub fn load_dir(&mut self, dir: &Path, as_anchors: bool) -> Result<(), Box<dyn Error>> {
+        if !dir.is_dir() {
+            return Ok(());
+        }
+        for entry in std::fs::read_dir(dir)? {
+            let contents = std::fs::read(entry?.path())?;
+            let certs = match Certificate::from_der(&contents) {
+                Ok(cert) => vec![cert],
+                Err(_) => Certificate::load_pem_chain(&contents).unwrap_or_default(),
+            };
+            for cert in certs {
+                if as_anchors {
+                    self.add_anchor(cert);
+                } else {
+                    self.add_intermediate(cert);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Saves the intermediates of a validated chain to `dir`, so later
+/// validations find them without the token that supplied them.
+pub fn cache_intermediates(dir: &Path, chain: &[Certificate]) -> Result<(), Box<dyn Error>> {
+    if chain.len() < 3 {
+        return Ok(());
+    }
+    std::fs::create_dir_all(dir)?;
+    for cert in &chain[1..chain.len() - 1] {
+        let der = cert.to_der()?;
+        let path = dir.join(format!("{}.der", hex::encode(Sha256::digest(&der))));
+        if !path.exists() {
+            std::fs::write(path, der)?;
+        }
+    }
+    Ok(())
+}
+
+/// The chain built for a signing certificate, leaf first, and everything
+/// wrong with it.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChainValidation {
+    pub chain: Vec<CertificateSummary>,
+    /// Whether the chain ends at a certificate from the trust store.
+    pub trusted: bool,
+    pub errors: Vec<String>,
+    /// Revocation status of the signing certificate, once checked.
+    pub revocation: Option<RevocationStatus>,
+    #[serde(skip)]
+    pub certificates: Vec<Certificate>,
+}
+
+impl ChainValidation {
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+fn subject_of(cert: &Certificate) -> String {
+    cert.tbs_certificate.subject.to_string()
+}
+
+/// Checks that `issuer`'s key made the signature on `cert`.
+fn verify_issued_by(cert: &Certificate, issuer: &Certificate) -> Result<(), Box<dyn Error>> {
+    certificate::verify_signed_by(
+        issuer,
+        cert.signature_algorithm.oid,
+        &cert.tbs_certificate.to_der()?,
+        &cert.signature,
+    )
+}
+
+/// Whether `candidate` may be the issuer of `cert` by name and, when both
+/// carry them, by key identifier.
+fn names_issuer(cert: &Certificate, candidate: &Certificate) -> bool {
+    if cert.tbs_certificate.issuer != candidate.tbs_certificate.subject {
+        return false;
+    }
+    let authority_key = cert
+        .tbs_certificate
+        .get::<AuthorityKeyIdentifier>()
+        .ok()
+        .flatten()
+        .and_then(|(_, aki)| aki.key_identifier);
+    let subject_key = candidate
+        .tbs_certificate
+        .get::<SubjectKeyIdentifier>()
+        .ok()
+        .flatten()
+        .map(|(_, ski)| ski.0);
+    match (authority_key, subject_key) {
+        (Some(authority_key), Some(subject_key)) => authority_key == subject_key,
+        _ => true,
+    }
+}
+
+fn is_self_issued(cert: &Certificate) -> bool {
+    cert.tbs_certificate.issuer == cert.tbs_certificate.subject
+}
+
+/// Builds the chain from `leaf` to a trust anchor out of `pool` and checks
+/// signatures, validity periods, basic constraints and key usage along it.
+/// With no anchors in the pool trust is not enforced: a chain that stops
+/// short of a root is reported as untrusted rather than as an error.
+pub fn validate(
+    leaf_der: &[u8],
+    pool: &CertificatePool,
+    now: DateTime<Utc>,
+) -> Result<ChainValidation, Box<dyn Error>> {
+    let leaf = Certificate::from_der(leaf_der)?;
+    let mut errors = Vec::new();
+    let mut chain = vec![leaf];
+    let mut trusted = false;
+    let enforce_trust = !pool.anchors.is_empty();
+
+    loop {
+        let current = &chain[chain.len() - 1];
+        if pool.anchors.contains(current) {
+            trusted = true;
+            break;
+        }
+        if chain.len() == MAX_CHAIN_LENGTH {
+            errors.push(format!(
+                "The chain is longer than {} certificates",
+                MAX_CHAIN_LENGTH
+            ));
+            break;
+        }
+
+        let candidates = pool
+            .anchors
+            .iter()
+            .chain(pool.intermediates.iter())
+            .filter(|candidate| !chain.contains(candidate) && names_issuer(current, candidate));
+        let mut issuer = None;
+        let mut bad_signature = false;
+        for candidate in candidates {
+            if verify_issued_by(current, candidate).is_ok() {
+                issuer = Some(candidate.clone());
+                break;
+            }
+            bad_signature = true;
+        }
+
+        match issuer {
+            Some(issuer) => chain.push(issuer),
+            None => {
+                if bad_signature {
+                    errors.push(format!(
+                        "The signature on {} does not verify with its issuer's key",
+                        subject_of(current)
+                    ));
+                } else if enforce_trust {
+                    if is_self_issued(current) {
+                        errors.push(format!(
+                            "The root {} is not in the trust store",
+                            subject_of(current)
+                        ));
+                    } else {
+                        errors.push(format!(
+                            "The issuer {} of {} was not found",
+                            current.tbs_certificate.issuer,
+                            subject_of(current)
+                        ));
+                    }
+                }
+                break;
+            }
+        }
+    }
+
+    let now = now.timestamp();
+    for (position, cert) in chain.iter().enumerate() {
+        let tbs = &cert.tbs_certificate;
+        let subject = subject_of(cert);
+        if (tbs.validity.not_before.to_unix_duration().as_secs() as i64) > now {
+            errors.push(format!("{} is not valid yet", subject));
+        }
+        if (tbs.validity.not_after.to_unix_duration().as_secs() as i64) < now {
+            errors.push(format!("{} has expired", subject));
+        }
+
+        let key_usage = tbs.get::<KeyUsage>()?.map(|(_, usage)| usage);
+        if position == 0 {
+            if let Some(usage) = key_usage {
+                if !usage.digital_signature() && !usage.non_repudiation() {
+                    errors.push(format!("{} is not allowed to sign documents", subject));
+                }
+            }
+            continue;
+        }
+
+        match tbs.get::<BasicConstraints>()? {
+            Some((_, constraints)) if constraints.ca => {
+                // Intermediate CAs between this one and the leaf.
+                let below = position - 1;
+                if let Some(limit) = constraints.path_len_constraint {
+                    if below > usize::from(limit) {
+                        errors.push(format!("{} allows at most {} CAs below it", subject, limit));
+                    }
+                }
+            }
+            _ => errors.push(format!("{} is not a certificate authority", subject)),
+        }
+        if let Some(usage) = key_usage {
+            if !usage.key_cert_sign() {
+                errors.push(format!("{} is not allowed to sign certificates", subject));
+            }
+        }
+    }
+
+    let summaries = chain
+        .iter()
+        .map(|cert| CertificateSummary::from_der(&cert.to_der()?))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(ChainValidation {
+        chain: summaries,
+        trusted,
+        errors,
+        revocation: None,
+        certificates: chain,
+    })
+}
@@ -0,0 +1,367 @@
+use base64::prelude::*;
+use coset::cbor::value::Value;
+use coset::iana::{self, EnumI64};
+use coset::{
+    CborSerializable, CoseSign1, CoseSign1Builder, HeaderBuilder, Label,
+    RegisteredLabelWithPrivate, TaggedCborSerializable,
+};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::signature::Verifier;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha384};
+use std::error::Error;
+use x509_cert::der::oid::db::rfc5912;
+use x509_cert::der::{Decode, Encode};
+use x509_cert::Certificate;
+
+/// How a COSE_Sign1 identifies its signing certificate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyReference {
+    /// The certificate chain in the `x5chain` header (RFC 9360).
+    #[default]
+    X5chain,
+    /// Only a `kid`, for verifiers that already hold the certificate. Keeps
+    /// the structure small enough for a QR code.
+    Kid,
+}
+
+/// The `kid` for a certificate: the first eight bytes of its SHA-256
+/// digest, as in the EU Digital COVID Certificate.
+pub fn key_id(cert_der: &[u8]) -> Vec<u8> {
+    Sha256::digest(cert_der)[..8].to_vec()
+}
+
+/// ES256 for P-256 keys and ES384 for P-384 keys.
+fn algorithm_for_certificate(cert_der: &[u8]) -> Result<iana::Algorithm, Box<dyn Error>> {
+    let cert = Certificate::from_der(cert_der)?;
+    let spki = &cert.tbs_certificate.subject_public_key_info;
+    if spki.algorithm.oid != rfc5912::ID_EC_PUBLIC_KEY {
+        return Err("COSE signatures need an EC certificate".into());
+    }
+    let curve = spki
+        .algorithm
+        .parameters
+        .as_ref()
+        .ok_or("Elliptic curve parameters are missing")?
+        .decode_as()?;
+    match curve {
+        rfc5912::SECP_256_R_1 => Ok(iana::Algorithm::ES256),
+        rfc5912::SECP_384_R_1 => Ok(iana::Algorithm::ES384),
+        _ => Err(format!("Unsupported elliptic curve {}", curve).into()),
+    }
+}
+
+fn algorithm_name(algorithm: iana::Algorithm) -> &'static str {
+    match algorithm {
+        iana::Algorithm::ES256 => "ES256",
+        iana::Algorithm::ES384 => "ES384",
+        _ => "unsupported",
+    }
+}
+
+/// A COSE_Sign1 waiting for the token's signature.
+pub struct Sign1 {
+    message: CoseSign1,
+    algorithm: iana::Algorithm,
+}
+
+impl Sign1 {
+    /// Puts the algorithm and the certificate reference in the protected
+    /// header. `chain` starts with the signing certificate.
+    pub fn new(
+        payload: Vec<u8>,
+        chain: &[Vec<u8>],
+        reference: KeyReference,
+    ) -> Result<Self, Box<dyn Error>> {
+        let leaf = chain.first().ok_or("The certificate chain is empty")?;
+        let algorithm = algorithm_for_certificate(leaf)?;
+        let header = HeaderBuilder::new().algorithm(algorithm);
+        let header = match reference {
+            KeyReference::Kid => header.key_id(key_id(leaf)),
+            KeyReference::X5chain => {
+                // A single certificate is a bare bstr, a chain an array.
+                let x5chain = match chain {
+                    [leaf] => Value::Bytes(leaf.clone()),
+                    _ => Value::Array(chain.iter().cloned().map(Value::Bytes).collect()),
+                };
+                header.value(iana::HeaderParameter::X5Chain.to_i64(), x5chain)
+            }
+        };
+        let message = CoseSign1Builder::new()
+            .protected(header.build())
+            .payload(payload)
+            .build();
+        Ok(Sign1 { message, algorithm })
+    }
+
+    /// The digest of the `Sig_structure` for `CKM_ECDSA`, which does not
+    /// hash its input.
+    pub fn token_input(&self) -> Vec<u8> {
+        let tbs = self.message.tbs_data(&[]);
+        match self.algorithm {
+            iana::Algorithm::ES384 => Sha384::digest(&tbs).to_vec(),
+            _ => Sha256::digest(&tbs).to_vec(),
+        }
+    }
+
+    /// The tagged COSE_Sign1 with the token's `r || s` signature, which is
+    /// already the form COSE uses.
+    pub fn finish(mut self, signature: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.message.signature = signature.to_vec();
+        self.message
+            .to_tagged_vec()
+            .map_err(|e| format!("Failed to encode COSE_Sign1: {}", e).into())
+    }
+}
+
+/// What a COSE_Sign1 claims about itself once its signature has been
+/// checked.
+#[derive(Debug, Serialize)]
+pub struct Sign1Verification {
+    pub algorithm: &'static str,
+    pub signature_valid: bool,
+    /// The `kid` in hex, if the header has one.
+    pub key_id: Option<String>,
+    /// Whether `kid` is the one `key_id` derives from the signing
+    /// certificate. Other issuers may use their own scheme.
+    pub key_id_matches: Option<bool>,
+    /// The base64-encoded payload.
+    pub payload: String,
+    /// The signing certificate followed by any other `x5chain` certificates.
+    #[serde(skip)]
+    pub certificates: Vec<Vec<u8>>,
+}
+
+/// Reads a header parameter from the protected header, then the
+/// unprotected one.
+fn header_value(message: &CoseSign1, label: i64) -> Option<&Value> {
+    [&message.protected.header, &message.unprotected]
+        .into_iter()
+        .flat_map(|header| header.rest.iter())
+        .find(|(name, _)| *name == Label::Int(label))
+        .map(|(_, value)| value)
+}
+
+fn x5chain(message: &CoseSign1) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+    match header_value(message, iana::HeaderParameter::X5Chain.to_i64()) {
+        None => Ok(Vec::new()),
+        Some(Value::Bytes(cert)) => Ok(vec![cert.clone()]),
+        Some(Value::Array(certs)) => certs
+            .iter()
+            .map(|cert| match cert {
+                Value::Bytes(cert) => Ok(cert.clone()),
+                _ => Err("x5chain must hold byte strings".into()),
+            })
+            .collect(),
+        Some(_) => Err("Malformed x5chain".into()),
+    }
+}
+
+/// Checks a COSE_Sign1, tagged or not, against `certificate` if given or
+/// else the first `x5chain` certificate. Malformed input is an error; a
+/// signature that does not verify is reported in the result.
+pub fn verify(
+    cose: &[u8],
+    certificate: Option<&[u8]>,
+) -> Result<Sign1Verification, Box<dyn Error>> {
+    let message = CoseSign1::from_tagged_slice(cose)
+        .or_else(|_| CoseSign1::from_slice(cose))
+        .map_err(|e| format!("Not a COSE_Sign1: {}", e))?;
+    if !message.protected.header.crit.is_empty() {
+        return Err("Critical header parameters are not supported".into());
+    }
+    let algorithm = match &message.protected.header.alg {
+        Some(RegisteredLabelWithPrivate::Assigned(
+            algorithm @ (iana::Algorithm::ES256 | iana::Algorithm::ES384),
+        )) => *algorithm,
+        Some(_) => return Err("Unsupported COSE algorithm".into()),
+        None => return Err("The protected header has no algorithm".into()),
+    };
+    let payload = message
+        .payload
+        .as_ref()
+        .ok_or("Detached payloads are not supported")?;
+
+    let mut certificates = x5chain(&message)?;
+    if let Some(certificate) = certificate {
+        if certificates.first().map(Vec::as_slice) != Some(certificate) {
+            certificates.insert(0, certificate.to_vec());
+        }
+    }
+    let leaf = certificates
+        .first()
+        .ok_or("The signature has no x5chain, so the signer's certificate is needed")?;
+
+    let kid = [&message.protected.header, &message.unprotected]
+        .into_iter()
+        .map(|header| &header.key_id)
+        .find(|kid| !kid.is_empty());
+    let tbs = message.tbs_data(&[]);
+    let signature_valid = algorithm_for_certificate(leaf).ok() == Some(algorithm)
+        && verify_signature(algorithm, leaf, &tbs, &message.signature).is_ok();
+    Ok(Sign1Verification {
+        algorithm: algorithm_name(algorithm),
+        signature_valid,
+        key_id: kid.map(hex::encode),
+        key_id_matches: kid.map(|kid| *kid == key_id(leaf)),
+        payload: BASE64_STANDARD.encode(payload),
+        certificates,
+    })
+}
+
+fn verify_signature(
+    algorithm: iana::Algorithm,
+    cert_der: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    let cert = Certificate::from_der(cert_der)?;
+    let spki_der = cert.tbs_certificate.subject_public_key_info.to_der()?;
+    if algorithm == iana::Algorithm::ES384 {
+        let key = p384::ecdsa::VerifyingKey::from_public_key_der(&spki_der)?;
+        let signature = p384::ecdsa::Signature::from_slice(signature)?;
+        key.verify(message, &signature)?;
+    } else {
+        let key = p256::ecdsa::VerifyingKey::from_public_key_der(&spki_der)?;
+        let signature = p256::ecdsa::Signature::from_slice(signature)?;
+        key.verify(message, &signature)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_certs;
+    use p256::ecdsa::signature::hazmat::PrehashSigner;
+
+    const PAYLOAD: &[u8] = b"certificate of residence 2024-0042";
+
+    /// Signs like the token does: ECDSA over the digest from
+    /// `token_input`, returning `r || s`.
+    fn sign(key: &p256::ecdsa::SigningKey, sign1: Sign1) -> Vec<u8> {
+        let signature: p256::ecdsa::Signature = key.sign_prehash(&sign1.token_input()).unwrap();
+        sign1.finish(&signature.to_bytes()).unwrap()
+    }
+
+    fn signed(chain: &[Vec<u8>], reference: KeyReference) -> (p256::ecdsa::SigningKey, Vec<u8>) {
+        let key = test_certs::p256_key();
+        let mut chain = chain.to_vec();
+        chain.insert(0, test_certs::p256_certificate(&key));
+        let cose = sign(
+            &key,
+            Sign1::new(PAYLOAD.to_vec(), &chain, reference).unwrap(),
+        );
+        (key, cose)
+    }
+
+    /// Re-encodes a signed message after `edit`, keeping its signature.
+    fn edited(cose: &[u8], edit: impl FnOnce(&mut CoseSign1)) -> Vec<u8> {
+        let mut message = CoseSign1::from_tagged_slice(cose).unwrap();
+        edit(&mut message);
+        message.to_tagged_vec().unwrap()
+    }
+
+    #[test]
+    fn x5chain_round_trip() {
+        let (_, cose) = signed(&[], KeyReference::X5chain);
+        let verification = verify(&cose, None).unwrap();
+        assert!(verification.signature_valid);
+        assert_eq!(verification.algorithm, "ES256");
+        assert_eq!(verification.payload, BASE64_STANDARD.encode(PAYLOAD));
+        assert_eq!(verification.certificates.len(), 1);
+        assert_eq!(verification.key_id, None);
+
+        let message = CoseSign1::from_tagged_slice(&cose).unwrap();
+        assert!(matches!(
+            header_value(&message, iana::HeaderParameter::X5Chain.to_i64()),
+            Some(Value::Bytes(_))
+        ));
+        assert!(
+            verify(&message.to_vec().unwrap(), None)
+                .unwrap()
+                .signature_valid
+        );
+    }
+
+    #[test]
+    fn longer_chain_is_an_array() {
+        let intermediate = test_certs::p256_certificate(&test_certs::p256_key());
+        let (_, cose) = signed(std::slice::from_ref(&intermediate), KeyReference::X5chain);
+        let message = CoseSign1::from_tagged_slice(&cose).unwrap();
+        assert!(matches!(
+            header_value(&message, iana::HeaderParameter::X5Chain.to_i64()),
+            Some(Value::Array(_))
+        ));
+        let verification = verify(&cose, None).unwrap();
+        assert!(verification.signature_valid);
+        assert_eq!(verification.certificates[1], intermediate);
+    }
+
+    #[test]
+    fn kid_needs_the_certificate() {
+        let key = test_certs::p256_key();
+        let cert = test_certs::p256_certificate(&key);
+        let sign1 = Sign1::new(
+            PAYLOAD.to_vec(),
+            std::slice::from_ref(&cert),
+            KeyReference::Kid,
+        )
+        .unwrap();
+        let cose = sign(&key, sign1);
+        assert!(verify(&cose, None).is_err());
+
+        let verification = verify(&cose, Some(&cert)).unwrap();
+        assert!(verification.signature_valid);
+        assert_eq!(verification.key_id, Some(hex::encode(key_id(&cert))));
+        assert_eq!(verification.key_id_matches, Some(true));
+
+        let other = test_certs::p256_certificate(&test_certs::p256_key());
+        let verification = verify(&cose, Some(&other)).unwrap();
+        assert!(!verification.signature_valid);
+        assert_eq!(verification.key_id_matches, Some(false));
+    }
+
+    #[test]
+    fn tampered_payload_does_not_verify() {
+        let (_, cose) = signed(&[], KeyReference::X5chain);
+        let tampered = edited(&cose, |message| {
+            message.payload = Some(b"certificate of residence 2024-0043".to_vec())
+        });
+        assert!(!verify(&tampered, None).unwrap().signature_valid);
+    }
+
+    #[test]
+    fn signature_must_be_r_and_s() {
+        let (_, cose) = signed(&[], KeyReference::X5chain);
+        let message = CoseSign1::from_tagged_slice(&cose).unwrap();
+        assert_eq!(message.signature.len(), 64);
+        let der = p256::ecdsa::Signature::from_slice(&message.signature)
+            .unwrap()
+            .to_der();
+        let tampered = edited(&cose, |message| message.signature = der.as_bytes().to_vec());
+        assert!(!verify(&tampered, None).unwrap().signature_valid);
+    }
+
+    #[test]
+    fn critical_parameters_are_rejected() {
+        let key = test_certs::p256_key();
+        let cert = test_certs::p256_certificate(&key);
+        let mut sign1 = Sign1::new(PAYLOAD.to_vec(), &[cert], KeyReference::X5chain).unwrap();
+        sign1
+            .message
+            .protected
+            .header
+            .crit
+            .push(coset::RegisteredLabel::Text("urn:example:ext".into()));
+        assert!(verify(&sign(&key, sign1), None).is_err());
+    }
+
+    #[test]
+    fn rsa_certificates_cannot_sign() {
+        let cert = test_certs::rsa_certificate(&test_certs::rsa_key());
+        assert!(Sign1::new(PAYLOAD.to_vec(), &[cert], KeyReference::X5chain).is_err());
+        assert!(Sign1::new(PAYLOAD.to_vec(), &[], KeyReference::X5chain).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use base64::prelude::*;
+use serde::Serialize;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Largest document the agent will accept for hashing.
+pub const MAX_DOCUMENT_SIZE: usize = 100 * 1024 * 1024;
+
+/// Largest document the popup is given a preview of.
+const MAX_PREVIEW_SIZE: usize = 20 * 1024 * 1024;
+
+/// Hash algorithm used for submitted documents when the request does not
+/// name one.
+pub const DEFAULT_HASH_ALGORITHM: &str = "SHA-256";
+
+/// Hex-encoded digest of `content` under one of the envelope hash algorithms.
+pub fn digest_hex(algorithm: &str, content: &[u8]) -> Result<String, Box<dyn Error>> {
+    let digest = match algorithm {
+        "SHA-256" => Sha256::digest(content).to_vec(),
+        "SHA-384" => Sha384::digest(content).to_vec(),
+        "SHA-512" => Sha512::digest(content).to_vec(),
+        _ => return Err(format!("Unsupported hash algorithm {}", algorithm).into()),
+    };
+    Ok(hex::encode(digest))
+}
+
+/// Recognizes the document formats the popup can render.
+fn sniff_media_type(content: &[u8]) -> Option<&'static str> {
+    if content.starts_with(b"%PDF-") {
+        Some("application/pdf")
+    } else if content.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if content.starts_with(b"\xff\xd8\xff") {
+        Some("image/jpeg")
+    } else if content.starts_with(b"GIF87a") || content.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if content.len() >= 12 && &content[..4] == b"RIFF" && &content[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+/// A document the agent hashed itself, shown in the popup before signing.
+#[derive(Debug, Clone, Serialize)]
+pub struct DocumentPreview {
+    pub media_type: &'static str,
+    /// Base64-encoded document content.
+    pub data: String,
+}
+
+impl DocumentPreview {
+    /// Builds a preview for PDFs and images that are small enough to render.
+    pub fn for_content(content: &[u8]) -> Option<Self> {
+        if content.len() > MAX_PREVIEW_SIZE {
+            return None;
+        }
+        let media_type = sniff_media_type(content)?;
+        Some(DocumentPreview {
+            media_type,
+            data: BASE64_STANDARD.encode(content),
+        })
+    }
+}
+
+/// SHA-256 digest of a file, read in chunks so large files are not loaded
+/// into memory.
+pub fn sha256_file(path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1024 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize().to_vec())
+}
@@ -0,0 +1,287 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Mutex;
+
+/// Envelope versions this agent knows how to canonicalize and check.
+pub const ENVELOPE_VERSION: u32 = 1;
+
+/// How far an envelope timestamp may drift from the local clock, in either
+/// direction, before the request is rejected as stale.
+const MAX_CLOCK_SKEW_SECONDS: i64 = 300;
+
+/// Signing authorization issued by the Gov-Smart platform.
+///
+/// The platform signs `canonical_bytes()` with the company key, so the
+/// authorization covers the document digest and its context rather than
+/// just the certificate and timestamp.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigningEnvelope {
+    pub version: u32,
+    /// Hex-encoded digest of the document to be signed.
+    pub doc_hash: String,
+    /// One of `SHA-256`, `SHA-384` or `SHA-512`.
+    pub hash_algorithm: String,
+    /// Hex-encoded SHA-256 fingerprint of the DER signing certificate.
+    pub cert_fingerprint: String,
+    /// Web origin the platform expects the request to come from.
+    pub origin: String,
+    /// RFC 3339 issue time.
+    pub timestamp: String,
+    /// Single-use random value, rejected if seen again.
+    pub nonce: String,
+    /// Human readable document name, shown to the user before signing.
+    pub document_name: String,
+}
+
+impl SigningEnvelope {
+    /// Serializes the envelope as compact JSON with lexicographically sorted
+    /// keys. This is the exact byte string covered by the company signature.
+    pub fn canonical_bytes(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        // `serde_json::Value` keeps object keys in a BTreeMap, so going
+        // through it yields sorted keys regardless of field order above.
+        let value = serde_json::to_value(self)?;
+        Ok(serde_json::to_vec(&value)?)
+    }
+
+    /// Checks that the envelope authorizes exactly the request being made,
+    /// including the `Origin` it was sent from.
+    pub fn check_request(
+        &self,
+        cert_der: &[u8],
+        doc_hash: &str,
+        origin: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.check_context(cert_der, origin, now)?;
+
+        let digest_len = hash_algorithm_len(&self.hash_algorithm)
+            .ok_or_else(|| format!("Unsupported hash algorithm {}", self.hash_algorithm))?;
+        let envelope_hash = hex::decode(&self.doc_hash)?;
+        if envelope_hash.len() != digest_len {
+            return Err("Envelope document hash does not match its hash algorithm".into());
+        }
+        if !self.doc_hash.eq_ignore_ascii_case(doc_hash) {
+            return Err("Envelope does not cover the requested document hash".into());
+        }
+        Ok(())
+    }
+
+    /// Checks everything `check_request` does except the document hash,
+    /// for requests whose document is only known after the user picks it.
+    pub fn check_context(
+        &self,
+        cert_der: &[u8],
+        origin: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.version != ENVELOPE_VERSION {
+            return Err(format!("Unsupported envelope version {}", self.version).into());
+        }
+
+        let fingerprint = hex::encode(Sha256::digest(cert_der));
+        if !self.cert_fingerprint.eq_ignore_ascii_case(&fingerprint) {
+            return Err("Envelope does not cover the requested certificate".into());
+        }
+
+        // Browsers always send Origin on cross-origin requests and page
+        // scripts cannot forge it, so this binds browser requests to the
+        // issuing site. Other clients can set any Origin they like; replay
+        // from them is stopped by the nonce and timestamp checks instead.
+        let origin = origin.ok_or("Envelope requests must carry an Origin header")?;
+        if self.origin != origin {
+            return Err(format!("Envelope was issued for origin {}", self.origin).into());
+        }
+
+        let issued_at = DateTime::parse_from_rfc3339(&self.timestamp)?.with_timezone(&Utc);
+        if (now - issued_at).num_seconds().abs() > MAX_CLOCK_SKEW_SECONDS {
+            return Err("Envelope timestamp is outside the accepted window".into());
+        }
+
+        if self.nonce.is_empty() {
+            return Err("Envelope nonce is missing".into());
+        }
+
+        Ok(())
+    }
+}
+
+fn hash_algorithm_len(algorithm: &str) -> Option<usize> {
+    match algorithm {
+        "SHA-256" => Some(32),
+        "SHA-384" => Some(48),
+        "SHA-512" => Some(64),
+        _ => None,
+    }
+}
+
+/// Remembers envelope nonces for as long as their envelopes could still pass
+/// the timestamp check, so a captured request cannot be replayed.
+#[derive(Debug, Default)]
+pub struct NonceCache {
+    seen: Mutex<HashMap<String, DateTime<Utc>>>,
+}
+
+impl NonceCache {
+    /// Records `nonce`, failing if it was already used within the window.
+    pub fn claim(&self, nonce: &str, now: DateTime<Utc>) -> Result<(), Box<dyn Error>> {
+        let mut seen = self.seen.lock().map_err(|_| "Nonce cache lock poisoned")?;
+        let horizon = now - Duration::seconds(MAX_CLOCK_SKEW_SECONDS * 2);
+        seen.retain(|_, claimed_at| *claimed_at > horizon);
+
+        if seen.contains_key(nonce) {
+            return Err("Envelope nonce has already been used".into());
+        }
+        seen.insert(nonce.to_string(), now);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CERT: &[u8] = b"test certificate";
+    const DOC_HASH: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+    const ORIGIN: &str = "https://app.gov-smart.example";
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn envelope() -> SigningEnvelope {
+        SigningEnvelope {
+            version: ENVELOPE_VERSION,
+            doc_hash: DOC_HASH.into(),
+            hash_algorithm: "SHA-256".into(),
+            cert_fingerprint: hex::encode(Sha256::digest(CERT)),
+            origin: ORIGIN.into(),
+            timestamp: now().to_rfc3339(),
+            nonce: "n-1".into(),
+            document_name: "contract.pdf".into(),
+        }
+    }
+
+    fn check(envelope: &SigningEnvelope, origin: Option<&str>) -> Result<(), String> {
+        envelope
+            .check_request(CERT, DOC_HASH, origin, now())
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn canonical_bytes_sort_keys() {
+        let bytes = envelope().canonical_bytes().unwrap();
+        let text = String::from_utf8(bytes).unwrap();
+        let keys = [
+            "cert_fingerprint",
+            "doc_hash",
+            "document_name",
+            "hash_algorithm",
+            "nonce",
+            "origin",
+            "timestamp",
+            "version",
+        ];
+        let positions: Vec<usize> = keys
+            .iter()
+            .map(|key| text.find(&format!("\"{}\":", key)).unwrap())
+            .collect();
+        assert!(
+            positions.windows(2).all(|pair| pair[0] < pair[1]),
+            "{}",
+            text
+        );
+        assert!(!text.contains(' '), "{}", text);
+    }
+
+    #[test]
+    fn canonical_bytes_are_stable() {
+        let first = envelope().canonical_bytes().unwrap();
+        let reparsed: SigningEnvelope = serde_json::from_slice(&first).unwrap();
+        assert_eq!(reparsed.canonical_bytes().unwrap(), first);
+    }
+
+    #[test]
+    fn matching_request_is_accepted() {
+        assert_eq!(check(&envelope(), Some(ORIGIN)), Ok(()));
+    }
+
+    #[test]
+    fn hash_mismatch_is_rejected() {
+        let mut other = envelope();
+        other.doc_hash = hex::encode(Sha256::digest(b"other document"));
+        assert!(check(&other, Some(ORIGIN)).is_err());
+
+        let mut truncated = envelope();
+        truncated.doc_hash = DOC_HASH[..32].into();
+        assert!(check(&truncated, Some(ORIGIN)).is_err());
+    }
+
+    #[test]
+    fn certificate_mismatch_is_rejected() {
+        let mut other = envelope();
+        other.cert_fingerprint = hex::encode(Sha256::digest(b"other certificate"));
+        assert!(check(&other, Some(ORIGIN)).is_err());
+    }
+
+    #[test]
+    fn origin_mismatch_is_rejected() {
+        assert!(check(&envelope(), Some("https://evil.example")).is_err());
+    }
+
+    #[test]
+    fn missing_origin_is_rejected() {
+        assert!(check(&envelope(), None).is_err());
+    }
+
+    #[test]
+    fn expired_timestamp_is_rejected() {
+        let mut stale = envelope();
+        stale.timestamp = (now() - Duration::seconds(MAX_CLOCK_SKEW_SECONDS + 1)).to_rfc3339();
+        assert!(check(&stale, Some(ORIGIN)).is_err());
+    }
+
+    #[test]
+    fn future_timestamp_is_rejected() {
+        let mut early = envelope();
+        early.timestamp = (now() + Duration::seconds(MAX_CLOCK_SKEW_SECONDS + 1)).to_rfc3339();
+        assert!(check(&early, Some(ORIGIN)).is_err());
+
+        early.timestamp = (now() + Duration::seconds(MAX_CLOCK_SKEW_SECONDS - 1)).to_rfc3339();
+        assert_eq!(check(&early, Some(ORIGIN)), Ok(()));
+    }
+
+    #[test]
+    fn context_check_ignores_the_document() {
+        let mut other = envelope();
+        other.doc_hash = hex::encode(Sha256::digest(b"other document"));
+        assert!(other.check_context(CERT, Some(ORIGIN), now()).is_ok());
+        assert!(other
+            .check_context(CERT, Some("https://evil.example"), now())
+            .is_err());
+        assert!(other.check_context(b"other", Some(ORIGIN), now()).is_err());
+    }
+
+    #[test]
+    fn replayed_nonce_is_rejected() {
+        let cache = NonceCache::default();
+        cache.claim("n-1", now()).unwrap();
+        assert!(cache.claim("n-1", now() + Duration::seconds(1)).is_err());
+        cache.claim("n-2", now()).unwrap();
+    }
+
+    #[test]
+    fn nonce_expires_after_twice_the_skew() {
+        let cache = NonceCache::default();
+        cache.claim("n-1", now()).unwrap();
+        let window = Duration::seconds(MAX_CLOCK_SKEW_SECONDS * 2);
+        assert!(cache
+            .claim("n-1", now() + window - Duration::seconds(1))
+            .is_err());
+        cache.claim("n-1", now() + window).unwrap();
+    }
+}
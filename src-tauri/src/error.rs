@@ -0,0 +1,221 @@
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use cryptoki::error::{Error as Pkcs11Error, RvError};
+use serde::{Serialize, Serializer};
+use std::error::Error;
+use std::fmt;
+
+/// Failures reported to callers of the local HTTP API.
+///
+/// Each variant has a stable `code` the web app can branch on and localize;
+/// `message` is an English fallback and `details` carries the underlying
+/// error text, if any, for diagnostics.
+#[derive(Debug)]
+pub enum AgentError {
+    PinIncorrect,
+    PinLocked,
+    TokenAbsent,
+    CertificateNotFound,
+    KeyNotFound,
+    /// The signing certificate's chain did not validate.
+    CertificateInvalid(String),
+    UnsupportedKeyType,
+    UserCancelled,
+    Timeout,
+    /// The request's `Origin` is not the one the platform authorized.
+    UnauthorizedOrigin(String),
+    /// The platform's authorization of the request did not verify.
+    UnauthorizedRequest(String),
+    InvalidRequest(String),
+    /// The time-stamping authority failed or returned an invalid token.
+    TimestampFailed(String),
+    Internal(String),
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'static str,
+    message: &'static str,
+    details: Option<&'a str>,
+}
+
+impl AgentError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AgentError::PinIncorrect => "PIN_INCORRECT",
+            AgentError::PinLocked => "PIN_LOCKED",
+            AgentError::TokenAbsent => "TOKEN_ABSENT",
+            AgentError::CertificateNotFound => "CERTIFICATE_NOT_FOUND",
+            AgentError::KeyNotFound => "KEY_NOT_FOUND",
+            AgentError::CertificateInvalid(_) => "CERTIFICATE_INVALID",
+            AgentError::UnsupportedKeyType => "UNSUPPORTED_KEY_TYPE",
+            AgentError::UserCancelled => "USER_CANCELLED",
+            AgentError::Timeout => "TIMEOUT",
+            AgentError::UnauthorizedOrigin(_) => "UNAUTHORIZED_ORIGIN",
+            AgentError::UnauthorizedRequest(_) => "UNAUTHORIZED_REQUEST",
+            AgentError::InvalidRequest(_) => "INVALID_REQUEST",
+            AgentError::TimestampFailed(_) => "TIMESTAMP_FAILED",
+            AgentError::Internal(_) => "INTERNAL_ERROR",
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            AgentError::PinIncorrect => "The PIN is incorrect.",
+            AgentError::PinLocked => "The PIN is locked after too many failed attempts.",
+            AgentError::TokenAbsent => "No security token is connected.",
+            AgentError::CertificateNotFound => "The certificate was not found on the token.",
+            AgentError::KeyNotFound => "No private key matching the certificate was found.",
+            AgentError::CertificateInvalid(_) => "The signing certificate is not valid.",
+            AgentError::UnsupportedKeyType => "The token key type is not supported for signing.",
+            AgentError::UserCancelled => "The user cancelled the request.",
+            AgentError::Timeout => "The user did not respond in time.",
+            AgentError::UnauthorizedOrigin(_) => "The requesting origin is not authorized.",
+            AgentError::UnauthorizedRequest(_) => "The signing request is not authorized.",
+            AgentError::InvalidRequest(_) => "The request is invalid.",
+            AgentError::TimestampFailed(_) => "The signature could not be timestamped.",
+            AgentError::Internal(_) => "An internal error occurred.",
+        }
+    }
+
+    pub fn details(&self) -> Option<&str> {
+        match self {
+            AgentError::CertificateInvalid(details)
+            | AgentError::UnauthorizedOrigin(details)
+            | AgentError::UnauthorizedRequest(details)
+            | AgentError::InvalidRequest(details)
+            | AgentError::TimestampFailed(details)
+            | AgentError::Internal(details) => Some(details),
+            _ => None,
+        }
+    }
+
+    /// Recovers the most specific error from the boxed errors the PKCS#11
+    /// helpers return.
+    pub fn from_boxed(error: Box<dyn Error>) -> Self {
+        let error = match error.downcast::<AgentError>() {
+            Ok(error) => return *error,
+            Err(error) => error,
+        };
+        match error.downcast::<Pkcs11Error>() {
+            Ok(error) => AgentError::from(*error),
+            Err(error) => AgentError::Internal(error.to_string()),
+        }
+    }
+}
+
+impl From<Pkcs11Error> for AgentError {
+    fn from(error: Pkcs11Error) -> Self {
+        match error {
+            Pkcs11Error::Pkcs11(RvError::PinIncorrect, _) => AgentError::PinIncorrect,
+            Pkcs11Error::Pkcs11(RvError::PinLocked, _) => AgentError::PinLocked,
+            Pkcs11Error::Pkcs11(
+                RvError::TokenNotPresent | RvError::TokenNotRecognized | RvError::DeviceRemoved,
+                _,
+            ) => AgentError::TokenAbsent,
+            Pkcs11Error::Pkcs11(RvError::FunctionCanceled, _) => AgentError::UserCancelled,
+            error => AgentError::Internal(error.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for AgentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.details() {
+            Some(details) => write!(f, "{} {}", self.message(), details),
+            None => f.write_str(self.message()),
+        }
+    }
+}
+
+impl Error for AgentError {}
+
+/// Serializes as the `{code, message, details}` body of error responses.
+impl Serialize for AgentError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ErrorBody {
+            code: self.code(),
+            message: self.message(),
+            details: self.details(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl ResponseError for AgentError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AgentError::PinIncorrect => StatusCode::UNAUTHORIZED,
+            AgentError::PinLocked => StatusCode::LOCKED,
+            AgentError::TokenAbsent => StatusCode::SERVICE_UNAVAILABLE,
+            AgentError::CertificateNotFound | AgentError::KeyNotFound => StatusCode::NOT_FOUND,
+            AgentError::UnsupportedKeyType | AgentError::CertificateInvalid(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            AgentError::UserCancelled => StatusCode::CONFLICT,
+            AgentError::Timeout => StatusCode::REQUEST_TIMEOUT,
+            AgentError::UnauthorizedOrigin(_) | AgentError::UnauthorizedRequest(_) => {
+                StatusCode::FORBIDDEN
+            }
+            AgentError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            AgentError::TimestampFailed(_) => StatusCode::BAD_GATEWAY,
+            AgentError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(self)
+    }
+}
+
+/// Extractor error handler that reports a malformed JSON body, path or
+/// query string as `InvalidRequest`, so every failure the API returns has
+/// the same `{code, message, details}` body.
+pub fn invalid_request<E: fmt::Display>(error: E, _req: &HttpRequest) -> actix_web::Error {
+    AgentError::InvalidRequest(error.to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App};
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Body {
+        name: String,
+    }
+
+    async fn echo(body: web::Json<Body>, query: web::Query<Body>) -> HttpResponse {
+        HttpResponse::Ok().body(format!("{} {}", body.name, query.name))
+    }
+
+    #[actix_web::test]
+    async fn malformed_input_is_an_invalid_request() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::JsonConfig::default().error_handler(invalid_request))
+                .app_data(web::QueryConfig::default().error_handler(invalid_request))
+                .route("/echo", web::post().to(echo)),
+        )
+        .await;
+
+        for (uri, body) in [
+            ("/echo?name=a", "{\"name\": 1}"),
+            ("/echo?name=a", "{}"),
+            ("/echo?name=a", "not json"),
+            ("/echo", "{\"name\": \"a\"}"),
+        ] {
+            let req = test::TestRequest::post()
+                .uri(uri)
+                .insert_header(("Content-Type", "application/json"))
+                .set_payload(body)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", body);
+            let json: serde_json::Value = test::read_body_json(resp).await;
+            assert_eq!(json["code"], "INVALID_REQUEST");
+            assert!(json["details"].is_string());
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::path::Path;
+use std::sync::Mutex;
+
+const HISTORY_FILE: &str = "history.sqlite3";
+
+/// Upper bound on `limit` for a single history query.
+const MAX_PAGE_SIZE: u32 = 500;
+
+/// A signature to be remembered in the history.
+#[derive(Debug, Clone)]
+pub struct NewHistoryEntry {
+    pub origin: Option<String>,
+    pub document_name: Option<String>,
+    pub doc_hash: String,
+    pub hash_algorithm: String,
+    /// Hex DER of the signing certificate.
+    pub certificate: String,
+    pub cert_fingerprint: String,
+    pub signature: String,
+}
+
+impl NewHistoryEntry {
+    /// An entry for a signature made with `cert_der`, without an origin.
+    pub fn new(
+        document_name: Option<String>,
+        cert_der: &[u8],
+        doc_hash: String,
+        hash_algorithm: String,
+        signature: String,
+    ) -> Self {
+        NewHistoryEntry {
+            origin: None,
+            document_name,
+            doc_hash,
+            hash_algorithm,
+            certificate: hex::encode(cert_der),
+            cert_fingerprint: hex::encode(Sha256::digest(cert_der)),
+            signature,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEntry {
+    pub id: i64,
+    /// RFC 3339 time the signature was produced.
+    pub signed_at: String,
+    pub origin: Option<String>,
+    pub document_name: Option<String>,
+    pub doc_hash: String,
+    pub hash_algorithm: String,
+    pub certificate: String,
+    pub cert_fingerprint: String,
+    pub signature: String,
+}
+
+impl HistoryEntry {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(HistoryEntry {
+            id: row.get("id")?,
+            signed_at: row.get("signed_at")?,
+            origin: row.get("origin")?,
+            document_name: row.get("document_name")?,
+            doc_hash: row.get("doc_hash")?,
+            hash_algorithm: row.get("hash_algorithm")?,
+            certificate: row.get("certificate")?,
+            cert_fingerprint: row.get("cert_fingerprint")?,
+            signature: row.get("signature")?,
+        })
+    }
+}
+
+/// Local store of produced signatures, kept in SQLite in the app data
+/// directory so users can find and re-export past signatures.
+#[derive(Debug)]
+pub struct SignatureHistory {
+    conn: Mutex<Connection>,
+}
+
+impl SignatureHistory {
+    pub fn open(data_dir: &Path) -> Result<Self, Box<dyn Error>> {
+        std::fs::create_dir_all(data_dir)?;
+        let conn = Connection::open(data_dir.join(HISTORY_FILE))?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS signatures (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                signed_at TEXT NOT NULL,
+                origin TEXT,
+                document_name TEXT,
+                doc_hash TEXT NOT NULL,
+                hash_algorithm TEXT NOT NULL,
+                certificate TEXT NOT NULL,
+                cert_fingerprint TEXT NOT NULL,
+                signature TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS signatures_signed_at ON signatures (signed_at);",
+        )?;
+        Ok(SignatureHistory {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> Result<std::sync::MutexGuard<'_, Connection>, Box<dyn Error>> {
+        self.conn
+            .lock()
+            .map_err(|_| "Signature history lock poisoned".into())
+    }
+
+    pub fn insert(&self, entry: NewHistoryEntry) -> Result<i64, Box<dyn Error>> {
+        let conn = self.conn()?;
+        conn.execute(
+            "INSERT INTO signatures (signed_at, origin, document_name, doc_hash, hash_algorithm,
+                certificate, cert_fingerprint, signature)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                chrono::Utc::now().to_rfc3339(),
+                entry.origin,
+                entry.document_name,
+                entry.doc_hash,
+                entry.hash_algorithm,
+                entry.certificate,
+                entry.cert_fingerprint,
+                entry.signature,
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Returns the newest entries first. `query` matches case-insensitively
+    /// against the document name, hash, origin and certificate fingerprint.
+    pub fn search(
+        &self,
+        query: Option<&str>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<HistoryEntry>, Box<dyn Error>> {
+        let pattern = format!(
+            "%{}%",
+            query
+                .unwrap_or_default()
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        let conn = self.conn()?;
+        let mut statement = conn.prepare(
+            "SELECT * FROM signatures
+             WHERE document_name LIKE ?1 ESCAPE '\\'
+                OR doc_hash LIKE ?1 ESCAPE '\\'
+                OR origin LIKE ?1 ESCAPE '\\'
+                OR cert_fingerprint LIKE ?1 ESCAPE '\\'
+             ORDER BY id DESC
+             LIMIT ?2 OFFSET ?3",
+        )?;
+        let rows = statement.query_map(
+            params![pattern, limit.min(MAX_PAGE_SIZE), offset],
+            HistoryEntry::from_row,
+        )?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    pub fn get(&self, id: i64) -> Result<Option<HistoryEntry>, Box<dyn Error>> {
+        let conn = self.conn()?;
+        Ok(conn
+            .query_row(
+                "SELECT * FROM signatures WHERE id = ?1",
+                params![id],
+                HistoryEntry::from_row,
+            )
+            .optional()?)
+    }
+
+    /// Deletes an entry, returning whether it existed.
+    pub fn delete(&self, id: i64) -> Result<bool, Box<dyn Error>> {
+        let conn = self.conn()?;
+        Ok(conn.execute("DELETE FROM signatures WHERE id = ?1", params![id])? > 0)
+    }
+}
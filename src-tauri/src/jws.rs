@@ -0,0 +1,469 @@
+use base64::prelude::*;
+use chrono::{DateTime, SecondsFormat, Utc};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::signature::Verifier;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use x509_cert::der::oid::db::rfc5912;
+use x509_cert::der::{Decode, Encode};
+use x509_cert::Certificate;
+
+/// Header parameters this module understands when a signature lists them in
+/// `crit`.
+const UNDERSTOOD_CRITICAL: &[&str] = &["sigT"];
+
+/// The JWS algorithms the token's keys can produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JwsAlgorithm {
+    RS256,
+    PS256,
+    ES256,
+}
+
+impl JwsAlgorithm {
+    fn name(self) -> &'static str {
+        match self {
+            JwsAlgorithm::RS256 => "RS256",
+            JwsAlgorithm::PS256 => "PS256",
+            JwsAlgorithm::ES256 => "ES256",
+        }
+    }
+
+    fn from_name(name: &str) -> Result<Self, Box<dyn Error>> {
+        match name {
+            "RS256" => Ok(JwsAlgorithm::RS256),
+            "PS256" => Ok(JwsAlgorithm::PS256),
+            "ES256" => Ok(JwsAlgorithm::ES256),
+            _ => Err(format!("Unsupported JWS algorithm {}", name).into()),
+        }
+    }
+
+    /// RS256 for RSA keys and ES256 for P-256 keys.
+    pub fn for_certificate(cert_der: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let cert = Certificate::from_der(cert_der)?;
+        let spki = &cert.tbs_certificate.subject_public_key_info;
+        if spki.algorithm.oid == rfc5912::RSA_ENCRYPTION {
+            Ok(JwsAlgorithm::RS256)
+        } else {
+            Ok(JwsAlgorithm::ES256)
+        }
+    }
+
+    /// Fails unless the certificate's key can sign with this algorithm.
+    pub fn check_certificate(self, cert_der: &[u8]) -> Result<(), Box<dyn Error>> {
+        let cert = Certificate::from_der(cert_der)?;
+        let spki = &cert.tbs_certificate.subject_public_key_info;
+        let matches = match self {
+            JwsAlgorithm::RS256 | JwsAlgorithm::PS256 => {
+                spki.algorithm.oid == rfc5912::RSA_ENCRYPTION
+            }
+            JwsAlgorithm::ES256 => {
+                spki.algorithm.oid == rfc5912::ID_EC_PUBLIC_KEY
+                    && spki
+                        .algorithm
+                        .parameters
+                        .as_ref()
+                        .and_then(|curve| curve.decode_as().ok())
+                        == Some(rfc5912::SECP_256_R_1)
+            }
+        };
+        if !matches {
+            return Err(format!("The certificate's key cannot sign with {}", self.name()).into());
+        }
+        Ok(())
+    }
+
+    pub fn uses_pss(self) -> bool {
+        self == JwsAlgorithm::PS256
+    }
+
+    /// What to hand the token to sign `signing_input`: the RSA mechanisms
+    /// hash their input, the ECDSA one does not.
+    pub fn token_input(self, signing_input: &[u8]) -> Vec<u8> {
+        match self {
+            JwsAlgorithm::RS256 | JwsAlgorithm::PS256 => signing_input.to_vec(),
+            JwsAlgorithm::ES256 => Sha256::digest(signing_input).to_vec(),
+        }
+    }
+}
+
+fn base64url(data: &[u8]) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(data)
+}
+
+/// The base64url-encoded protected header of a JAdES baseline signature:
+/// the algorithm, the certificate chain as `x5c`, the signing
+/// certificate's `x5t#S256` digest and, if given, the claimed signing time
+/// as the critical `sigT` parameter.
+pub fn protected_header(
+    algorithm: JwsAlgorithm,
+    chain: &[Vec<u8>],
+    signing_time: Option<DateTime<Utc>>,
+) -> Result<String, Box<dyn Error>> {
+    let leaf = chain.first().ok_or("The certificate chain is empty")?;
+    let mut header = json!({
+        "alg": algorithm.name(),
+        "cty": "json",
+        "x5c": chain.iter().map(|cert| BASE64_STANDARD.encode(cert)).collect::<Vec<_>>(),
+        "x5t#S256": base64url(&Sha256::digest(leaf)),
+    });
+    if let Some(signing_time) = signing_time {
+        header["sigT"] = json!(signing_time.to_rfc3339_opts(SecondsFormat::Secs, true));
+        header["crit"] = json!(["sigT"]);
+    }
+    Ok(base64url(&serde_json::to_vec(&header)?))
+}
+
+/// A signed JWS in its three base64url-encoded parts.
+pub struct Jws {
+    pub protected: String,
+    pub payload: String,
+    pub signature: String,
+}
+
+impl Jws {
+    pub fn new(protected: String, payload: &[u8]) -> Self {
+        Jws {
+            protected,
+            payload: base64url(payload),
+            signature: String::new(),
+        }
+    }
+
+    /// The bytes the signature covers.
+    pub fn signing_input(&self) -> String {
+        format!("{}.{}", self.protected, self.payload)
+    }
+
+    /// Sets the signature from the token's output, which for ECDSA is
+    /// already the `r || s` form JWS uses.
+    pub fn set_signature(&mut self, raw_signature: &[u8]) {
+        self.signature = base64url(raw_signature);
+    }
+
+    pub fn compact(&self) -> String {
+        format!("{}.{}.{}", self.protected, self.payload, self.signature)
+    }
+
+    /// The flattened JWS JSON serialization.
+    pub fn json(&self) -> Value {
+        json!({
+            "protected": self.protected,
+            "payload": self.payload,
+            "signature": self.signature,
+        })
+    }
+
+    /// Reads the compact serialization, or the flattened or general JSON
+    /// serialization with a single signature.
+    pub fn parse(serialized: &Value) -> Result<Self, Box<dyn Error>> {
+        if let Some(compact) = serialized.as_str() {
+            let parts: Vec<&str> = compact.trim().split('.').collect();
+            let [protected, payload, signature] = parts.as_slice() else {
+                return Err("A compact JWS has three parts".into());
+            };
+            return Ok(Jws {
+                protected: protected.to_string(),
+                payload: payload.to_string(),
+                signature: signature.to_string(),
+            });
+        }
+
+        let object = serialized.as_object().ok_or("Not a JWS")?;
+        let signature_object = match object.get("signatures") {
+            Some(Value::Array(signatures)) if signatures.len() == 1 => {
+                signatures[0].as_object().ok_or("Malformed JWS signature")?
+            }
+            Some(_) => return Err("Only JWS with a single signature are supported".into()),
+            None => object,
+        };
+        let field = |map: &Map<String, Value>, name: &str| {
+            map.get(name)
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| format!("The JWS has no {}", name))
+        };
+        Ok(Jws {
+            protected: field(signature_object, "protected")?,
+            payload: field(object, "payload")?,
+            signature: field(signature_object, "signature")?,
+        })
+    }
+}
+
+/// What a JWS claims about itself once its signature has been checked
+/// against the first `x5c` certificate.
+#[derive(Debug, Serialize)]
+pub struct JwsVerification {
+    pub algorithm: JwsAlgorithm,
+    pub signature_valid: bool,
+    /// The `sigT` signing time, if the header has one.
+    pub signing_time: Option<String>,
+    /// The decoded payload.
+    pub payload: String,
+    /// The DER certificates from `x5c`, signing certificate first.
+    #[serde(skip)]
+    pub certificates: Vec<Vec<u8>>,
+}
+
+/// Checks a JWS made by `Jws` or any other JAdES baseline signer that puts
+/// its certificate in `x5c`. Malformed input is an error; a signature that
+/// does not verify is reported in the result.
+pub fn verify(jws: &Jws) -> Result<JwsVerification, Box<dyn Error>> {
+    let header: Map<String, Value> =
+        serde_json::from_slice(&BASE64_URL_SAFE_NO_PAD.decode(&jws.protected)?)?;
+    let algorithm = JwsAlgorithm::from_name(
+        header
+            .get("alg")
+            .and_then(Value::as_str)
+            .ok_or("The header has no alg")?,
+    )?;
+    if let Some(critical) = header.get("crit") {
+        for name in critical.as_array().ok_or("crit must be an array")? {
+            let name = name.as_str().ok_or("crit must list names")?;
+            if !UNDERSTOOD_CRITICAL.contains(&name) {
+                return Err(format!("Unsupported critical header parameter {}", name).into());
+            }
+            if !header.contains_key(name) {
+                return Err(format!("The critical header parameter {} is missing", name).into());
+            }
+        }
+    }
+
+    let certificates = header
+        .get("x5c")
+        .and_then(Value::as_array)
+        .ok_or("The header has no x5c certificate chain")?
+        .iter()
+        .map(|cert| {
+            let cert = cert.as_str().ok_or("x5c must hold strings")?;
+            Ok(BASE64_STANDARD.decode(cert)?)
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    let leaf = certificates.first().ok_or("x5c is empty")?;
+    if let Some(thumbprint) = header.get("x5t#S256") {
+        if thumbprint.as_str() != Some(base64url(&Sha256::digest(leaf)).as_str()) {
+            return Err("x5t#S256 does not match the x5c signing certificate".into());
+        }
+    }
+
+    let signature = BASE64_URL_SAFE_NO_PAD.decode(&jws.signature)?;
+    let signature_valid = algorithm.check_certificate(leaf).is_ok()
+        && verify_signature(algorithm, leaf, jws.signing_input().as_bytes(), &signature).is_ok();
+    let payload = BASE64_URL_SAFE_NO_PAD.decode(&jws.payload)?;
+    Ok(JwsVerification {
+        algorithm,
+        signature_valid,
+        signing_time: header
+            .get("sigT")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        payload: String::from_utf8(payload)?,
+        certificates,
+    })
+}
+
+fn verify_signature(
+    algorithm: JwsAlgorithm,
+    cert_der: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    let cert = Certificate::from_der(cert_der)?;
+    let spki_der = cert.tbs_certificate.subject_public_key_info.to_der()?;
+    match algorithm {
+        JwsAlgorithm::RS256 => {
+            let key = rsa::RsaPublicKey::from_public_key_der(&spki_der)?;
+            let signature = rsa::pkcs1v15::Signature::try_from(signature)?;
+            rsa::pkcs1v15::VerifyingKey::<Sha256>::new(key).verify(message, &signature)?;
+        }
+        JwsAlgorithm::PS256 => {
+            let key = rsa::RsaPublicKey::from_public_key_der(&spki_der)?;
+            let signature = rsa::pss::Signature::try_from(signature)?;
+            rsa::pss::VerifyingKey::<Sha256>::new(key).verify(message, &signature)?;
+        }
+        JwsAlgorithm::ES256 => {
+            let key = p256::ecdsa::VerifyingKey::from_public_key_der(&spki_der)?;
+            let signature = p256::ecdsa::Signature::from_slice(signature)?;
+            key.verify(message, &signature)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_certs;
+    use p256::ecdsa::signature::hazmat::PrehashSigner;
+
+    const PAYLOAD: &str = r#"{"document":"contract","amount":100}"#;
+
+    /// Signs like the token does: ECDSA over the digest from
+    /// `token_input`, returning `r || s`.
+    fn es256_jws(key: &p256::ecdsa::SigningKey, protected: String, payload: &str) -> Jws {
+        let mut jws = Jws::new(protected, payload.as_bytes());
+        let digest = JwsAlgorithm::ES256.token_input(jws.signing_input().as_bytes());
+        let signature: p256::ecdsa::Signature = key.sign_prehash(&digest).unwrap();
+        jws.set_signature(&signature.to_bytes());
+        jws
+    }
+
+    /// A protected header built by hand, for headers `protected_header`
+    /// would never produce.
+    fn encode_header(header: Value) -> String {
+        base64url(&serde_json::to_vec(&header).unwrap())
+    }
+
+    fn es256_header(cert: &[u8]) -> Value {
+        json!({
+            "alg": "ES256",
+            "x5c": [BASE64_STANDARD.encode(cert)],
+            "x5t#S256": base64url(&Sha256::digest(cert)),
+        })
+    }
+
+    #[test]
+    fn es256_round_trip() {
+        let key = test_certs::p256_key();
+        let cert = test_certs::p256_certificate(&key);
+        assert_eq!(
+            JwsAlgorithm::for_certificate(&cert).unwrap(),
+            JwsAlgorithm::ES256
+        );
+        let signing_time = DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let protected = protected_header(
+            JwsAlgorithm::ES256,
+            std::slice::from_ref(&cert),
+            Some(signing_time),
+        )
+        .unwrap();
+        let jws = es256_jws(&key, protected, PAYLOAD);
+
+        for serialized in [json!(jws.compact()), jws.json()] {
+            let verification = verify(&Jws::parse(&serialized).unwrap()).unwrap();
+            assert!(verification.signature_valid);
+            assert_eq!(verification.algorithm, JwsAlgorithm::ES256);
+            assert_eq!(verification.payload, PAYLOAD);
+            assert_eq!(
+                verification.signing_time.as_deref(),
+                Some("2024-05-01T12:00:00Z")
+            );
+            assert_eq!(verification.certificates, vec![cert.clone()]);
+        }
+    }
+
+    #[test]
+    fn rsa_round_trips() {
+        let key = test_certs::rsa_key();
+        let cert = test_certs::rsa_certificate(&key);
+        assert_eq!(
+            JwsAlgorithm::for_certificate(&cert).unwrap(),
+            JwsAlgorithm::RS256
+        );
+        assert!(JwsAlgorithm::ES256.check_certificate(&cert).is_err());
+
+        let rs256 = rsa::pkcs1v15::SigningKey::<Sha256>::new(key.clone());
+        let ps256 = rsa::pss::BlindedSigningKey::<Sha256>::new(key);
+        for algorithm in [JwsAlgorithm::RS256, JwsAlgorithm::PS256] {
+            algorithm.check_certificate(&cert).unwrap();
+            let protected = protected_header(algorithm, std::slice::from_ref(&cert), None).unwrap();
+            let mut jws = Jws::new(protected, PAYLOAD.as_bytes());
+            let input = algorithm.token_input(jws.signing_input().as_bytes());
+            let signature = if algorithm.uses_pss() {
+                use rsa::signature::{RandomizedSigner, SignatureEncoding};
+                ps256
+                    .sign_with_rng(&mut rand::thread_rng(), &input)
+                    .to_vec()
+            } else {
+                use rsa::signature::{SignatureEncoding, Signer};
+                rs256.sign(&input).to_vec()
+            };
+            jws.set_signature(&signature);
+            let verification = verify(&jws).unwrap();
+            assert!(verification.signature_valid, "{:?}", algorithm);
+            assert_eq!(verification.signing_time, None);
+        }
+    }
+
+    #[test]
+    fn tampered_payload_does_not_verify() {
+        let key = test_certs::p256_key();
+        let cert = test_certs::p256_certificate(&key);
+        let protected = protected_header(JwsAlgorithm::ES256, &[cert], None).unwrap();
+        let mut jws = es256_jws(&key, protected, PAYLOAD);
+        jws.payload = base64url(br#"{"document":"contract","amount":1000}"#);
+        assert!(!verify(&jws).unwrap().signature_valid);
+    }
+
+    #[test]
+    fn other_key_does_not_verify() {
+        let cert = test_certs::p256_certificate(&test_certs::p256_key());
+        let protected = protected_header(JwsAlgorithm::ES256, &[cert], None).unwrap();
+        let jws = es256_jws(&test_certs::p256_key(), protected, PAYLOAD);
+        assert!(!verify(&jws).unwrap().signature_valid);
+    }
+
+    #[test]
+    fn wrong_thumbprint_is_rejected() {
+        let key = test_certs::p256_key();
+        let cert = test_certs::p256_certificate(&key);
+        let mut header = es256_header(&cert);
+        header["x5t#S256"] = json!(base64url(&Sha256::digest(b"another certificate")));
+        let jws = es256_jws(&key, encode_header(header), PAYLOAD);
+        assert!(verify(&jws).is_err());
+    }
+
+    #[test]
+    fn unknown_critical_parameter_is_rejected() {
+        let key = test_certs::p256_key();
+        let cert = test_certs::p256_certificate(&key);
+        let mut header = es256_header(&cert);
+        header["b64"] = json!(false);
+        header["crit"] = json!(["b64"]);
+        let jws = es256_jws(&key, encode_header(header), PAYLOAD);
+        assert!(verify(&jws).is_err());
+    }
+
+    #[test]
+    fn missing_critical_parameter_is_rejected() {
+        let key = test_certs::p256_key();
+        let cert = test_certs::p256_certificate(&key);
+        let mut header = es256_header(&cert);
+        header["crit"] = json!(["sigT"]);
+        let jws = es256_jws(&key, encode_header(header), PAYLOAD);
+        assert!(verify(&jws).is_err());
+    }
+
+    #[test]
+    fn es256_signature_must_be_r_and_s() {
+        let key = test_certs::p256_key();
+        let cert = test_certs::p256_certificate(&key);
+        let protected = protected_header(JwsAlgorithm::ES256, &[cert], None).unwrap();
+        let mut jws = es256_jws(&key, protected, PAYLOAD);
+        let raw = BASE64_URL_SAFE_NO_PAD.decode(&jws.signature).unwrap();
+        assert_eq!(raw.len(), 64);
+
+        let der = p256::ecdsa::Signature::from_slice(&raw).unwrap().to_der();
+        jws.set_signature(der.as_bytes());
+        assert!(!verify(&jws).unwrap().signature_valid);
+        jws.set_signature(&raw[..63]);
+        assert!(!verify(&jws).unwrap().signature_valid);
+    }
+
+    #[test]
+    fn malformed_serializations_are_rejected() {
+        assert!(Jws::parse(&json!("a.b")).is_err());
+        assert!(Jws::parse(&json!({ "payload": "e30" })).is_err());
+        let signature = json!({ "protected": "e30", "signature": "" });
+        assert!(Jws::parse(&json!({
+            "payload": "e30",
+            "signatures": [signature.clone(), signature],
+        }))
+        .is_err());
+    }
+}
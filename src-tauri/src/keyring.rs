@@ -0,0 +1,416 @@
+use base64::prelude::*;
+use chrono::{DateTime, Utc};
+use pgp::types::{KeyId, PublicKeyTrait};
+use pgp::{Deserializable, SignedPublicKey, StandaloneSignature};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+const KEYRING_FILE: &str = "keyring.json";
+
+/// A company key as distributed in a keyring document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyringEntry {
+    /// ASCII-armored OpenPGP public key.
+    pub armored_key: String,
+    /// RFC 3339 time from which signatures by this key are accepted.
+    pub not_before: Option<String>,
+    /// RFC 3339 time after which signatures by this key are rejected.
+    pub not_after: Option<String>,
+}
+
+/// The JSON document the platform signs when rotating company keys.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyringDocument {
+    /// Monotonic version; an update must carry a higher serial than the
+    /// keyring it replaces, so an old keyring cannot be replayed.
+    pub serial: u64,
+    pub keys: Vec<KeyringEntry>,
+}
+
+#[derive(Debug)]
+struct TrustedKey {
+    key: SignedPublicKey,
+    not_before: Option<DateTime<Utc>>,
+    not_after: Option<DateTime<Utc>>,
+}
+
+impl TrustedKey {
+    fn is_valid_at(&self, now: DateTime<Utc>) -> bool {
+        self.not_before.is_none_or(|start| now >= start)
+            && self.not_after.is_none_or(|end| now <= end)
+    }
+
+    /// Tries the primary key and its subkeys, restricted to the key IDs the
+    /// signature names as issuer when it names any.
+    fn verifies(
+        &self,
+        signature: &StandaloneSignature,
+        issuers: &[&KeyId],
+        message: &[u8],
+    ) -> bool {
+        let wanted = |key_id: KeyId| issuers.is_empty() || issuers.contains(&&key_id);
+
+        if wanted(self.key.key_id()) && signature.verify(&self.key, message).is_ok() {
+            return true;
+        }
+        self.key.public_subkeys.iter().any(|subkey| {
+            wanted(subkey.key.key_id()) && signature.verify(&subkey.key, message).is_ok()
+        })
+    }
+}
+
+/// The set of company keys trusted to authorize signing requests.
+#[derive(Debug)]
+pub struct Keyring {
+    serial: u64,
+    keys: Vec<TrustedKey>,
+}
+
+fn parse_time(value: &Option<String>) -> Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+    match value {
+        Some(value) => Ok(Some(
+            DateTime::parse_from_rfc3339(value)?.with_timezone(&Utc),
+        )),
+        None => Ok(None),
+    }
+}
+
+fn keyring_path(data_dir: &Path) -> PathBuf {
+    data_dir.join(KEYRING_FILE)
+}
+
+/// Decodes the base64-wrapped, ASCII-armored detached signature format the
+/// platform uses for everything it signs.
+pub fn decode_signature(signature: &str) -> Result<StandaloneSignature, Box<dyn Error>> {
+    let sig_decoded = BASE64_STANDARD.decode(signature)?;
+    let (signature, _) = StandaloneSignature::from_armor_single_buf(sig_decoded.as_slice())?;
+    Ok(signature)
+}
+
+impl Keyring {
+    pub fn from_document(document: &KeyringDocument) -> Result<Self, Box<dyn Error>> {
+        let mut keys = Vec::with_capacity(document.keys.len());
+        for entry in &document.keys {
+            let (key, _) = SignedPublicKey::from_string(&entry.armored_key)?;
+            key.verify()?;
+            keys.push(TrustedKey {
+                key,
+                not_before: parse_time(&entry.not_before)?,
+                not_after: parse_time(&entry.not_after)?,
+            });
+        }
+        if keys.is_empty() {
+            return Err("Keyring does not contain any keys".into());
+        }
+        Ok(Keyring {
+            serial: document.serial,
+            keys,
+        })
+    }
+
+    /// Builds the serial-0 keyring from the key shipped with the app.
+    pub fn bundled(armored_key: &str) -> Result<Self, Box<dyn Error>> {
+        Self::from_document(&KeyringDocument {
+            serial: 0,
+            keys: vec![KeyringEntry {
+                armored_key: armored_key.to_string(),
+                not_before: None,
+                not_after: None,
+            }],
+        })
+    }
+
+    /// Loads the keyring last accepted through `apply_update`, if any.
+    pub fn load(data_dir: &Path) -> Result<Option<Self>, Box<dyn Error>> {
+        let path = keyring_path(data_dir);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = std::fs::read_to_string(path)?;
+        let document: KeyringDocument = serde_json::from_str(&contents)?;
+        Ok(Some(Self::from_document(&document)?))
+    }
+
+    pub fn serial(&self) -> u64 {
+        self.serial
+    }
+
+    /// Verifies a detached signature over `message` against the keys that
+    /// are inside their validity window at `now`.
+    pub fn verify(
+        &self,
+        message: &[u8],
+        signature: &StandaloneSignature,
+        now: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>> {
+        let issuers = signature.signature.issuer();
+        let verified = self
+            .keys
+            .iter()
+            .filter(|key| key.is_valid_at(now))
+            .any(|key| key.verifies(signature, &issuers, message));
+        if verified {
+            Ok(())
+        } else {
+            Err("Signature was not made by a currently valid company key".into())
+        }
+    }
+
+    /// Accepts a new keyring signed by a key of this one and persists it to
+    /// `data_dir`, returning the keyring now in effect.
+    pub fn apply_update(
+        &self,
+        data_dir: &Path,
+        document_json: &str,
+        signature: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Keyring, Box<dyn Error>> {
+        self.verify(document_json.as_bytes(), &decode_signature(signature)?, now)?;
+
+        let document: KeyringDocument = serde_json::from_str(document_json)?;
+        if document.serial <= self.serial {
+            return Err(format!(
+                "Keyring serial {} does not supersede current serial {}",
+                document.serial, self.serial
+            )
+            .into());
+        }
+        let keyring = Self::from_document(&document)?;
+        if !keyring
+            .keys
+            .iter()
+            .any(|key| key.not_after.is_none_or(|end| now <= end))
+        {
+            return Err("Keyring update does not contain any unexpired key".into());
+        }
+
+        // Written through a rename so an interrupted update leaves the
+        // previous keyring in place rather than a truncated file.
+        std::fs::create_dir_all(data_dir)?;
+        let path = keyring_path(data_dir);
+        let temp = path.with_extension("json.tmp");
+        std::fs::write(&temp, document_json)?;
+        std::fs::rename(&temp, &path)?;
+        Ok(keyring)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, SubsecRound};
+    use pgp::crypto::hash::HashAlgorithm;
+    use pgp::packet::{SignatureConfig, SignatureType, Subpacket, SubpacketData};
+    use pgp::types::SecretKeyTrait;
+    use pgp::{ArmorOptions, KeyType, SecretKeyParamsBuilder, SignedSecretKey};
+
+    fn generate_key() -> SignedSecretKey {
+        let mut rng = rand::thread_rng();
+        SecretKeyParamsBuilder::default()
+            .key_type(KeyType::EdDSALegacy)
+            .can_sign(true)
+            .primary_user_id("Gov-Smart <keys@gov-smart.example>".into())
+            .build()
+            .unwrap()
+            .generate(&mut rng)
+            .unwrap()
+            .sign(&mut rng, String::new)
+            .unwrap()
+    }
+
+    fn armored_public(key: &SignedSecretKey) -> String {
+        key.public_key()
+            .sign(rand::thread_rng(), key, String::new)
+            .unwrap()
+            .to_armored_string(ArmorOptions::default())
+            .unwrap()
+    }
+
+    /// Signs `message` with `key`, naming `issuer` as the issuing key.
+    fn sign_as(key: &SignedSecretKey, issuer: KeyId, message: &[u8]) -> String {
+        let mut config = SignatureConfig::v4(
+            SignatureType::Binary,
+            key.algorithm(),
+            HashAlgorithm::SHA2_256,
+        );
+        config.hashed_subpackets = vec![Subpacket::regular(SubpacketData::SignatureCreationTime(
+            Utc::now().trunc_subsecs(0),
+        ))];
+        config.unhashed_subpackets = vec![Subpacket::regular(SubpacketData::Issuer(issuer))];
+        let signature = config.sign(key, String::new, message).unwrap();
+        let armored = StandaloneSignature::new(signature)
+            .to_armored_bytes(ArmorOptions::default())
+            .unwrap();
+        BASE64_STANDARD.encode(armored)
+    }
+
+    fn sign(key: &SignedSecretKey, message: &[u8]) -> String {
+        sign_as(key, key.key_id(), message)
+    }
+
+    fn entry(
+        key: &SignedSecretKey,
+        not_before: Option<DateTime<Utc>>,
+        not_after: Option<DateTime<Utc>>,
+    ) -> KeyringEntry {
+        KeyringEntry {
+            armored_key: armored_public(key),
+            not_before: not_before.map(|time| time.to_rfc3339()),
+            not_after: not_after.map(|time| time.to_rfc3339()),
+        }
+    }
+
+    fn document_json(serial: u64, keys: Vec<KeyringEntry>) -> String {
+        serde_json::to_string(&KeyringDocument { serial, keys }).unwrap()
+    }
+
+    fn verify(keyring: &Keyring, signature: &str, now: DateTime<Utc>) -> bool {
+        keyring
+            .verify(b"message", &decode_signature(signature).unwrap(), now)
+            .is_ok()
+    }
+
+    #[test]
+    fn bundled_key_verifies_its_signatures() {
+        let key = generate_key();
+        let keyring = Keyring::bundled(&armored_public(&key)).unwrap();
+        assert_eq!(keyring.serial(), 0);
+        assert!(verify(&keyring, &sign(&key, b"message"), Utc::now()));
+        assert!(!verify(&keyring, &sign(&key, b"other"), Utc::now()));
+    }
+
+    #[test]
+    fn unknown_key_is_rejected() {
+        let keyring = Keyring::bundled(&armored_public(&generate_key())).unwrap();
+        assert!(!verify(
+            &keyring,
+            &sign(&generate_key(), b"message"),
+            Utc::now()
+        ));
+    }
+
+    #[test]
+    fn keys_are_only_valid_inside_their_window() {
+        let now = Utc::now();
+        let key = generate_key();
+        let keyring = Keyring::from_document(&KeyringDocument {
+            serial: 1,
+            keys: vec![entry(
+                &key,
+                Some(now - Duration::days(1)),
+                Some(now + Duration::days(1)),
+            )],
+        })
+        .unwrap();
+        let signature = sign(&key, b"message");
+        assert!(verify(&keyring, &signature, now));
+        assert!(!verify(&keyring, &signature, now - Duration::days(2)));
+        assert!(!verify(&keyring, &signature, now + Duration::days(2)));
+    }
+
+    #[test]
+    fn only_the_named_issuer_is_tried() {
+        let first = generate_key();
+        let second = generate_key();
+        let keyring = Keyring::from_document(&KeyringDocument {
+            serial: 1,
+            keys: vec![entry(&first, None, None), entry(&second, None, None)],
+        })
+        .unwrap();
+        assert!(verify(&keyring, &sign(&first, b"message"), Utc::now()));
+        assert!(verify(&keyring, &sign(&second, b"message"), Utc::now()));
+
+        let misattributed = sign_as(&first, second.key_id(), b"message");
+        assert!(!verify(&keyring, &misattributed, Utc::now()));
+    }
+
+    #[test]
+    fn update_must_raise_the_serial() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = Utc::now();
+        let key = generate_key();
+        let current = Keyring::from_document(&KeyringDocument {
+            serial: 5,
+            keys: vec![entry(&key, None, None)],
+        })
+        .unwrap();
+
+        for serial in [4, 5] {
+            let json = document_json(serial, vec![entry(&key, None, None)]);
+            let result = current.apply_update(dir.path(), &json, &sign(&key, json.as_bytes()), now);
+            assert!(result.is_err(), "serial {} was accepted", serial);
+        }
+        assert!(Keyring::load(dir.path()).unwrap().is_none());
+
+        let json = document_json(6, vec![entry(&key, None, None)]);
+        let updated = current
+            .apply_update(dir.path(), &json, &sign(&key, json.as_bytes()), now)
+            .unwrap();
+        assert_eq!(updated.serial(), 6);
+        assert_eq!(Keyring::load(dir.path()).unwrap().unwrap().serial(), 6);
+    }
+
+    #[test]
+    fn rotation_hands_over_to_the_new_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = Utc::now();
+        let old_key = generate_key();
+        let new_key = generate_key();
+        let current = Keyring::bundled(&armored_public(&old_key)).unwrap();
+
+        let json = document_json(
+            1,
+            vec![
+                entry(&old_key, None, Some(now + Duration::days(30))),
+                entry(&new_key, Some(now), None),
+            ],
+        );
+        let rotated = current
+            .apply_update(dir.path(), &json, &sign(&old_key, json.as_bytes()), now)
+            .unwrap();
+
+        let old_signature = sign(&old_key, b"message");
+        assert!(verify(&rotated, &old_signature, now));
+        assert!(!verify(&rotated, &old_signature, now + Duration::days(31)));
+        assert!(verify(
+            &rotated,
+            &sign(&new_key, b"message"),
+            now + Duration::days(31)
+        ));
+    }
+
+    #[test]
+    fn update_signed_by_an_unknown_key_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let current = Keyring::bundled(&armored_public(&generate_key())).unwrap();
+        let intruder = generate_key();
+        let json = document_json(1, vec![entry(&intruder, None, None)]);
+        let result = current.apply_update(
+            dir.path(),
+            &json,
+            &sign(&intruder, json.as_bytes()),
+            Utc::now(),
+        );
+        assert!(result.is_err());
+        assert!(Keyring::load(dir.path()).unwrap().is_none());
+    }
+
+    #[test]
+    fn update_without_an_unexpired_key_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = Utc::now();
+        let key = generate_key();
+        let current = Keyring::bundled(&armored_public(&key)).unwrap();
+        let json = document_json(1, vec![entry(&key, None, Some(now - Duration::days(1)))]);
+        let result = current.apply_update(dir.path(), &json, &sign(&key, json.as_bytes()), now);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn corrupt_keyring_file_fails_to_load() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(KEYRING_FILE), "{\"serial\": 3").unwrap();
+        assert!(Keyring::load(dir.path()).is_err());
+    }
+}
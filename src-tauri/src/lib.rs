@@ -3,40 +3,152 @@
     windows_subsystem = "windows"
 )]
 
+mod appearance;
+mod asic;
+mod audit;
+mod cades;
+mod certificate;
+mod chain;
+mod cose;
+mod document;
+mod envelope;
+mod error;
+mod history;
+mod jws;
+mod keyring;
+mod merkle;
+mod openpgp;
+mod pdf;
+mod revocation;
+mod session;
+mod settings;
+mod smime;
+#[cfg(test)]
+mod test_certs;
+mod timestamp;
+
 use actix_cors::Cors;
-use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder};
+use actix_multipart::Multipart;
+use actix_web::{delete, get, post, web, App, HttpRequest, HttpResponse, HttpServer};
+use audit::{AuditEntry, AuditLog, AuditOutcome, AuditRecord, AuditVerification};
+use base64::prelude::*;
+use cades::CmsSigner;
+use certificate::CertificateSummary;
+use chain::{CertificatePool, ChainValidation};
+use cose::KeyReference;
 use cryptoki::context::{CInitializeArgs, Pkcs11};
-use cryptoki::mechanism::Mechanism;
+use cryptoki::error::{Error as Pkcs11Error, RvError};
+use cryptoki::mechanism::rsa::{PkcsMgfType, PkcsPssParams};
+use cryptoki::mechanism::{Mechanism, MechanismType};
 use cryptoki::object::{Attribute, AttributeType, KeyType, ObjectClass};
-use cryptoki::session::UserType;
+use cryptoki::session::{Session, UserType};
 use cryptoki::slot::Slot;
 use cryptoki::types::AuthPin;
+use der::{Decode, Encode};
+use document::{DocumentPreview, DEFAULT_HASH_ALGORITHM, MAX_DOCUMENT_SIZE};
+use envelope::{NonceCache, SigningEnvelope};
+use error::AgentError;
+use futures_util::{Stream, StreamExt};
+use history::{HistoryEntry, NewHistoryEntry, SignatureHistory};
+use jws::{Jws, JwsAlgorithm};
+use keyring::Keyring;
+use merkle::{LeafProof, MerkleTree};
+use pdf::{SignatureLevel, SignatureOptions, ValidationData};
+use revocation::{RevocationChecker, RevocationEvidence, RevocationStatus};
 use serde::{Deserialize, Serialize};
+use session::{SessionManager, SigningSession};
+use sha2::{Digest, Sha256};
 use std::error::Error;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tauri::{
     menu::{Menu, MenuItem},
     tray::TrayIconBuilder,
 };
 use tauri::{AppHandle, Manager};
+use timestamp::TimestampClient;
 
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 use tokio::sync::oneshot;
 
+use tauri_plugin_dialog::DialogExt;
 use tauri_plugin_updater::UpdaterExt;
 
 #[derive(Debug)]
 struct SigningRequest {
     cert_hash: String,
     doc_hash: String,
-    timestamp: String,
-    signed_certificate: String,
-    response_tx: oneshot::Sender<Result<String, String>>,
+    hash_algorithm: String,
+    origin: Option<String>,
+    document_name: Option<String>,
+    preview: Option<DocumentPreview>,
+    chain: Vec<CertificateSummary>,
+    /// What the token signs once the PIN is entered.
+    token_input: TokenInput,
+    response_tx: oneshot::Sender<Result<String, AgentError>>,
 }
 
 #[derive(Debug)]
 struct SigningState {
     current_request: Mutex<Option<SigningRequest>>,
+    current_batch: Mutex<Option<BatchSigningRequest>>,
+    used_nonces: NonceCache,
+}
+
+/// Most documents a single `/sign-batch` request may contain.
+const MAX_BATCH_SIZE: usize = 100;
+
+/// One signature result per batch item, in item order.
+type BatchResults = Vec<Result<String, AgentError>>;
+
+#[derive(Debug)]
+struct BatchSigningRequest {
+    cert_hash: String,
+    origin: Option<String>,
+    items: Vec<PendingBatchItem>,
+    chain: Vec<CertificateSummary>,
+    response_tx: oneshot::Sender<Result<BatchResults, AgentError>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct PendingBatchItem {
+    document_name: Option<String>,
+    doc_hash: String,
+    hash_algorithm: String,
+    /// Claimed only once the user approves the batch.
+    #[serde(skip)]
+    envelope: Option<SigningEnvelope>,
+}
+
+/// What the batch popup shows about the documents it was opened for.
+#[derive(Serialize)]
+struct PendingBatch {
+    origin: Option<String>,
+    certificate: Option<CertificateSummary>,
+    items: Vec<PendingBatchItem>,
+    chain: Vec<CertificateSummary>,
+    /// How long the popup may offer to keep the token unlocked; zero if
+    /// signing sessions are disabled.
+    session_minutes: u32,
+    pin_pad: bool,
+}
+
+/// What the PIN popup shows about the request it was opened for.
+#[derive(Serialize)]
+struct PendingRequest {
+    origin: Option<String>,
+    document_name: Option<String>,
+    doc_hash: String,
+    hash_algorithm: String,
+    certificate: Option<CertificateSummary>,
+    preview: Option<DocumentPreview>,
+    /// How long the popup may offer to keep the token unlocked; zero if
+    /// signing sessions are disabled.
+    session_minutes: u32,
+    /// The PIN is entered on the reader instead of in the popup.
+    pin_pad: bool,
+    /// The validated certificate chain, signing certificate first.
+    chain: Vec<CertificateSummary>,
 }
 
 #[derive(Deserialize)]
@@ -45,6 +157,147 @@ struct SignDocumentRequest {
     hash: String,
     timestamp: String,
     signed_certificate: String,
+    envelope: Option<SigningEnvelope>,
+    /// Set when the agent hashed the document itself.
+    #[serde(skip)]
+    document: Option<SubmittedDocument>,
+    /// Set when the token signs something other than `hash`, such as CMS
+    /// signed attributes over it.
+    #[serde(skip)]
+    token_input: Option<TokenInput>,
+}
+
+/// How the token pads RSA signatures. ECDSA keys ignore it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum RsaPadding {
+    #[default]
+    Pkcs1v15,
+    Pss,
+    /// PKCS#1 v1.5 over a `DigestInfo` the caller has already built.
+    Pkcs1v15Raw,
+}
+
+/// The bytes the token signs for a request and how it signs them.
+#[derive(Debug, Clone)]
+struct TokenInput {
+    data: Vec<u8>,
+    padding: RsaPadding,
+}
+
+impl TokenInput {
+    fn new(data: Vec<u8>) -> Self {
+        TokenInput {
+            data,
+            padding: RsaPadding::default(),
+        }
+    }
+}
+
+/// A document submitted to `/sign-content` or `/sign-local-file`.
+struct SubmittedDocument {
+    name: Option<String>,
+    preview: Option<DocumentPreview>,
+}
+
+impl SignDocumentRequest {
+    fn document_name(&self) -> Option<String> {
+        match (&self.envelope, &self.document) {
+            (Some(envelope), _) => Some(envelope.document_name.clone()),
+            (None, Some(document)) => document.name.clone(),
+            (None, None) => None,
+        }
+    }
+
+    fn token_input(&self) -> TokenInput {
+        self.token_input
+            .clone()
+            .unwrap_or_else(|| TokenInput::new(self.hash.as_bytes().to_vec()))
+    }
+
+    fn hash_algorithm(&self) -> String {
+        match (&self.envelope, &self.document) {
+            (Some(envelope), _) => envelope.hash_algorithm.clone(),
+            (None, Some(_)) => DEFAULT_HASH_ALGORITHM.to_string(),
+            (None, None) => "unspecified".to_string(),
+        }
+    }
+}
+
+/// A `/sign-document` request without `hash`, which the agent computes from
+/// the document it is given.
+#[derive(Deserialize)]
+struct SignContentRequest {
+    cert_hash: String,
+    timestamp: String,
+    signed_certificate: String,
+    envelope: Option<SigningEnvelope>,
+}
+
+impl SignContentRequest {
+    fn hash_document(
+        self,
+        name: Option<String>,
+        content: &[u8],
+    ) -> Result<SignDocumentRequest, AgentError> {
+        let algorithm = self
+            .envelope
+            .as_ref()
+            .map_or(DEFAULT_HASH_ALGORITHM, |envelope| &envelope.hash_algorithm);
+        let hash = document::digest_hex(algorithm, content)
+            .map_err(|e| AgentError::InvalidRequest(e.to_string()))?;
+        Ok(SignDocumentRequest {
+            cert_hash: self.cert_hash,
+            hash,
+            timestamp: self.timestamp,
+            signed_certificate: self.signed_certificate,
+            envelope: self.envelope,
+            document: Some(SubmittedDocument {
+                name,
+                preview: DocumentPreview::for_content(content),
+            }),
+            token_input: None,
+        })
+    }
+}
+
+/// A `/sign-batch` request: several documents signed with one certificate
+/// after a single PIN entry. Each item is authorized like a
+/// `/sign-document` request.
+#[derive(Deserialize)]
+struct SignBatchRequest {
+    cert_hash: String,
+    items: Vec<SignBatchItem>,
+}
+
+#[derive(Deserialize)]
+struct SignBatchItem {
+    hash: String,
+    timestamp: String,
+    signed_certificate: String,
+    envelope: Option<SigningEnvelope>,
+}
+
+impl SignBatchItem {
+    fn into_request(self, cert_hash: &str) -> SignDocumentRequest {
+        SignDocumentRequest {
+            cert_hash: cert_hash.to_string(),
+            hash: self.hash,
+            timestamp: self.timestamp,
+            signed_certificate: self.signed_certificate,
+            envelope: self.envelope,
+            document: None,
+            token_input: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BatchItemResult {
+    index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<AgentError>,
 }
 
 #[derive(Debug)]
@@ -61,7 +314,7 @@ pub struct CertifiedKey {
 
 #[derive(Debug)]
 struct CertificateRequest {
-    response_tx: oneshot::Sender<Result<String, String>>,
+    response_tx: oneshot::Sender<Result<String, AgentError>>,
 }
 
 #[derive(Debug)]
@@ -69,10 +322,76 @@ struct CertificateState {
     current_request: Mutex<Option<CertificateRequest>>,
 }
 
+/// How long a popup waits for the user before the HTTP request fails with
+/// `AgentError::Timeout`.
+const POPUP_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Waits for a popup to report back, giving up after `POPUP_TIMEOUT`.
+async fn await_popup<T>(rx: oneshot::Receiver<Result<T, AgentError>>) -> Result<T, AgentError> {
+    match tokio::time::timeout(POPUP_TIMEOUT, rx).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(_)) => Err(AgentError::Internal(
+            "Popup closed without reporting a result".into(),
+        )),
+        Err(_) => Err(AgentError::Timeout),
+    }
+}
+
+/// Closes a popup whose request timed out.
+fn close_popup(app: &AppHandle, label: &str) {
+    if let Some(window) = app.get_webview_window(label) {
+        let _ = window.close();
+    }
+}
+
+/// Locks the slot holding a popup's pending request.
+fn lock_pending<T>(slot: &Mutex<Option<T>>) -> Result<MutexGuard<'_, Option<T>>, AgentError> {
+    slot.lock()
+        .map_err(|_| AgentError::Internal("Pending request lock poisoned".into()))
+}
+
+/// Fails the request a popup was opened for when the user closes the popup
+/// instead of completing it.
+fn cancel_popup_request(window: &tauri::Window) {
+    match window.label() {
+        "sign_popup" => {
+            let state = window.state::<Arc<SigningState>>();
+            let pending = lock_pending(&state.current_request)
+                .ok()
+                .and_then(|mut slot| slot.take());
+            if let Some(req) = pending {
+                let _ = req.response_tx.send(Err(AgentError::UserCancelled));
+            }
+        }
+        "batch_popup" => {
+            let state = window.state::<Arc<SigningState>>();
+            let pending = lock_pending(&state.current_batch)
+                .ok()
+                .and_then(|mut slot| slot.take());
+            if let Some(req) = pending {
+                let _ = req.response_tx.send(Err(AgentError::UserCancelled));
+            }
+        }
+        "cert_popup" => {
+            let state = window.state::<Arc<CertificateState>>();
+            let pending = lock_pending(&state.current_request)
+                .ok()
+                .and_then(|mut slot| slot.take());
+            if let Some(req) = pending {
+                let _ = req.response_tx.send(Err(AgentError::UserCancelled));
+            }
+        }
+        _ => {}
+    }
+}
+
 #[derive(Serialize)]
 pub struct CertificateInfo {
     id: String,
     label: String,
+    /// Filled in where the caller asked for revocation checking.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revocation: Option<RevocationStatus>,
 }
 
 pub fn list_certificates(pkcs11: &Pkcs11) -> Result<Vec<CertificateInfo>, Box<dyn Error>> {
@@ -99,7 +418,8 @@ pub fn list_certificates(pkcs11: &Pkcs11) -> Result<Vec<CertificateInfo>, Box<dy
                 let label_str = label.unwrap_or_else(|| b"Unknown Certificate".to_vec());
                 cert_list.push(CertificateInfo {
                     id: id_hex,
-                    label: String::from_utf8(label_str).unwrap(),
+                    label: String::from_utf8_lossy(&label_str).into_owned(),
+                    revocation: None,
                 });
             }
         }
@@ -120,7 +440,7 @@ pub fn extract_certificate_by_id(
     ];
     let cert_objs = session.find_objects(&search_template)?;
     if cert_objs.is_empty() {
-        return Err("Certificate not found on the token".into());
+        return Err(AgentError::CertificateNotFound.into());
     }
     let cert_handle = cert_objs[0];
     let attrs = session.get_attributes(cert_handle, &[AttributeType::Value])?;
@@ -132,29 +452,60 @@ pub fn extract_certificate_by_id(
     Err("Certificate object found but CKA_VALUE attribute is missing".into())
 }
 
+/// Looks for a certificate by its `CKA_ID` on every connected token.
+pub fn find_certificate_by_id(
+    pkcs11: &Pkcs11,
+    cert_id: &[u8],
+) -> Result<(Slot, Vec<u8>), Box<dyn Error>> {
+    for slot in pkcs11.get_slots_with_token()? {
+        if let Ok(cert) = extract_certificate_by_id(pkcs11, slot, cert_id) {
+            return Ok((slot, cert));
+        }
+    }
+    Err(AgentError::CertificateNotFound.into())
+}
+
+/// Lists the token certificates with whatever revocation status is cached
+/// for them. Revocation is only checked online when a certificate is used.
+fn list_certificates_with_status(app: &AppHandle) -> Result<Vec<CertificateInfo>, Box<dyn Error>> {
+    let pkcs11 = get_pkcs_11(app.clone())?;
+    let mut certs = list_certificates(&pkcs11)?;
+    let pool = certificate_pool(app)?;
+    let checker = revocation_checker(app)?;
+    let now = chrono::Utc::now();
+    for cert in &mut certs {
+        let Ok((_, cert_der)) = find_certificate_by_id(&pkcs11, &hex::decode(&cert.id)?) else {
+            continue;
+        };
+        let Ok(validation) = chain::validate(&cert_der, &pool, now) else {
+            continue;
+        };
+        if let [leaf, issuer, ..] = validation.certificates.as_slice() {
+            cert.revocation = checker.cached(leaf, issuer, now);
+        }
+    }
+    Ok(certs)
+}
+
 #[get("/certificate")]
 async fn get_certificate_route(
     cert_state: web::Data<Arc<CertificateState>>,
     app_handle: web::Data<AppHandle>,
-) -> impl Responder {
+) -> Result<HttpResponse, AgentError> {
     let (tx, rx) = oneshot::channel();
-    {
-        let mut lock = cert_state.current_request.lock().unwrap();
-        *lock = Some(CertificateRequest { response_tx: tx });
-    }
+    *lock_pending(&cert_state.current_request)? = Some(CertificateRequest { response_tx: tx });
 
-    // Create the PKCS#11 instance and list available certificates.
-    let pkcs11 = match get_pkcs_11(app_handle.get_ref().clone()) {
-        Ok(pkcs11) => pkcs11,
-        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
-    };
-    let certs = match list_certificates(&pkcs11) {
-        Ok(certs) => certs,
-        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
-    };
+    let app = app_handle.get_ref().clone();
+    let certs = web::block(move || {
+        list_certificates_with_status(&app).map_err(|e| AgentError::from_boxed(e).to_string())
+    })
+    .await
+    .map_err(|e| AgentError::Internal(e.to_string()))?
+    .map_err(AgentError::Internal)?;
 
     // Serialize the certificates list as JSON and URL-encode it.
-    let certs_json = serde_json::to_string(&certs).unwrap();
+    let certs_json =
+        serde_json::to_string(&certs).map_err(|e| AgentError::Internal(e.to_string()))?;
     let certs_param = urlencoding::encode(&certs_json);
     let url_with_params = format!("cert_pin.html?certs={}", certs_param);
 
@@ -167,165 +518,2637 @@ async fn get_certificate_route(
     .title("Select Certificate")
     .build();
 
-    match rx.await {
-        Ok(result) => match result {
-            Ok(cert_str) => {
-                let cert: serde_json::Value =
-                    serde_json::from_str(&cert_str).unwrap_or_else(|_| {
-                        serde_json::json!( {
-                            "derHex": cert_str,
-                            "name": "Unknown Certificate"
-                        })
-                    });
-                HttpResponse::Ok().json(serde_json::json!({ "certificate": cert }))
-            }
-            Err(err_msg) => HttpResponse::BadRequest().body(err_msg),
-        },
-        Err(_) => HttpResponse::InternalServerError().body("Failed to receive certificate result"),
-    }
+    let result = await_popup(rx).await;
+    if let Err(AgentError::Timeout) = result {
+        lock_pending(&cert_state.current_request)?.take();
+        close_popup(raw_app_handle, "cert_popup");
+    }
+
+    let cert_str = result?;
+    let cert: serde_json::Value = serde_json::from_str(&cert_str).unwrap_or_else(|_| {
+        serde_json::json!( {
+            "derHex": cert_str,
+            "name": "Unknown Certificate"
+        })
+    });
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "certificate": cert })))
+}
+
+#[get("/list-certificates")]
+async fn list_certificates_route(
+    app_handle: web::Data<AppHandle>,
+) -> Result<HttpResponse, AgentError> {
+    let app = app_handle.get_ref().clone();
+    let certs = web::block(move || {
+        list_certificates_with_status(&app).map_err(|e| AgentError::from_boxed(e).to_string())
+    })
+    .await
+    .map_err(|e| AgentError::Internal(e.to_string()))?
+    .map_err(AgentError::Internal)?;
+
+    // Return the certificates directly to the frontend
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "certificates": certs })))
+}
+
+#[tauri::command]
+fn complete_certificate(
+    window: tauri::Window,
+    cert_id: String,
+    cert_state: tauri::State<Arc<CertificateState>>,
+) -> Result<(), String> {
+    let app_handle = window.app_handle();
+    let pkcs11 =
+        get_pkcs_11(app_handle.clone()).map_err(|e| AgentError::from_boxed(e).to_string())?;
+    let cert_id_bytes = hex::decode(&cert_id).map_err(|e| e.to_string())?;
+
+    let (_, cert_bytes) = find_certificate_by_id(&pkcs11, &cert_id_bytes)
+        .map_err(|e| AgentError::from_boxed(e).to_string())?;
+    let label = list_certificates(&pkcs11)
+        .ok()
+        .and_then(|cert_list| cert_list.into_iter().find(|ci| ci.id == cert_id))
+        .map(|cert_info| cert_info.label)
+        .unwrap_or_else(|| "Unknown Certificate".into());
+
+    let cert_object = serde_json::json!({
+         "derHex": hex::encode(cert_bytes),
+         "name": label
+    });
+
+    let req = lock_pending(&cert_state.current_request)
+        .map_err(|e| e.to_string())?
+        .take()
+        .ok_or("No certificate request pending")?;
+    req.response_tx
+        .send(Ok(cert_object.to_string()))
+        .map_err(|_| "Failed to send certificate extraction result".to_string())?;
+    window
+        .close()
+        .map_err(|err| format!("Failed to close window: {}", err))?;
+    Ok(())
+}
+
+/// Returns the company keyring accepted through `/keyring`, falling back
+/// to the key bundled with the app when none was accepted or the stored
+/// one cannot be read.
+fn company_keyring(app: AppHandle) -> Result<Keyring, Box<dyn Error>> {
+    match Keyring::load(&app_data_dir(&app)?) {
+        Ok(Some(keyring)) => return Ok(keyring),
+        Ok(None) => {}
+        Err(e) => log::error!("Ignoring unreadable company keyring: {}", e),
+    }
+    Keyring::bundled(&get_public_key_str(app)?)
+}
+
+fn verify_company_signature(
+    app: AppHandle,
+    message: &[u8],
+    signature: &str,
+) -> Result<(), Box<dyn Error>> {
+    let signature = keyring::decode_signature(signature)?;
+    company_keyring(app)?.verify(message, &signature, chrono::Utc::now())
+}
+
+/// Checks the platform's authorization for a `/sign-document` request,
+/// either through a `SigningEnvelope` or, when enabled, the legacy
+/// `{cert_hash}_{timestamp}` message.
+fn authorize_sign_request(
+    app: AppHandle,
+    state: &SigningState,
+    request: &SignDocumentRequest,
+    origin: Option<&str>,
+) -> Result<(), AgentError> {
+    verify_sign_request(app, request, origin)?;
+    claim_envelope_nonce(state, request.envelope.as_ref())
+}
+
+/// `authorize_sign_request` without claiming the envelope nonce, for
+/// requests that still need the user's approval before they are used up.
+fn verify_sign_request(
+    app: AppHandle,
+    request: &SignDocumentRequest,
+    origin: Option<&str>,
+) -> Result<(), AgentError> {
+    match &request.envelope {
+        Some(envelope) => {
+            let cert_der = hex::decode(&request.cert_hash)
+                .map_err(|e| AgentError::InvalidRequest(e.to_string()))?;
+            envelope
+                .check_request(&cert_der, &request.hash, origin, chrono::Utc::now())
+                .map_err(|e| AgentError::UnauthorizedRequest(e.to_string()))?;
+            verify_envelope_signature(app, envelope, &request.signed_certificate)
+        }
+        None => verify_legacy_authorization(
+            app,
+            &request.cert_hash,
+            &request.timestamp,
+            &request.signed_certificate,
+        ),
+    }
+}
+
+/// Marks an envelope nonce as used, failing if it already was.
+fn claim_envelope_nonce(
+    state: &SigningState,
+    envelope: Option<&SigningEnvelope>,
+) -> Result<(), AgentError> {
+    match envelope {
+        Some(envelope) => state
+            .used_nonces
+            .claim(&envelope.nonce, chrono::Utc::now())
+            .map_err(|e| AgentError::UnauthorizedRequest(e.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// Checks the part of a `/sign-local-file` authorization that does not
+/// depend on the document, so that an unauthorized page cannot open the
+/// file dialog. `authorize_sign_request` runs in full once the file is read.
+fn preauthorize_content_request(
+    app: AppHandle,
+    request: &SignContentRequest,
+    origin: Option<&str>,
+) -> Result<(), AgentError> {
+    match &request.envelope {
+        Some(envelope) => {
+            let cert_der = hex::decode(&request.cert_hash)
+                .map_err(|e| AgentError::InvalidRequest(e.to_string()))?;
+            envelope
+                .check_context(&cert_der, origin, chrono::Utc::now())
+                .map_err(|e| AgentError::UnauthorizedRequest(e.to_string()))?;
+            verify_envelope_signature(app, envelope, &request.signed_certificate)
+        }
+        None => verify_legacy_authorization(
+            app,
+            &request.cert_hash,
+            &request.timestamp,
+            &request.signed_certificate,
+        ),
+    }
+}
+
+fn verify_envelope_signature(
+    app: AppHandle,
+    envelope: &SigningEnvelope,
+    signature: &str,
+) -> Result<(), AgentError> {
+    let message = envelope
+        .canonical_bytes()
+        .map_err(|e| AgentError::Internal(e.to_string()))?;
+    verify_company_signature(app, &message, signature)
+        .map_err(|e| AgentError::UnauthorizedRequest(e.to_string()))
+}
+
+fn verify_legacy_authorization(
+    app: AppHandle,
+    cert_hash: &str,
+    timestamp: &str,
+    signature: &str,
+) -> Result<(), AgentError> {
+    let settings = app_data_dir(&app)
+        .and_then(|data_dir| settings::load(&data_dir))
+        .map_err(|e| AgentError::Internal(e.to_string()))?;
+    if !settings.accept_legacy_requests {
+        return Err(AgentError::UnauthorizedRequest(
+            "Signing requests without an envelope are disabled".into(),
+        ));
+    }
+    let message = format!("{}_{}", cert_hash, timestamp);
+    verify_company_signature(app, message.as_bytes(), signature)
+        .map_err(|e| AgentError::UnauthorizedRequest(e.to_string()))
+}
+
+/// Bundled directory of trusted root certificates.
+const TRUST_STORE_DIR: &str = "trust-store";
+
+/// Directory in the app data directory where intermediates seen on tokens
+/// are kept for later chain building.
+const INTERMEDIATES_DIR: &str = "intermediates";
+
+/// Every certificate stored on the connected tokens.
+fn token_certificates(pkcs11: &Pkcs11) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+    let mut certificates = Vec::new();
+    for slot in pkcs11.get_slots_with_token()? {
+        let session = pkcs11.open_ro_session(slot)?;
+        let cert_objs = session.find_objects(&[Attribute::Class(ObjectClass::CERTIFICATE)])?;
+        for cert_handle in cert_objs {
+            for attr in session.get_attributes(cert_handle, &[AttributeType::Value])? {
+                if let Attribute::Value(cert) = attr {
+                    certificates.push(cert);
+                }
+            }
+        }
+    }
+    Ok(certificates)
+}
+
+/// Directory in the app data directory where OCSP responses and CRLs are
+/// kept for offline use.
+const REVOCATION_CACHE_DIR: &str = "revocation";
+
+/// The bundled trust store, the CA certificates on the token and the
+/// intermediates cache.
+fn certificate_pool(app: &AppHandle) -> Result<CertificatePool, Box<dyn Error>> {
+    let mut pool = CertificatePool::default();
+    pool.load_dir(&app.path().resource_dir()?.join(TRUST_STORE_DIR), true)?;
+    let data_dir = app_data_dir(app)?;
+    if let Some(dir) = settings::load(&data_dir)?.trust_anchor_dir {
+        pool.load_dir(Path::new(&dir), true)?;
+    }
+    pool.load_dir(&data_dir.join(INTERMEDIATES_DIR), false)?;
+    let pkcs11 = get_pkcs_11(app.clone())?;
+    for cert in token_certificates(&pkcs11)? {
+        if let Ok(cert) = x509_cert::Certificate::from_der(&cert) {
+            pool.add_intermediate(cert);
+        }
+    }
+    Ok(pool)
+}
+
+/// Directory in the app data directory for roots trusted only to issue
+/// time-stamping certificates.
+const TSA_ANCHORS_DIR: &str = "tsa-anchors";
+
+/// A client for the TSA at `url` that accepts tokens chaining to the trust
+/// store or the TSA anchors.
+fn timestamp_client(app: &AppHandle, url: &str) -> Result<TimestampClient, Box<dyn Error>> {
+    let mut trust = certificate_pool(app)?;
+    trust.load_dir(&app_data_dir(app)?.join(TSA_ANCHORS_DIR), true)?;
+    Ok(TimestampClient::new(url, trust))
+}
+
+fn revocation_checker(app: &AppHandle) -> Result<RevocationChecker, Box<dyn Error>> {
+    let data_dir = app_data_dir(app)?;
+    let settings = settings::load(&data_dir)?;
+    Ok(RevocationChecker::new(
+        data_dir.join(REVOCATION_CACHE_DIR),
+        settings.ocsp_url,
+        settings.crl_url,
+    ))
+}
+
+/// Builds and checks the chain of `cert_der` from `certificate_pool`, then
+/// checks whether the certificate was revoked.
+fn validate_certificate_chain(
+    app: &AppHandle,
+    cert_der: &[u8],
+) -> Result<ChainValidation, Box<dyn Error>> {
+    let pool = certificate_pool(app)?;
+    let now = chrono::Utc::now();
+    let mut validation = chain::validate(cert_der, &pool, now)?;
+    if let [cert, issuer, ..] = validation.certificates.as_slice() {
+        validation.revocation = Some(revocation_checker(app)?.check(cert, issuer, now));
+    }
+    if validation.is_valid() {
+        let cache_dir = app_data_dir(app)?.join(INTERMEDIATES_DIR);
+        if let Err(e) = chain::cache_intermediates(&cache_dir, &validation.certificates) {
+            log::warn!("Failed to cache intermediate certificates: {}", e);
+        }
+    }
+    Ok(validation)
+}
+
+/// Validates the hex-encoded certificate a request wants to sign with,
+/// failing with the validation errors if it should not be used.
+fn require_valid_certificate(
+    app: &AppHandle,
+    cert_hash: &str,
+) -> Result<ChainValidation, AgentError> {
+    let cert_der = hex::decode(cert_hash).map_err(|e| AgentError::InvalidRequest(e.to_string()))?;
+    let validation = validate_certificate_chain(app, &cert_der).map_err(AgentError::from_boxed)?;
+    if !validation.is_valid() {
+        return Err(AgentError::CertificateInvalid(validation.errors.join("; ")));
+    }
+    match &validation.revocation {
+        Some(RevocationStatus::Revoked { revoked_at, .. }) => {
+            return Err(AgentError::CertificateInvalid(format!(
+                "The certificate was revoked at {}",
+                revoked_at
+            )));
+        }
+        Some(RevocationStatus::Unknown { reason }) => {
+            let settings = app_data_dir(app)
+                .and_then(|data_dir| settings::load(&data_dir))
+                .map_err(|e| AgentError::Internal(e.to_string()))?;
+            if settings.require_revocation_status {
+                return Err(AgentError::CertificateInvalid(format!(
+                    "The revocation status could not be checked: {}",
+                    reason
+                )));
+            }
+        }
+        _ => {}
+    }
+    Ok(validation)
+}
+
+/// `require_valid_certificate` off the async runtime, since revocation
+/// checking blocks on the network.
+async fn check_signing_certificate(
+    app: &AppHandle,
+    cert_hash: &str,
+) -> Result<ChainValidation, AgentError> {
+    let (app, cert_hash) = (app.clone(), cert_hash.to_string());
+    tauri::async_runtime::spawn_blocking(move || require_valid_certificate(&app, &cert_hash))
+        .await
+        .map_err(|e| AgentError::Internal(e.to_string()))?
+}
+
+#[derive(Deserialize)]
+struct ValidateCertificateRequest {
+    cert_hash: String,
+}
+
+#[post("/validate-certificate")]
+async fn validate_certificate_route(
+    req_body: web::Json<ValidateCertificateRequest>,
+    app_handle: web::Data<AppHandle>,
+) -> Result<HttpResponse, AgentError> {
+    let cert_der =
+        hex::decode(&req_body.cert_hash).map_err(|e| AgentError::InvalidRequest(e.to_string()))?;
+    let app = app_handle.get_ref().clone();
+    let validation =
+        web::block(move || validate_certificate_chain(&app, &cert_der).map_err(|e| e.to_string()))
+            .await
+            .map_err(|e| AgentError::Internal(e.to_string()))?
+            .map_err(AgentError::InvalidRequest)?;
+    Ok(HttpResponse::Ok().json(validation))
+}
+
+pub fn app_data_dir(app: &AppHandle) -> Result<PathBuf, Box<dyn Error>> {
+    Ok(app.path().app_data_dir()?)
+}
+
+#[tauri::command]
+fn get_settings(app: AppHandle) -> Result<settings::Settings, String> {
+    let data_dir = app_data_dir(&app).map_err(|e| e.to_string())?;
+    settings::load(&data_dir).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn update_settings(app: AppHandle, settings: settings::Settings) -> Result<(), String> {
+    let data_dir = app_data_dir(&app).map_err(|e| e.to_string())?;
+    settings::save(&data_dir, &settings).map_err(|e| e.to_string())
+}
+
+#[derive(Deserialize)]
+struct KeyringUpdateRequest {
+    keyring: String,
+    signature: String,
+}
+
+#[post("/keyring")]
+async fn update_keyring_route(
+    req_body: web::Json<KeyringUpdateRequest>,
+    app_handle: web::Data<AppHandle>,
+) -> Result<HttpResponse, AgentError> {
+    let app = app_handle.get_ref().clone();
+    let keyring = app_data_dir(&app)
+        .and_then(|data_dir| {
+            company_keyring(app)?.apply_update(
+                &data_dir,
+                &req_body.keyring,
+                &req_body.signature,
+                chrono::Utc::now(),
+            )
+        })
+        .map_err(|e| AgentError::UnauthorizedRequest(format!("Keyring update rejected: {}", e)))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "serial": keyring.serial() })))
+}
+
+fn record_sign_request(
+    audit_log: &AuditLog,
+    request: &SignDocumentRequest,
+    origin: Option<&str>,
+    outcome: AuditOutcome,
+    error: Option<String>,
+) {
+    let cert_fingerprint = hex::decode(&request.cert_hash)
+        .ok()
+        .map(|cert_der| hex::encode(Sha256::digest(cert_der)));
+    let record = AuditRecord {
+        origin: origin.map(str::to_string),
+        cert_fingerprint,
+        doc_hash: request.hash.clone(),
+        algorithm: request.hash_algorithm(),
+        outcome,
+        error,
+    };
+    write_audit(audit_log, record);
+}
+
+/// Appends to the audit log. Failures are only logged, so that a full disk
+/// does not stop the user from signing.
+fn write_audit(audit_log: &AuditLog, record: AuditRecord) {
+    if let Err(e) = audit_log.append(record) {
+        log::warn!("Failed to write audit log entry: {}", e);
+    }
+}
+
+/// Records a token signature made from the agent's own window, which has
+/// no requesting origin.
+fn record_local_signature<T>(
+    app: &AppHandle,
+    cert_der: &[u8],
+    doc_hash: &[u8],
+    result: &Result<T, Box<dyn Error>>,
+) {
+    let (outcome, error) = match result {
+        Ok(_) => (AuditOutcome::Signed, None),
+        Err(e) => (AuditOutcome::Failed, Some(e.to_string())),
+    };
+    let record = AuditRecord {
+        origin: None,
+        cert_fingerprint: Some(hex::encode(Sha256::digest(cert_der))),
+        doc_hash: hex::encode(doc_hash),
+        algorithm: DEFAULT_HASH_ALGORITHM.to_string(),
+        outcome,
+        error,
+    };
+    write_audit(&app.state::<Arc<AuditLog>>(), record);
+}
+
+/// Records a signature in the history. Failures are only logged, since the
+/// signature has already been produced.
+fn remember_signature(history: &SignatureHistory, entry: NewHistoryEntry) {
+    if let Err(e) = history.insert(entry) {
+        log::warn!("Failed to save signature to history: {}", e);
+    }
+}
+
+fn request_history_entry(
+    request: &SignDocumentRequest,
+    origin: Option<&str>,
+    signature: &str,
+) -> NewHistoryEntry {
+    let cert_der = hex::decode(&request.cert_hash).unwrap_or_default();
+    let mut entry = NewHistoryEntry::new(
+        request.document_name(),
+        &cert_der,
+        request.hash.clone(),
+        request.hash_algorithm(),
+        signature.to_string(),
+    );
+    entry.origin = origin.map(str::to_string);
+    entry
+}
+
+/// Final component of `path`, as recorded in the history.
+fn file_name(path: &Path) -> Option<String> {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+}
+
+fn request_origin(http_req: &HttpRequest) -> Option<&str> {
+    http_req
+        .headers()
+        .get("Origin")
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Authorizes a signing request, asks the user for their PIN and records
+/// the outcome. Returns the hex-encoded signature.
+async fn process_sign_request(
+    app: &AppHandle,
+    origin: Option<&str>,
+    data: &SigningState,
+    audit_log: &AuditLog,
+    history: &SignatureHistory,
+    sessions: &SessionManager,
+    request: SignDocumentRequest,
+) -> Result<String, AgentError> {
+    let authorized = authorize_signing(app, origin, data, audit_log, request).await?;
+    sign_authorized_request(app, origin, data, audit_log, history, sessions, authorized).await
+}
+
+/// A signing request that passed authorization, with the validated chain
+/// of its certificate.
+struct AuthorizedSignRequest {
+    request: SignDocumentRequest,
+    validation: ChainValidation,
+}
+
+impl AuthorizedSignRequest {
+    /// The DER certificates of the validated chain, signer first.
+    fn chain_der(&self) -> Result<Vec<Vec<u8>>, AgentError> {
+        self.validation
+            .certificates
+            .iter()
+            .map(|cert| cert.to_der())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| AgentError::Internal(e.to_string()))
+    }
+}
+
+/// Authorizes a signing request and then validates its certificate,
+/// auditing a rejection. Unauthorized callers never get as far as the
+/// revocation lookup.
+async fn authorize_signing(
+    app: &AppHandle,
+    origin: Option<&str>,
+    data: &SigningState,
+    audit_log: &AuditLog,
+    request: SignDocumentRequest,
+) -> Result<AuthorizedSignRequest, AgentError> {
+    if let Err(e) = authorize_sign_request(app.clone(), data, &request, origin) {
+        record_sign_request(
+            audit_log,
+            &request,
+            origin,
+            AuditOutcome::Rejected,
+            Some(e.to_string()),
+        );
+        return Err(e);
+    }
+
+    let validation = match check_signing_certificate(app, &request.cert_hash).await {
+        Ok(validation) => validation,
+        Err(e) => {
+            record_sign_request(
+                audit_log,
+                &request,
+                origin,
+                AuditOutcome::Rejected,
+                Some(e.to_string()),
+            );
+            return Err(e);
+        }
+    };
+    Ok(AuthorizedSignRequest {
+        request,
+        validation,
+    })
+}
+
+/// Signs an authorized request in the open signing session, or asks the
+/// user for their PIN, and records the outcome. Returns the hex-encoded
+/// signature.
+async fn sign_authorized_request(
+    app: &AppHandle,
+    origin: Option<&str>,
+    data: &SigningState,
+    audit_log: &AuditLog,
+    history: &SignatureHistory,
+    sessions: &SessionManager,
+    authorized: AuthorizedSignRequest,
+) -> Result<String, AgentError> {
+    let AuthorizedSignRequest {
+        mut request,
+        validation,
+    } = authorized;
+    let (tx, rx) = oneshot::channel();
+
+    let unlocked = sign_with_session(
+        app,
+        sessions,
+        origin,
+        &request.cert_hash,
+        &request.token_input(),
+    );
+    if let Some(result) = unlocked {
+        match &result {
+            Ok(signature) => {
+                record_sign_request(audit_log, &request, origin, AuditOutcome::Signed, None);
+                remember_signature(history, request_history_entry(&request, origin, signature));
+            }
+            Err(e) => record_sign_request(
+                audit_log,
+                &request,
+                origin,
+                AuditOutcome::Failed,
+                Some(e.to_string()),
+            ),
+        }
+        return result;
+    }
+
+    *lock_pending(&data.current_request)? = Some(SigningRequest {
+        cert_hash: request.cert_hash.clone(),
+        doc_hash: request.hash.clone(),
+        hash_algorithm: request.hash_algorithm(),
+        origin: origin.map(str::to_string),
+        document_name: request.document_name(),
+        preview: request
+            .document
+            .as_mut()
+            .and_then(|document| document.preview.take()),
+        chain: validation.chain,
+        token_input: request.token_input(),
+        response_tx: tx,
+    });
+
+    let _ = tauri::WebviewWindowBuilder::new(
+        app,
+        "sign_popup",
+        tauri::WebviewUrl::App("popup.html".into()),
+    )
+    .title("Sign Document")
+    .build();
+
+    match await_popup(rx).await {
+        Ok(signature) => {
+            record_sign_request(audit_log, &request, origin, AuditOutcome::Signed, None);
+            remember_signature(history, request_history_entry(&request, origin, &signature));
+            Ok(signature)
+        }
+        Err(e) => {
+            if let AgentError::Timeout = e {
+                if let Ok(mut slot) = lock_pending(&data.current_request) {
+                    slot.take();
+                }
+                close_popup(app, "sign_popup");
+            }
+            record_sign_request(
+                audit_log,
+                &request,
+                origin,
+                AuditOutcome::Failed,
+                Some(e.to_string()),
+            );
+            Err(e)
+        }
+    }
+}
+
+#[post("/sign-document")]
+async fn sign_document(
+    http_req: HttpRequest,
+    data: web::Data<Arc<SigningState>>,
+    audit_log: web::Data<Arc<AuditLog>>,
+    history: web::Data<Arc<SignatureHistory>>,
+    sessions: web::Data<Arc<SessionManager>>,
+    req_body: web::Json<SignDocumentRequest>,
+    app_handle: web::Data<AppHandle>,
+) -> Result<HttpResponse, AgentError> {
+    let signature = process_sign_request(
+        &app_handle,
+        request_origin(&http_req),
+        &data,
+        &audit_log,
+        &history,
+        &sessions,
+        req_body.into_inner(),
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "signature": signature })))
+}
+
+/// A request to sign many digests at once through a Merkle tree. The
+/// envelope, if any, must authorize the tree's root as its `doc_hash`.
+#[derive(Deserialize)]
+struct SignMerkleRequest {
+    cert_hash: String,
+    /// Hex-encoded document digests, one per leaf.
+    digests: Vec<String>,
+    timestamp: String,
+    signed_certificate: String,
+    envelope: Option<SigningEnvelope>,
+}
+
+/// Signs the root of a SHA-256 Merkle tree over the submitted digests and
+/// returns an inclusion proof for each of them.
+#[post("/sign-merkle")]
+async fn sign_merkle(
+    http_req: HttpRequest,
+    data: web::Data<Arc<SigningState>>,
+    audit_log: web::Data<Arc<AuditLog>>,
+    history: web::Data<Arc<SignatureHistory>>,
+    sessions: web::Data<Arc<SessionManager>>,
+    req_body: web::Json<SignMerkleRequest>,
+    app_handle: web::Data<AppHandle>,
+) -> Result<HttpResponse, AgentError> {
+    let batch = req_body.into_inner();
+    let digests = batch
+        .digests
+        .iter()
+        .map(hex::decode)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| AgentError::InvalidRequest(e.to_string()))?;
+    let tree =
+        MerkleTree::build(&digests).map_err(|e| AgentError::InvalidRequest(e.to_string()))?;
+    let root = hex::encode(tree.root());
+    // The token signs the 32 root bytes, not their hex encoding.
+    let cert_der =
+        hex::decode(&batch.cert_hash).map_err(|e| AgentError::InvalidRequest(e.to_string()))?;
+    let key_algorithm = CmsSigner::new(&cert_der)
+        .map_err(AgentError::from_boxed)?
+        .key_algorithm();
+
+    let request = SignDocumentRequest {
+        cert_hash: batch.cert_hash,
+        hash: root.clone(),
+        timestamp: batch.timestamp,
+        signed_certificate: batch.signed_certificate,
+        envelope: batch.envelope,
+        document: Some(SubmittedDocument {
+            name: Some(format!("Batch of {} documents", digests.len())),
+            preview: None,
+        }),
+        token_input: Some(TokenInput::new(key_algorithm.token_input(&tree.root()))),
+    };
+    let signature = process_sign_request(
+        &app_handle,
+        request_origin(&http_req),
+        &data,
+        &audit_log,
+        &history,
+        &sessions,
+        request,
+    )
+    .await?;
+
+    let leaves: Vec<LeafProof> = batch
+        .digests
+        .into_iter()
+        .enumerate()
+        .map(|(index, digest)| LeafProof {
+            index,
+            digest,
+            proof: tree.proof(index),
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "root": root,
+        "signature": signature,
+        "leaves": leaves,
+    })))
+}
+
+/// What `/pdf/complete` needs to rebuild the signed attributes handed out
+/// by `/pdf/prepare`. PAdES attributes carry no signing time, so the same
+/// inputs always produce the same attributes.
+#[derive(Serialize, Deserialize)]
+struct PdfSigningState {
+    cert_hash: String,
+    byte_range_digest: String,
+}
+
+impl PdfSigningState {
+    fn encode(&self) -> Result<String, AgentError> {
+        let json = serde_json::to_vec(self).map_err(|e| AgentError::Internal(e.to_string()))?;
+        Ok(BASE64_STANDARD.encode(json))
+    }
+
+    fn decode(state: &str) -> Result<Self, AgentError> {
+        let invalid = |e: String| AgentError::InvalidRequest(format!("Invalid state: {}", e));
+        let json = BASE64_STANDARD
+            .decode(state)
+            .map_err(|e| invalid(e.to_string()))?;
+        serde_json::from_slice(&json).map_err(|e| invalid(e.to_string()))
+    }
+
+    /// The signer and the CMS signed attributes over the ByteRange digest.
+    fn signed_attributes(
+        &self,
+    ) -> Result<(CmsSigner, cms::signed_data::SignedAttributes), AgentError> {
+        let digest = hex::decode(&self.byte_range_digest)
+            .map_err(|e| AgentError::InvalidRequest(e.to_string()))?;
+        if digest.len() != Sha256::output_size() {
+            return Err(AgentError::InvalidRequest(
+                "The ByteRange digest must be a SHA-256 digest".into(),
+            ));
+        }
+        let cert_der =
+            hex::decode(&self.cert_hash).map_err(|e| AgentError::InvalidRequest(e.to_string()))?;
+        let signer = CmsSigner::new(&cert_der).map_err(AgentError::from_boxed)?;
+        let attrs = signer
+            .signed_attributes(&digest, None)
+            .map_err(AgentError::from_boxed)?;
+        Ok((signer, attrs))
+    }
+}
+
+#[derive(Deserialize)]
+struct PreparePdfRequest {
+    cert_hash: String,
+    /// Hex-encoded SHA-256 digest of the PDF's ByteRange.
+    byte_range_digest: String,
+}
+
+/// Returns the digest of the CMS signed attributes a PAdES signature over
+/// the given ByteRange digest will carry, and the state `/pdf/complete`
+/// needs to finish it.
+#[post("/pdf/prepare")]
+async fn prepare_pdf_route(
+    req_body: web::Json<PreparePdfRequest>,
+) -> Result<HttpResponse, AgentError> {
+    let request = req_body.into_inner();
+    let state = PdfSigningState {
+        cert_hash: request.cert_hash,
+        byte_range_digest: request.byte_range_digest.to_lowercase(),
+    };
+    let (_, attrs) = state.signed_attributes()?;
+    let tbs = cades::to_be_signed(&attrs).map_err(AgentError::from_boxed)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "signed_attributes_digest": hex::encode(Sha256::digest(tbs)),
+        "state": state.encode()?,
+    })))
+}
+
+/// Finishes a signature started with `/pdf/prepare`. The envelope, if any,
+/// must authorize the ByteRange digest as its `doc_hash`.
+#[derive(Deserialize)]
+struct CompletePdfRequest {
+    state: String,
+    timestamp: String,
+    signed_certificate: String,
+    envelope: Option<SigningEnvelope>,
+    /// Whether to timestamp the signature through the configured TSA.
+    #[serde(default)]
+    signature_timestamp: bool,
+}
+
+/// Signs the attributes from `/pdf/prepare` with the token and returns the
+/// CMS signature to insert into the PDF's /Contents.
+#[post("/pdf/complete")]
+async fn complete_pdf_route(
+    http_req: HttpRequest,
+    data: web::Data<Arc<SigningState>>,
+    audit_log: web::Data<Arc<AuditLog>>,
+    history: web::Data<Arc<SignatureHistory>>,
+    sessions: web::Data<Arc<SessionManager>>,
+    req_body: web::Json<CompletePdfRequest>,
+    app_handle: web::Data<AppHandle>,
+) -> Result<HttpResponse, AgentError> {
+    let completion = req_body.into_inner();
+    let state = PdfSigningState::decode(&completion.state)?;
+    let (signer, attrs) = state.signed_attributes()?;
+    let tsa_url = if completion.signature_timestamp {
+        let dir = app_data_dir(&app_handle).map_err(AgentError::from_boxed)?;
+        let settings = settings::load(&dir).map_err(AgentError::from_boxed)?;
+        Some(settings.tsa_url.ok_or_else(|| {
+            AgentError::TimestampFailed("No time-stamping authority is configured".into())
+        })?)
+    } else {
+        None
+    };
+
+    let tbs = cades::to_be_signed(&attrs).map_err(AgentError::from_boxed)?;
+    let request = SignDocumentRequest {
+        cert_hash: state.cert_hash,
+        hash: state.byte_range_digest,
+        timestamp: completion.timestamp,
+        signed_certificate: completion.signed_certificate,
+        envelope: completion.envelope,
+        document: Some(SubmittedDocument {
+            name: Some("PDF document".to_string()),
+            preview: None,
+        }),
+        token_input: Some(TokenInput::new(signer.key_algorithm().token_input(&tbs))),
+    };
+    let signature = process_sign_request(
+        &app_handle,
+        request_origin(&http_req),
+        &data,
+        &audit_log,
+        &history,
+        &sessions,
+        request,
+    )
+    .await?;
+    let signature = hex::decode(signature).map_err(|e| AgentError::Internal(e.to_string()))?;
+
+    let app = app_handle.get_ref().clone();
+    let cms = web::block(move || {
+        let signature_timestamp = match &tsa_url {
+            Some(url) => Some(timestamp_signature(&app, url, &signer, &signature)?),
+            None => None,
+        };
+        signer
+            .signed_data(attrs, &signature, signature_timestamp.as_deref())
+            .map_err(AgentError::from_boxed)
+    })
+    .await
+    .map_err(|e| AgentError::Internal(e.to_string()))??;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "cms": BASE64_STANDARD.encode(cms) })))
+}
+
+fn document_too_large() -> AgentError {
+    AgentError::InvalidRequest(format!(
+        "Documents larger than {} bytes are not accepted",
+        MAX_DOCUMENT_SIZE
+    ))
+}
+
+/// Signs several documents after a single PIN entry. Items that fail
+/// authorization or signing are reported individually; the rest are still
+/// signed.
+#[post("/sign-batch")]
+async fn sign_batch(
+    http_req: HttpRequest,
+    data: web::Data<Arc<SigningState>>,
+    audit_log: web::Data<Arc<AuditLog>>,
+    history: web::Data<Arc<SignatureHistory>>,
+    sessions: web::Data<Arc<SessionManager>>,
+    req_body: web::Json<SignBatchRequest>,
+    app_handle: web::Data<AppHandle>,
+) -> Result<HttpResponse, AgentError> {
+    let origin = request_origin(&http_req);
+    let batch = req_body.into_inner();
+    if batch.items.is_empty() || batch.items.len() > MAX_BATCH_SIZE {
+        return Err(AgentError::InvalidRequest(format!(
+            "A batch must contain between 1 and {} documents",
+            MAX_BATCH_SIZE
+        )));
+    }
+
+    let requests: Vec<SignDocumentRequest> = batch
+        .items
+        .into_iter()
+        .map(|item| item.into_request(&batch.cert_hash))
+        .collect();
+    let mut results: Vec<Option<Result<String, AgentError>>> =
+        requests.iter().map(|_| None).collect();
+    let validation = check_signing_certificate(&app_handle, &batch.cert_hash).await;
+    let mut authorized = Vec::new();
+    for (index, request) in requests.iter().enumerate() {
+        let authorization = match &validation {
+            Ok(_) => verify_sign_request(app_handle.get_ref().clone(), request, origin),
+            Err(e) => Err(AgentError::CertificateInvalid(
+                e.details().unwrap_or_default().to_string(),
+            )),
+        };
+        match authorization {
+            Ok(()) => authorized.push(index),
+            Err(e) => {
+                record_sign_request(
+                    &audit_log,
+                    request,
+                    origin,
+                    AuditOutcome::Rejected,
+                    Some(e.to_string()),
+                );
+                results[index] = Some(Err(e));
+            }
+        }
+    }
+
+    let unlocked = sessions.covers(
+        origin,
+        &batch.cert_hash,
+        authorized.len(),
+        chrono::Utc::now(),
+    );
+    if unlocked {
+        // The user approved this origin's requests when opening the session.
+        for index in authorized {
+            let request = &requests[index];
+            let result = claim_envelope_nonce(&data, request.envelope.as_ref()).and_then(|()| {
+                sign_with_session(
+                    &app_handle,
+                    &sessions,
+                    origin,
+                    &request.cert_hash,
+                    &request.token_input(),
+                )
+                .unwrap_or_else(|| {
+                    Err(AgentError::Internal("The signing session has ended".into()))
+                })
+            });
+            record_batch_result(&audit_log, &history, request, origin, &result);
+            results[index] = Some(result);
+        }
+    } else if !authorized.is_empty() {
+        let (tx, rx) = oneshot::channel();
+        *lock_pending(&data.current_batch)? = Some(BatchSigningRequest {
+            cert_hash: batch.cert_hash.clone(),
+            origin: origin.map(str::to_string),
+            items: authorized
+                .iter()
+                .map(|&index| PendingBatchItem {
+                    document_name: requests[index].document_name(),
+                    doc_hash: requests[index].hash.clone(),
+                    hash_algorithm: requests[index].hash_algorithm(),
+                    envelope: requests[index].envelope.clone(),
+                })
+                .collect(),
+            chain: validation
+                .map(|validation| validation.chain)
+                .unwrap_or_default(),
+            response_tx: tx,
+        });
+
+        let raw_app_handle = app_handle.get_ref();
+        let _ = tauri::WebviewWindowBuilder::new(
+            raw_app_handle,
+            "batch_popup",
+            tauri::WebviewUrl::App("batch.html".into()),
+        )
+        .title("Sign Documents")
+        .build();
+
+        let signed = match await_popup(rx).await {
+            Ok(signed) => signed,
+            Err(e) => {
+                if let AgentError::Timeout = e {
+                    if let Ok(mut slot) = lock_pending(&data.current_batch) {
+                        slot.take();
+                    }
+                    close_popup(raw_app_handle, "batch_popup");
+                }
+                for &index in &authorized {
+                    record_sign_request(
+                        &audit_log,
+                        &requests[index],
+                        origin,
+                        AuditOutcome::Failed,
+                        Some(e.to_string()),
+                    );
+                }
+                return Err(e);
+            }
+        };
+
+        for (index, result) in authorized.into_iter().zip(signed) {
+            record_batch_result(&audit_log, &history, &requests[index], origin, &result);
+            results[index] = Some(result);
+        }
+    }
+
+    let results: Vec<BatchItemResult> = results
+        .into_iter()
+        .enumerate()
+        .map(|(index, result)| match result {
+            Some(Ok(signature)) => BatchItemResult {
+                index,
+                signature: Some(signature),
+                error: None,
+            },
+            Some(Err(e)) => BatchItemResult {
+                index,
+                signature: None,
+                error: Some(e),
+            },
+            None => BatchItemResult {
+                index,
+                signature: None,
+                error: Some(AgentError::Internal("Document was not signed".into())),
+            },
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "results": results })))
+}
+
+fn record_batch_result(
+    audit_log: &AuditLog,
+    history: &SignatureHistory,
+    request: &SignDocumentRequest,
+    origin: Option<&str>,
+    result: &Result<String, AgentError>,
+) {
+    match result {
+        Ok(signature) => {
+            record_sign_request(audit_log, request, origin, AuditOutcome::Signed, None);
+            remember_signature(history, request_history_entry(request, origin, signature));
+        }
+        Err(e) => record_sign_request(
+            audit_log,
+            request,
+            origin,
+            AuditOutcome::Failed,
+            Some(e.to_string()),
+        ),
+    }
+}
+
+/// Buffers a document body, refusing anything over `MAX_DOCUMENT_SIZE`.
+async fn read_document<S, E>(mut stream: S) -> Result<Vec<u8>, AgentError>
+where
+    S: Stream<Item = Result<web::Bytes, E>> + Unpin,
+    E: std::fmt::Display,
+{
+    let mut content = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| AgentError::InvalidRequest(e.to_string()))?;
+        if content.len() + chunk.len() > MAX_DOCUMENT_SIZE {
+            return Err(document_too_large());
+        }
+        content.extend_from_slice(&chunk);
+    }
+    Ok(content)
+}
+
+/// A file uploaded as a `file` part, with its name if the client gave one.
+type UploadedFile = (Option<String>, Vec<u8>);
+
+/// Reads a multipart body with a `request` JSON part and one or more `file`
+/// parts, which together may not exceed the document size limit.
+async fn read_multipart(
+    mut multipart: Multipart,
+) -> Result<(SignContentRequest, Vec<UploadedFile>), AgentError> {
+    let invalid = |e: String| AgentError::InvalidRequest(e);
+    let mut request = None;
+    let mut files: Vec<UploadedFile> = Vec::new();
+    let mut total_size = 0;
+    while let Some(field) = multipart.next().await {
+        let field = field.map_err(|e| invalid(e.to_string()))?;
+        match field.name() {
+            Some("request") => {
+                let json = read_document(field).await?;
+                request = Some(serde_json::from_slice(&json).map_err(|e| invalid(e.to_string()))?);
+            }
+            Some("file") => {
+                let name = field
+                    .content_disposition()
+                    .and_then(|disposition| disposition.get_filename())
+                    .map(str::to_string);
+                let content = read_document(field).await?;
+                total_size += content.len();
+                if total_size > MAX_DOCUMENT_SIZE {
+                    return Err(document_too_large());
+                }
+                files.push((name, content));
+            }
+            _ => {}
+        }
+    }
+    let request = request.ok_or_else(|| invalid("Missing request part".into()))?;
+    if files.is_empty() {
+        return Err(invalid("Missing file part".into()));
+    }
+    Ok((request, files))
+}
+
+#[derive(Deserialize)]
+struct SignContentQuery {
+    /// JSON-encoded `SignContentRequest`.
+    request: String,
+    name: Option<String>,
+}
+
+/// Signs a document the agent hashes itself. The document is either the
+/// raw request body, with the request in the query string, or the `file`
+/// part of a multipart body.
+#[post("/sign-content")]
+async fn sign_content(
+    http_req: HttpRequest,
+    payload: web::Payload,
+    data: web::Data<Arc<SigningState>>,
+    audit_log: web::Data<Arc<AuditLog>>,
+    history: web::Data<Arc<SignatureHistory>>,
+    sessions: web::Data<Arc<SessionManager>>,
+    app_handle: web::Data<AppHandle>,
+) -> Result<HttpResponse, AgentError> {
+    let is_multipart = http_req
+        .headers()
+        .get("Content-Type")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("multipart/form-data"));
+
+    let (request, name, content) = if is_multipart {
+        let (request, files) = read_multipart(Multipart::new(http_req.headers(), payload)).await?;
+        let [(name, content)] = <[UploadedFile; 1]>::try_from(files)
+            .map_err(|_| AgentError::InvalidRequest("Send exactly one file part to sign".into()))?;
+        (request, name, content)
+    } else {
+        let query = web::Query::<SignContentQuery>::from_query(http_req.query_string())
+            .map_err(|e| AgentError::InvalidRequest(e.to_string()))?
+            .into_inner();
+        let request: SignContentRequest = serde_json::from_str(&query.request)
+            .map_err(|e| AgentError::InvalidRequest(e.to_string()))?;
+        (request, query.name, read_document(payload).await?)
+    };
+
+    let signature = process_sign_request(
+        &app_handle,
+        request_origin(&http_req),
+        &data,
+        &audit_log,
+        &history,
+        &sessions,
+        request.hash_document(name, &content)?,
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "signature": signature })))
+}
+
+/// Signs a document the user picks from their computer in a file dialog.
+#[post("/sign-local-file")]
+async fn sign_local_file(
+    http_req: HttpRequest,
+    data: web::Data<Arc<SigningState>>,
+    audit_log: web::Data<Arc<AuditLog>>,
+    history: web::Data<Arc<SignatureHistory>>,
+    sessions: web::Data<Arc<SessionManager>>,
+    req_body: web::Json<SignContentRequest>,
+    app_handle: web::Data<AppHandle>,
+) -> Result<HttpResponse, AgentError> {
+    let request = req_body.into_inner();
+    let origin = request_origin(&http_req);
+    let app = app_handle.get_ref().clone();
+    if let Err(e) = preauthorize_content_request(app.clone(), &request, origin) {
+        let record = AuditRecord {
+            origin: origin.map(str::to_string),
+            cert_fingerprint: hex::decode(&request.cert_hash)
+                .ok()
+                .map(|cert_der| hex::encode(Sha256::digest(cert_der))),
+            doc_hash: String::new(),
+            algorithm: DEFAULT_HASH_ALGORITHM.to_string(),
+            outcome: AuditOutcome::Rejected,
+            error: Some(e.to_string()),
+        };
+        write_audit(&audit_log, record);
+        return Err(e);
+    }
+    let picked = web::block(move || {
+        app.dialog()
+            .file()
+            .set_title("Choose the document to sign")
+            .blocking_pick_file()
+    })
+    .await
+    .map_err(|e| AgentError::Internal(e.to_string()))?;
+    let path = picked
+        .ok_or(AgentError::UserCancelled)?
+        .into_path()
+        .map_err(|e| AgentError::InvalidRequest(e.to_string()))?;
+
+    let size = tokio::fs::metadata(&path)
+        .await
+        .map_err(|e| AgentError::InvalidRequest(e.to_string()))?
+        .len();
+    if size > MAX_DOCUMENT_SIZE as u64 {
+        return Err(document_too_large());
+    }
+    let content = tokio::fs::read(&path)
+        .await
+        .map_err(|e| AgentError::InvalidRequest(e.to_string()))?;
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned());
+
+    let signature = process_sign_request(
+        &app_handle,
+        origin,
+        &data,
+        &audit_log,
+        &history,
+        &sessions,
+        request.hash_document(name, &content)?,
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "signature": signature })))
+}
+
+/// Packs the `file` parts of a multipart body into an ASiC-E container with
+/// a CAdES signature over its manifest. The envelope, if any, must
+/// authorize the SHA-256 digest of the container's ASiCManifest.xml as its
+/// `doc_hash`.
+#[post("/asic/create")]
+async fn create_asic_route(
+    http_req: HttpRequest,
+    payload: web::Payload,
+    data: web::Data<Arc<SigningState>>,
+    audit_log: web::Data<Arc<AuditLog>>,
+    history: web::Data<Arc<SignatureHistory>>,
+    sessions: web::Data<Arc<SessionManager>>,
+    app_handle: web::Data<AppHandle>,
+) -> Result<HttpResponse, AgentError> {
+    let invalid = |e: Box<dyn Error>| AgentError::InvalidRequest(e.to_string());
+    let (request, files) = read_multipart(Multipart::new(http_req.headers(), payload)).await?;
+    let files = files
+        .into_iter()
+        .map(|(name, content)| {
+            name.map(|name| (name, content)).ok_or_else(|| {
+                AgentError::InvalidRequest("Every file part needs a file name".into())
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let objects: Vec<asic::DataObject> = files
+        .iter()
+        .map(|(name, content)| asic::DataObject {
+            name: name.clone(),
+            digest: Sha256::digest(content).to_vec(),
+        })
+        .collect();
+    let manifest = asic::manifest(&objects).map_err(invalid)?;
+    let manifest_digest = Sha256::digest(&manifest);
+
+    let cert_der =
+        hex::decode(&request.cert_hash).map_err(|e| AgentError::InvalidRequest(e.to_string()))?;
+    let signer = CmsSigner::new(&cert_der).map_err(invalid)?;
+    let signed_attrs = signer
+        .signed_attributes(&manifest_digest, Some(chrono::Utc::now()))
+        .map_err(AgentError::from_boxed)?;
+    let tbs = cades::to_be_signed(&signed_attrs).map_err(AgentError::from_boxed)?;
+    let tsa_url = app_data_dir(&app_handle)
+        .and_then(|dir| settings::load(&dir))
+        .map_err(AgentError::from_boxed)?
+        .tsa_url;
+
+    let sign_request = SignDocumentRequest {
+        cert_hash: request.cert_hash,
+        hash: hex::encode(manifest_digest),
+        timestamp: request.timestamp,
+        signed_certificate: request.signed_certificate,
+        envelope: request.envelope,
+        document: Some(SubmittedDocument {
+            name: Some(format!("ASiC container with {} files", files.len())),
+            preview: None,
+        }),
+        token_input: Some(TokenInput::new(signer.key_algorithm().token_input(&tbs))),
+    };
+    let signature = process_sign_request(
+        &app_handle,
+        request_origin(&http_req),
+        &data,
+        &audit_log,
+        &history,
+        &sessions,
+        sign_request,
+    )
+    .await?;
+    let signature = hex::decode(signature).map_err(|e| AgentError::Internal(e.to_string()))?;
+
+    let app = app_handle.get_ref().clone();
+    let container = web::block(move || {
+        let signature_timestamp = match &tsa_url {
+            Some(url) => Some(timestamp_signature(&app, url, &signer, &signature)?),
+            None => None,
+        };
+        let p7s = signer
+            .signed_data(signed_attrs, &signature, signature_timestamp.as_deref())
+            .map_err(AgentError::from_boxed)?;
+        let build = || -> Result<Vec<u8>, Box<dyn Error>> {
+            let mut container = asic::ContainerWriter::new(std::io::Cursor::new(Vec::new()))?;
+            for (name, content) in &files {
+                container.add_file(name, &mut content.as_slice())?;
+            }
+            Ok(container.finish(&manifest, &p7s)?.into_inner())
+        };
+        build().map_err(|e| AgentError::Internal(e.to_string()))
+    })
+    .await
+    .map_err(|e| AgentError::Internal(e.to_string()))??;
+    Ok(HttpResponse::Ok()
+        .content_type(asic::MIMETYPE)
+        .body(container))
+}
+
+/// A `/jws/sign` request. The envelope, if any, must authorize the digest
+/// of `payload` as its `doc_hash`.
+#[derive(Deserialize)]
+struct SignJwsRequest {
+    #[serde(flatten)]
+    request: SignContentRequest,
+    /// The JSON document to sign, signed byte for byte as given.
+    payload: String,
+    /// Defaults to RS256 for RSA keys and ES256 for EC keys.
+    algorithm: Option<JwsAlgorithm>,
+}
+
+/// Signs a JSON document as a JAdES baseline JWS with the token certificate
+/// chain in `x5c`. Returns both the compact and the JSON serialization.
+#[post("/jws/sign")]
+async fn sign_jws_route(
+    http_req: HttpRequest,
+    data: web::Data<Arc<SigningState>>,
+    audit_log: web::Data<Arc<AuditLog>>,
+    history: web::Data<Arc<SignatureHistory>>,
+    sessions: web::Data<Arc<SessionManager>>,
+    req_body: web::Json<SignJwsRequest>,
+    app_handle: web::Data<AppHandle>,
+) -> Result<HttpResponse, AgentError> {
+    let invalid = |e: Box<dyn Error>| AgentError::InvalidRequest(e.to_string());
+    let SignJwsRequest {
+        request,
+        payload,
+        algorithm,
+    } = req_body.into_inner();
+    serde_json::from_str::<serde_json::Value>(&payload)
+        .map_err(|e| AgentError::InvalidRequest(format!("The payload is not JSON: {}", e)))?;
+    let cert_der =
+        hex::decode(&request.cert_hash).map_err(|e| AgentError::InvalidRequest(e.to_string()))?;
+    let algorithm = match algorithm {
+        Some(algorithm) => algorithm,
+        None => JwsAlgorithm::for_certificate(&cert_der).map_err(invalid)?,
+    };
+    algorithm.check_certificate(&cert_der).map_err(invalid)?;
+
+    let origin = request_origin(&http_req);
+    let sign_request =
+        request.hash_document(Some("JSON document".to_string()), payload.as_bytes())?;
+    let mut authorized =
+        authorize_signing(&app_handle, origin, &data, &audit_log, sign_request).await?;
+    let protected = jws::protected_header(
+        algorithm,
+        &authorized.chain_der()?,
+        Some(chrono::Utc::now()),
+    )
+    .map_err(AgentError::from_boxed)?;
+    let mut signed = Jws::new(protected, payload.as_bytes());
+    authorized.request.token_input = Some(TokenInput {
+        data: algorithm.token_input(signed.signing_input().as_bytes()),
+        padding: if algorithm.uses_pss() {
+            RsaPadding::Pss
+        } else {
+            RsaPadding::Pkcs1v15
+        },
+    });
+    let signature = sign_authorized_request(
+        &app_handle,
+        origin,
+        &data,
+        &audit_log,
+        &history,
+        &sessions,
+        authorized,
+    )
+    .await?;
+    signed.set_signature(&hex::decode(signature).map_err(|e| AgentError::Internal(e.to_string()))?);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "compact": signed.compact(),
+        "json": signed.json(),
+    })))
+}
+
+#[derive(Deserialize)]
+struct VerifyJwsRequest {
+    /// A compact JWS string or a JWS JSON serialization object.
+    jws: serde_json::Value,
+}
+
+/// Checks a JWS signature against its `x5c` signing certificate and
+/// validates that certificate's chain against the trust store.
+#[post("/jws/verify")]
+async fn verify_jws_route(
+    req_body: web::Json<VerifyJwsRequest>,
+    app_handle: web::Data<AppHandle>,
+) -> Result<HttpResponse, AgentError> {
+    let invalid = |e: Box<dyn Error>| AgentError::InvalidRequest(e.to_string());
+    let parsed = Jws::parse(&req_body.jws).map_err(invalid)?;
+    let verification = jws::verify(&parsed).map_err(invalid)?;
+
+    let app = app_handle.get_ref().clone();
+    let certificates = verification.certificates.clone();
+    let chain = web::block(move || {
+        let validate = || -> Result<ChainValidation, Box<dyn Error>> {
+            let mut pool = certificate_pool(&app)?;
+            for cert in &certificates[1..] {
+                pool.add_intermediate(x509_cert::Certificate::from_der(cert)?);
+            }
+            chain::validate(&certificates[0], &pool, chrono::Utc::now())
+        };
+        validate().map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| AgentError::Internal(e.to_string()))?
+    .map_err(AgentError::Internal)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "valid": verification.signature_valid && chain.is_valid(),
+        "verification": verification,
+        "chain": chain,
+    })))
+}
+
+/// A `/cose/sign` request. The envelope, if any, must authorize the digest
+/// of the decoded payload as its `doc_hash`.
+#[derive(Deserialize)]
+struct SignCoseRequest {
+    #[serde(flatten)]
+    request: SignContentRequest,
+    /// The base64-encoded payload.
+    payload: String,
+    /// Defaults to the certificate chain in `x5chain`.
+    #[serde(default)]
+    key_reference: KeyReference,
+}
+
+/// Signs a payload as a tagged COSE_Sign1 with the token's EC key, for
+/// documents that carry their signature in a QR code. Returns the CBOR in
+/// base64.
+#[post("/cose/sign")]
+async fn sign_cose_route(
+    http_req: HttpRequest,
+    data: web::Data<Arc<SigningState>>,
+    audit_log: web::Data<Arc<AuditLog>>,
+    history: web::Data<Arc<SignatureHistory>>,
+    sessions: web::Data<Arc<SessionManager>>,
+    req_body: web::Json<SignCoseRequest>,
+    app_handle: web::Data<AppHandle>,
+) -> Result<HttpResponse, AgentError> {
+    let SignCoseRequest {
+        request,
+        payload,
+        key_reference,
+    } = req_body.into_inner();
+    let payload = BASE64_STANDARD
+        .decode(&payload)
+        .map_err(|e| AgentError::InvalidRequest(format!("The payload is not base64: {}", e)))?;
+
+    let origin = request_origin(&http_req);
+    let sign_request = request.hash_document(Some("COSE payload".to_string()), &payload)?;
+    let mut authorized =
+        authorize_signing(&app_handle, origin, &data, &audit_log, sign_request).await?;
+    let sign1 = cose::Sign1::new(payload, &authorized.chain_der()?, key_reference)
+        .map_err(|e| AgentError::InvalidRequest(e.to_string()))?;
+    authorized.request.token_input = Some(TokenInput::new(sign1.token_input()));
+    let signature = sign_authorized_request(
+        &app_handle,
+        origin,
+        &data,
+        &audit_log,
+        &history,
+        &sessions,
+        authorized,
+    )
+    .await?;
+    let signature = hex::decode(signature).map_err(|e| AgentError::Internal(e.to_string()))?;
+    let cose = sign1.finish(&signature).map_err(AgentError::from_boxed)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "cose": BASE64_STANDARD.encode(cose),
+    })))
+}
+
+#[derive(Deserialize)]
+struct VerifyCoseRequest {
+    /// The base64-encoded COSE_Sign1.
+    cose: String,
+    /// The hex DER signing certificate, needed when the signature only
+    /// carries a `kid`.
+    certificate: Option<String>,
+}
+
+/// Checks a COSE_Sign1 signature and validates the signing certificate's
+/// chain against the trust store.
+#[post("/cose/verify")]
+async fn verify_cose_route(
+    req_body: web::Json<VerifyCoseRequest>,
+    app_handle: web::Data<AppHandle>,
+) -> Result<HttpResponse, AgentError> {
+    let invalid = |e: Box<dyn Error>| AgentError::InvalidRequest(e.to_string());
+    let cose = BASE64_STANDARD
+        .decode(req_body.cose.trim())
+        .map_err(|e| AgentError::InvalidRequest(e.to_string()))?;
+    let certificate = req_body
+        .certificate
+        .as_deref()
+        .map(hex::decode)
+        .transpose()
+        .map_err(|e| AgentError::InvalidRequest(e.to_string()))?;
+    let verification = cose::verify(&cose, certificate.as_deref()).map_err(invalid)?;
+
+    let app = app_handle.get_ref().clone();
+    let certificates = verification.certificates.clone();
+    let chain = web::block(move || {
+        let validate = || -> Result<ChainValidation, Box<dyn Error>> {
+            let mut pool = certificate_pool(&app)?;
+            for cert in &certificates[1..] {
+                pool.add_intermediate(x509_cert::Certificate::from_der(cert)?);
+            }
+            chain::validate(&certificates[0], &pool, chrono::Utc::now())
+        };
+        validate().map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| AgentError::Internal(e.to_string()))?
+    .map_err(AgentError::Internal)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "valid": verification.signature_valid && chain.is_valid(),
+        "verification": verification,
+        "chain": chain,
+    })))
+}
+
+/// Returns the audit log and its verification to origins listed in the
+/// `audit_origins` setting. The server answers every origin through
+/// permissive CORS, so the allow-list is what keeps other sites out.
+#[get("/audit")]
+async fn audit_route(
+    http_req: HttpRequest,
+    app_handle: web::Data<AppHandle>,
+    audit_log: web::Data<Arc<AuditLog>>,
+) -> Result<HttpResponse, AgentError> {
+    let internal = |e: Box<dyn Error>| AgentError::Internal(e.to_string());
+    let settings = app_data_dir(app_handle.get_ref())
+        .and_then(|data_dir| settings::load(&data_dir))
+        .map_err(internal)?;
+    let origin = request_origin(&http_req).unwrap_or_default();
+    if !settings
+        .audit_origins
+        .iter()
+        .any(|allowed| allowed == origin)
+    {
+        return Err(AgentError::UnauthorizedOrigin(origin.to_string()));
+    }
+
+    let entries = audit_log.read_entries().map_err(internal)?;
+    let verification = audit_log.verify().map_err(internal)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "entries": entries,
+        "verification": verification,
+    })))
+}
+
+/// Checks that a leaf returned by `/sign-merkle` is covered by the signed
+/// root: the proof must lead to `root` and `signature` must be the
+/// certificate's signature over it.
+#[tauri::command]
+fn verify_merkle_proof(
+    certificate: String,
+    root: String,
+    signature: String,
+    leaf: LeafProof,
+) -> Result<bool, String> {
+    let cert_der = hex::decode(&certificate).map_err(|e| e.to_string())?;
+    let digest = hex::decode(&leaf.digest).map_err(|e| e.to_string())?;
+    let signature = hex::decode(&signature).map_err(|e| e.to_string())?;
+
+    let computed = merkle::root_from_proof(&digest, &leaf.proof).map_err(|e| e.to_string())?;
+    if !hex::encode(computed).eq_ignore_ascii_case(&root) {
+        return Ok(false);
+    }
+    let key_algorithm = CmsSigner::new(&cert_der)
+        .map_err(|e| e.to_string())?
+        .key_algorithm();
+    Ok(certificate::verify_token_signature(
+        &cert_der,
+        &key_algorithm.token_input(&computed),
+        &signature,
+    )
+    .is_ok())
+}
+
+#[tauri::command]
+fn get_audit_log(audit_log: tauri::State<Arc<AuditLog>>) -> Result<Vec<AuditEntry>, String> {
+    audit_log.read_entries().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn verify_audit_log(audit_log: tauri::State<Arc<AuditLog>>) -> Result<AuditVerification, String> {
+    audit_log.verify().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn search_signature_history(
+    history: tauri::State<Arc<SignatureHistory>>,
+    query: Option<String>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+) -> Result<Vec<HistoryEntry>, String> {
+    history
+        .search(query.as_deref(), limit.unwrap_or(50), offset.unwrap_or(0))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn delete_signature_history_entry(
+    history: tauri::State<Arc<SignatureHistory>>,
+    id: i64,
+) -> Result<bool, String> {
+    history.delete(id).map_err(|e| e.to_string())
+}
+
+/// Lets the user save a past signature to a file of their choice. Returns
+/// the chosen path, or `None` if the dialog was cancelled.
+#[tauri::command]
+async fn export_signature_history_entry(
+    app: AppHandle,
+    history: tauri::State<'_, Arc<SignatureHistory>>,
+    id: i64,
+) -> Result<Option<String>, String> {
+    let entry = history
+        .get(id)
+        .map_err(|e| e.to_string())?
+        .ok_or("Signature not found in history")?;
+    let file_name = format!(
+        "{}.signature.txt",
+        entry.document_name.as_deref().unwrap_or("document")
+    );
+
+    let Some(path) = app
+        .dialog()
+        .file()
+        .set_file_name(file_name)
+        .blocking_save_file()
+    else {
+        return Ok(None);
+    };
+    let path = path.into_path().map_err(|e| e.to_string())?;
+    std::fs::write(&path, &entry.signature).map_err(|e| e.to_string())?;
+    Ok(Some(path.to_string_lossy().into_owned()))
+}
+
+/// Lets the user pick a local file to sign. Returns `None` if the dialog
+/// was cancelled.
+#[tauri::command]
+async fn choose_document(app: AppHandle) -> Result<Option<String>, String> {
+    let Some(path) = app
+        .dialog()
+        .file()
+        .set_title("Choose the document to sign")
+        .blocking_pick_file()
+    else {
+        return Ok(None);
+    };
+    let path = path.into_path().map_err(|e| e.to_string())?;
+    Ok(Some(path.to_string_lossy().into_owned()))
+}
+
+/// Signs a local file with a token certificate and writes a detached CMS
+/// signature next to it as `<file>.p7s`. Returns the signature's path.
+#[tauri::command]
+async fn sign_file_detached(
+    app: AppHandle,
+    history: tauri::State<'_, Arc<SignatureHistory>>,
+    path: String,
+    cert_id: String,
+    pin: String,
+) -> Result<String, String> {
+    let history = history.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        write_detached_signature(app, &history, Path::new(&path), &cert_id, &pin)
+            .map(|output| output.to_string_lossy().into_owned())
+            .map_err(|e| AgentError::from_boxed(e).to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Has the TSA at `url` timestamp a token signature as it will appear in the
+/// signer info.
+fn timestamp_signature(
+    app: &AppHandle,
+    url: &str,
+    signer: &CmsSigner,
+    raw_signature: &[u8],
+) -> Result<Vec<u8>, AgentError> {
+    let signature_value = signer
+        .key_algorithm()
+        .encode_signature(raw_signature)
+        .map_err(AgentError::from_boxed)?;
+    timestamp_client(app, url)
+        .map_err(AgentError::from_boxed)?
+        .timestamp(&signature_value)
+        .map_err(|e| AgentError::TimestampFailed(e.to_string()))
+}
+
+fn write_detached_signature(
+    app: AppHandle,
+    history: &SignatureHistory,
+    path: &Path,
+    cert_id: &str,
+    pin: &str,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let digest = document::sha256_file(path)?;
+    let tsa_url = settings::load(&app_data_dir(&app)?)?.tsa_url;
+
+    let pkcs11 = get_pkcs_11(app.clone())?;
+    let (slot, cert_der) = find_certificate_by_id(&pkcs11, &hex::decode(cert_id)?)?;
+    require_valid_certificate(&app, &hex::encode(&cert_der))?;
+    let signer = CmsSigner::new(&cert_der)?;
+    let signed_attrs = signer.signed_attributes(&digest, Some(chrono::Utc::now()))?;
+    let tbs = signer
+        .key_algorithm()
+        .token_input(&cades::to_be_signed(&signed_attrs)?);
+    let signature = sign_hash_with_cert(&pkcs11, slot, pin, &cert_der, &tbs);
+    record_local_signature(&app, &cert_der, &digest, &signature);
+    let signature = signature?;
+    let signature_timestamp = match &tsa_url {
+        Some(url) => Some(timestamp_signature(&app, url, &signer, &signature)?),
+        None => None,
+    };
+    let p7s = signer.signed_data(signed_attrs, &signature, signature_timestamp.as_deref())?;
+
+    let mut output = path.as_os_str().to_owned();
+    output.push(".p7s");
+    let output = PathBuf::from(output);
+    std::fs::write(&output, &p7s)?;
+
+    let entry = NewHistoryEntry::new(
+        file_name(path),
+        &cert_der,
+        hex::encode(&digest),
+        DEFAULT_HASH_ALGORITHM.to_string(),
+        BASE64_STANDARD.encode(&p7s),
+    );
+    remember_signature(history, entry);
+    Ok(output)
+}
+
+/// Certificates and revocation data for the signing certificate's chain
+/// and, if there is a signature timestamp, the TSA's chain.
+fn collect_validation_data(
+    app: &AppHandle,
+    chain: &[x509_cert::Certificate],
+    signature_timestamp: Option<&[u8]>,
+) -> Result<ValidationData, Box<dyn Error>> {
+    let now = chrono::Utc::now();
+    let checker = revocation_checker(app)?;
+    let mut chains = vec![chain.to_vec()];
+    if let Some(token) = signature_timestamp {
+        let tsa_certificate = timestamp::token_certificate(token)?;
+        let tsa_chain = chain::validate(&tsa_certificate.to_der()?, &certificate_pool(app)?, now)?;
+        chains.push(tsa_chain.certificates);
+    }
+
+    let mut data = ValidationData::default();
+    for (index, chain) in chains.iter().enumerate() {
+        for cert in chain {
+            data.add_certificate(cert.to_der()?);
+        }
+        for (position, pair) in chain.windows(2).enumerate() {
+            match checker.check_with_evidence(&pair[0], &pair[1], now) {
+                (_, Some(RevocationEvidence::Ocsp(response))) => data.ocsp_responses.push(response),
+                (_, Some(RevocationEvidence::Crl(crl))) => {
+                    if !data.crls.contains(&crl) {
+                        data.crls.push(crl);
+                    }
+                }
+                (RevocationStatus::Unknown { reason }, None) if index == 0 && position == 0 => {
+                    return Err(format!(
+                        "No revocation data could be collected for the signing certificate: {}",
+                        reason
+                    )
+                    .into());
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(data)
+}
+
+/// Signs a local PDF at the given PAdES level, visibly if `options` has an
+/// appearance. Returns the signed copy's path.
+#[tauri::command]
+async fn sign_pdf(
+    app: AppHandle,
+    history: tauri::State<'_, Arc<SignatureHistory>>,
+    path: String,
+    cert_id: String,
+    pin: String,
+    level: SignatureLevel,
+    options: Option<SignatureOptions>,
+) -> Result<String, String> {
+    let history = history.inner().clone();
+    let options = options.unwrap_or_default();
+    tauri::async_runtime::spawn_blocking(move || {
+        write_signed_pdf(
+            app,
+            &history,
+            Path::new(&path),
+            &cert_id,
+            &pin,
+            level,
+            &options,
+        )
+        .map(|output| output.to_string_lossy().into_owned())
+        .map_err(|e| AgentError::from_boxed(e).to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Signs the PDF at `path` and writes the result next to it as
+/// `<name>-signed.pdf`.
+fn write_signed_pdf(
+    app: AppHandle,
+    history: &SignatureHistory,
+    path: &Path,
+    cert_id: &str,
+    pin: &str,
+    level: SignatureLevel,
+    options: &SignatureOptions,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let signed = signed_pdf(app, history, path, cert_id, pin, level, options)?;
+    let output = suffixed_path(path, "signed");
+    std::fs::write(&output, signed)?;
+    Ok(output)
+}
+
+/// `path` with `-<suffix>` added to its file stem.
+fn suffixed_path(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    match path.extension() {
+        Some(extension) => path.with_file_name(format!(
+            "{}-{}.{}",
+            stem,
+            suffix,
+            extension.to_string_lossy()
+        )),
+        None => path.with_file_name(format!("{}-{}", stem, suffix)),
+    }
+}
+
+/// The PDF at `path` with a new signature field signed by the token
+/// certificate appended as an incremental update, so signatures already
+/// in it stay intact.
+fn signed_pdf(
+    app: AppHandle,
+    history: &SignatureHistory,
+    path: &Path,
+    cert_id: &str,
+    pin: &str,
+    level: SignatureLevel,
+    options: &SignatureOptions,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let original = std::fs::read(path)?;
+    let tsa_url = settings::load(&app_data_dir(&app)?)?.tsa_url;
+    let tsa_url = match (level.needs_timestamp(), tsa_url) {
+        (true, None) => {
+            return Err(AgentError::TimestampFailed(
+                "Timestamped signature levels need a time-stamping authority".into(),
+            )
+            .into())
+        }
+        (true, Some(url)) => Some(url),
+        (false, _) => None,
+    };
+
+    let pkcs11 = get_pkcs_11(app.clone())?;
+    let (slot, cert_der) = find_certificate_by_id(&pkcs11, &hex::decode(cert_id)?)?;
+    let validation = require_valid_certificate(&app, &hex::encode(&cert_der))?;
+    let prepared = pdf::prepare_signature(
+        &original,
+        chrono::Utc::now(),
+        &certificate::subject_name(&cert_der)?,
+        options,
+    )?;
+    let signer = CmsSigner::new(&cert_der)?;
+    let signed_attrs = signer.signed_attributes(&prepared.digest(), None)?;
+    let tbs = signer
+        .key_algorithm()
+        .token_input(&cades::to_be_signed(&signed_attrs)?);
+    let signature = sign_hash_with_cert(&pkcs11, slot, pin, &cert_der, &tbs);
+    record_local_signature(&app, &cert_der, &Sha256::digest(&original), &signature);
+    let signature = signature?;
+    let signature_timestamp = match &tsa_url {
+        Some(url) => Some(timestamp_signature(&app, url, &signer, &signature)?),
+        None => None,
+    };
+    let cms = signer.signed_data(signed_attrs, &signature, signature_timestamp.as_deref())?;
+    let signed = prepared.embed(&cms)?;
+
+    let mut output_pdf = if level.needs_validation_data() {
+        let data = collect_validation_data(
+            &app,
+            &validation.certificates,
+            signature_timestamp.as_deref(),
+        )?;
+        pdf::add_validation_data(&signed, &data)?
+    } else {
+        signed.bytes
+    };
+    if let (true, Some(url)) = (level.needs_document_timestamp(), &tsa_url) {
+        let prepared = pdf::prepare_document_timestamp(&output_pdf)?;
+        let token = timestamp_client(&app, url)?
+            .timestamp(&prepared.signed_bytes())
+            .map_err(|e| AgentError::TimestampFailed(e.to_string()))?;
+        output_pdf = prepared.embed(&token)?.bytes;
+    }
+
+    let entry = NewHistoryEntry::new(
+        file_name(path),
+        &cert_der,
+        hex::encode(Sha256::digest(&original)),
+        DEFAULT_HASH_ALGORITHM.to_string(),
+        BASE64_STANDARD.encode(&cms),
+    );
+    remember_signature(history, entry);
+    Ok(output_pdf)
+}
+
+/// Packs local files into an ASiC-E container signed with a token
+/// certificate. Returns the container's path.
+#[tauri::command]
+async fn create_asic_container(
+    app: AppHandle,
+    history: tauri::State<'_, Arc<SignatureHistory>>,
+    paths: Vec<String>,
+    cert_id: String,
+    pin: String,
+) -> Result<String, String> {
+    let history = history.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let paths: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+        write_asic_container(app, &history, &paths, &cert_id, &pin)
+            .map(|output| output.to_string_lossy().into_owned())
+            .map_err(|e| AgentError::from_boxed(e).to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Writes the container next to the first file as `<name>.asice`.
+fn write_asic_container(
+    app: AppHandle,
+    history: &SignatureHistory,
+    paths: &[PathBuf],
+    cert_id: &str,
+    pin: &str,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let names = paths
+        .iter()
+        .map(|path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .ok_or_else(|| format!("{} is not a file", path.display()).into())
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    let first = paths
+        .first()
+        .ok_or("An ASiC container needs at least one file")?;
+    let stem = first
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let output = first.with_file_name(format!("{}.asice", stem));
+    if paths.contains(&output) {
+        return Err(format!("{} would overwrite one of the files", output.display()).into());
+    }
+    let tsa_url = settings::load(&app_data_dir(&app)?)?.tsa_url;
+
+    let pkcs11 = get_pkcs_11(app.clone())?;
+    let (slot, cert_der) = find_certificate_by_id(&pkcs11, &hex::decode(cert_id)?)?;
+    require_valid_certificate(&app, &hex::encode(&cert_der))?;
+
+    // The files are hashed while they are packed, so the container is
+    // written before the manifest can be signed and is removed if that
+    // fails.
+    let write = || -> Result<(Vec<u8>, Vec<u8>), Box<dyn Error>> {
+        let mut container = asic::ContainerWriter::new(std::fs::File::create(&output)?)?;
+        let mut objects = Vec::new();
+        for (path, name) in paths.iter().zip(names) {
+            let digest = container.add_file(&name, &mut std::fs::File::open(path)?)?;
+            objects.push(asic::DataObject { name, digest });
+        }
+        let manifest = asic::manifest(&objects)?;
+        let manifest_digest = Sha256::digest(&manifest);
+
+        let signer = CmsSigner::new(&cert_der)?;
+        let signed_attrs = signer.signed_attributes(&manifest_digest, Some(chrono::Utc::now()))?;
+        let tbs = signer
+            .key_algorithm()
+            .token_input(&cades::to_be_signed(&signed_attrs)?);
+        let signature = sign_hash_with_cert(&pkcs11, slot, pin, &cert_der, &tbs);
+        record_local_signature(&app, &cert_der, &manifest_digest, &signature);
+        let signature = signature?;
+        let signature_timestamp = match &tsa_url {
+            Some(url) => Some(timestamp_signature(&app, url, &signer, &signature)?),
+            None => None,
+        };
+        let p7s = signer.signed_data(signed_attrs, &signature, signature_timestamp.as_deref())?;
+        container.finish(&manifest, &p7s)?;
+        Ok((manifest_digest.to_vec(), p7s))
+    };
+    let (manifest_digest, p7s) = write().inspect_err(|_| {
+        let _ = std::fs::remove_file(&output);
+    })?;
+
+    let entry = NewHistoryEntry::new(
+        file_name(&output),
+        &cert_der,
+        hex::encode(manifest_digest),
+        DEFAULT_HASH_ALGORITHM.to_string(),
+        BASE64_STANDARD.encode(&p7s),
+    );
+    remember_signature(history, entry);
+    Ok(output)
+}
+
+/// A message signed by `sign_email`, and where it was written if it came
+/// from a file.
+#[derive(Serialize)]
+struct SignedEmail {
+    path: Option<String>,
+    message: String,
+}
+
+/// Signs an RFC 5322 message as S/MIME `multipart/signed`. The message is
+/// read from the `.eml` file at `path`, in which case the signed copy is
+/// written next to it, or taken from `message`.
+#[tauri::command]
+async fn sign_email(
+    app: AppHandle,
+    history: tauri::State<'_, Arc<SignatureHistory>>,
+    path: Option<String>,
+    message: Option<String>,
+    cert_id: String,
+    pin: String,
+) -> Result<SignedEmail, String> {
+    let history = history.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        write_signed_email(app, &history, path, message, &cert_id, &pin)
+            .map_err(|e| AgentError::from_boxed(e).to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
 }
 
-#[get("/list-certificates")]
-async fn list_certificates_route(
-    app_handle: web::Data<AppHandle>,
-) -> impl Responder {
-    // Create the PKCS#11 instance and list available certificates.
-    let pkcs11 = match get_pkcs_11(app_handle.get_ref().clone()) {
-        Ok(pkcs11) => pkcs11,
-        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+fn write_signed_email(
+    app: AppHandle,
+    history: &SignatureHistory,
+    path: Option<String>,
+    message: Option<String>,
+    cert_id: &str,
+    pin: &str,
+) -> Result<SignedEmail, Box<dyn Error>> {
+    let original = match (&path, message) {
+        (Some(path), _) => std::fs::read(path)?,
+        (None, Some(message)) => message.into_bytes(),
+        (None, None) => return Err("Either a message or the path of an .eml file is needed".into()),
     };
-    
-    let certs = match list_certificates(&pkcs11) {
-        Ok(certs) => certs,
-        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    let prepared = smime::PreparedMessage::new(&original)?;
+    let digest = Sha256::digest(prepared.entity());
+    let tsa_url = settings::load(&app_data_dir(&app)?)?.tsa_url;
+
+    let pkcs11 = get_pkcs_11(app.clone())?;
+    let (slot, cert_der) = find_certificate_by_id(&pkcs11, &hex::decode(cert_id)?)?;
+    require_valid_certificate(&app, &hex::encode(&cert_der))?;
+    let signer = CmsSigner::new(&cert_der)?;
+    let signed_attrs = signer.signed_attributes(&digest, Some(chrono::Utc::now()))?;
+    let tbs = signer
+        .key_algorithm()
+        .token_input(&cades::to_be_signed(&signed_attrs)?);
+    let signature = sign_hash_with_cert(&pkcs11, slot, pin, &cert_der, &tbs);
+    record_local_signature(&app, &cert_der, &digest, &signature);
+    let signature = signature?;
+    let signature_timestamp = match &tsa_url {
+        Some(url) => Some(timestamp_signature(&app, url, &signer, &signature)?),
+        None => None,
     };
+    let p7s = signer.signed_data(signed_attrs, &signature, signature_timestamp.as_deref())?;
 
-    // Return the certificates directly to the frontend
-    HttpResponse::Ok().json(serde_json::json!({ "certificates": certs }))
+    let document_name = prepared.subject().or_else(|| {
+        path.as_deref()
+            .and_then(|path| Path::new(path).file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+    });
+    let signed = prepared.assemble(&p7s)?;
+    let output = match &path {
+        Some(path) => {
+            let path = Path::new(path);
+            let stem = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let output = path.with_file_name(format!("{}-signed.eml", stem));
+            std::fs::write(&output, &signed)?;
+            Some(output.to_string_lossy().into_owned())
+        }
+        None => None,
+    };
+
+    let entry = NewHistoryEntry::new(
+        document_name,
+        &cert_der,
+        hex::encode(digest),
+        DEFAULT_HASH_ALGORITHM.to_string(),
+        BASE64_STANDARD.encode(&p7s),
+    );
+    remember_signature(history, entry);
+    Ok(SignedEmail {
+        path: output,
+        message: String::from_utf8(signed)?,
+    })
 }
 
-#[tauri::command]
-fn complete_certificate(
-    window: tauri::Window,
-    cert_id: String,
-    cert_state: tauri::State<Arc<CertificateState>>,
-) -> Result<(), String> {
-    let app_handle = window.app_handle();
-    let pkcs11 = get_pkcs_11(app_handle.clone()).map_err(|e| e.to_string())?;
-    let slots = pkcs11.get_slots_with_token().map_err(|e| e.to_string())?;
-    let cert_id_bytes = hex::decode(&cert_id).map_err(|e| e.to_string())?;
+/// A local file holding a CMS signature that another signer is added to.
+enum SignedFile {
+    /// A `.p7s` or `.p7m` file that is the CMS itself.
+    Cms,
+    Pdf(pdf::EmbeddedSignature),
+    Asic,
+}
 
-    let mut found_cert = None;
-    let mut found_label = None;
-    for slot in slots {
-        if let Ok(cert) = extract_certificate_by_id(&pkcs11, slot, &cert_id_bytes) {
-            found_cert = Some(cert);
-            if let Ok(cert_list) = list_certificates(&pkcs11) {
-                if let Some(cert_info) = cert_list.into_iter().find(|ci| ci.id == cert_id) {
-                    found_label = Some(cert_info.label);
+/// How `cosign_file` adds its signer.
+#[derive(Default, Deserialize)]
+struct CosignOptions {
+    /// Where a detached `.p7s`'s content is, if not next to it without the
+    /// extension.
+    content_path: Option<PathBuf>,
+    /// The PAdES level for a PDF, by default B-T if a TSA is configured and
+    /// B-B otherwise. CMS signatures are timestamped whenever a TSA is
+    /// configured.
+    level: Option<SignatureLevel>,
+}
+
+/// What `cosign_file` and `countersign_file` add to a CMS signature.
+enum AddedSignature {
+    /// A parallel signer.
+    Parallel(CosignOptions),
+    /// A countersignature of the signer at this index.
+    Countersignature(usize),
+}
+
+impl SignedFile {
+    /// Finds the CMS in `bytes`, picking a PDF's signature by index, the
+    /// newest by default.
+    fn locate(
+        path: &Path,
+        bytes: &[u8],
+        signature: Option<usize>,
+    ) -> Result<(Self, Vec<u8>), Box<dyn Error>> {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("pdf") => {
+                let mut signatures = pdf::embedded_signatures(bytes)?;
+                let count = signatures.len();
+                if count == 0 {
+                    return Err("The PDF has no signatures".into());
                 }
+                let index = signature.unwrap_or(count - 1);
+                if index >= count {
+                    return Err(
+                        format!("There is no signature {}, the PDF has {}", index, count).into(),
+                    );
+                }
+                let embedded = signatures.swap_remove(index);
+                let cms = embedded.contents(bytes)?;
+                Ok((SignedFile::Pdf(embedded), cms))
             }
-            break;
+            Some("asice" | "sce") => Ok((SignedFile::Asic, asic::read_signature(bytes)?.1)),
+            _ => Ok((SignedFile::Cms, bytes.to_vec())),
         }
     }
 
-    let cert_bytes = found_cert.ok_or("Certificate not found".to_string())?;
-    let label = found_label.unwrap_or_else(|| "Unknown Certificate".into());
+    /// The content the existing signers signed. A detached `.p7s` is looked
+    /// up at `content_path`, or by default next to it without the extension.
+    /// If nothing is there, the caller has to say where the content is.
+    fn content(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        existing: &cades::ExistingSignedData,
+        content_path: Option<&Path>,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        match self {
+            SignedFile::Pdf(_) => {
+                Err("PDFs are co-signed with a signature field of their own".into())
+            }
+            SignedFile::Asic => {
+                let (manifest, _) = asic::read_signature(bytes)?;
+                asic::check_manifest(bytes, &manifest)?;
+                Ok(manifest)
+            }
+            SignedFile::Cms => {
+                if let Some(content) = existing.content()? {
+                    return Ok(content);
+                }
+                let content_path = match content_path {
+                    Some(content_path) => content_path.to_path_buf(),
+                    None => {
+                        let derived = path.with_extension("");
+                        if derived == path || !derived.is_file() {
+                            return Err(format!(
+                                "The signed content of {} was not found; choose the signed file",
+                                path.display()
+                            )
+                            .into());
+                        }
+                        derived
+                    }
+                };
+                std::fs::read(&content_path).map_err(|e| {
+                    format!(
+                        "The signed content {} could not be read: {}",
+                        content_path.display(),
+                        e
+                    )
+                    .into()
+                })
+            }
+        }
+    }
 
-    let cert_object = serde_json::json!({
-         "derHex": hex::encode(cert_bytes),
-         "name": label
-    });
+    /// The file's bytes with `cms` in place of the signature `locate` found.
+    fn with_signature(&self, bytes: &[u8], cms: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        match self {
+            SignedFile::Cms => Ok(cms.to_vec()),
+            SignedFile::Pdf(embedded) => embedded.replace_contents(bytes, cms),
+            SignedFile::Asic => asic::replace_signature(bytes, cms),
+        }
+    }
+}
 
-    let req = {
-        let mut lock = cert_state.current_request.lock().unwrap();
-        lock.take().expect("Certificate request expected")
-    };
-    req.response_tx
-        .send(Ok(serde_json::to_string(&cert_object).unwrap()))
-        .map_err(|_| "Failed to send certificate extraction result".to_string())?;
-    window
-        .close()
-        .map_err(|err| format!("Failed to close window: {}", err))?;
-    Ok(())
+/// Adds a parallel signature by a token certificate to the CMS signature
+/// in a `.p7s` or `.p7m` file or an ASiC-E container. A detached `.p7s`
+/// also needs the signed file, by default the one it is named after. A PDF
+/// gets a new signature field at the requested level instead, and
+/// `signature` is ignored for it. Returns the path of the co-signed copy.
+#[tauri::command]
+async fn cosign_file(
+    app: AppHandle,
+    history: tauri::State<'_, Arc<SignatureHistory>>,
+    path: String,
+    signature: Option<usize>,
+    options: Option<CosignOptions>,
+    cert_id: String,
+    pin: String,
+) -> Result<String, String> {
+    let history = history.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        write_added_signature(
+            app,
+            &history,
+            Path::new(&path),
+            signature,
+            AddedSignature::Parallel(options.unwrap_or_default()),
+            &cert_id,
+            &pin,
+        )
+        .map(|output| output.to_string_lossy().into_owned())
+        .map_err(|e| AgentError::from_boxed(e).to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
 }
 
-fn verify_company_signature(
-    _app: AppHandle,
-    _message: &str,
-    _signature: &str,
-) -> Result<bool, Box<dyn Error>> {
-    Ok(true)
-    // let public_key_str = get_public_key_str(app)?;
-    // let public_key: SignedPublicKey = SignedPublicKey::from_string(&public_key_str).unwrap().0;
-    // public_key.verify().unwrap();
-    // let binding = base64::decode(signature)?;
-    // let sig_decoded = binding.as_slice();
-    // let (StandaloneSignature { signature }, _) =
-    //     StandaloneSignature::from_armor_single_buf(sig_decoded)?;
-    // println!("Message: {:?}", message);
-    // println!("Decoded Signature: {:?}", String::from_utf8(sig_decoded.to_vec()).unwrap());
-    // signature.verify(&public_key, message.as_bytes())?;
-    // Ok(true)
+/// Names the signers of a CMS signature in the same kinds of files as
+/// `cosign_file`, in the order `countersign_file` indexes them. Adding a
+/// signature can change that order.
+#[tauri::command]
+fn list_signers(path: String, signature: Option<usize>) -> Result<Vec<String>, String> {
+    let list = || -> Result<Vec<String>, Box<dyn Error>> {
+        let path = Path::new(&path);
+        let (_, cms) = SignedFile::locate(path, &std::fs::read(path)?, signature)?;
+        Ok(cades::ExistingSignedData::parse(&cms)?.signer_names())
+    };
+    list().map_err(|e| e.to_string())
 }
 
-#[derive(Deserialize)]
-struct SignDocumentWithPinRequest {
-    cert_hash: String,
-    hash: String,
-    timestamp: String,
-    signed_certificate: String,
+/// Countersigns a signer, the first by default, of the CMS signature in
+/// the same kinds of files as `cosign_file`. Returns the path of the
+/// countersigned copy.
+#[tauri::command]
+async fn countersign_file(
+    app: AppHandle,
+    history: tauri::State<'_, Arc<SignatureHistory>>,
+    path: String,
+    signature: Option<usize>,
+    signer: Option<usize>,
+    cert_id: String,
     pin: String,
+) -> Result<String, String> {
+    let history = history.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        write_added_signature(
+            app,
+            &history,
+            Path::new(&path),
+            signature,
+            AddedSignature::Countersignature(signer.unwrap_or(0)),
+            &cert_id,
+            &pin,
+        )
+        .map(|output| output.to_string_lossy().into_owned())
+        .map_err(|e| AgentError::from_boxed(e).to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
 }
 
-#[post("/sign-document")]
-async fn sign_document(
-    data: web::Data<Arc<SigningState>>,
-    req_body: web::Json<SignDocumentRequest>,
-    app_handle: web::Data<AppHandle>,
-) -> impl Responder {
-    let (tx, rx) = oneshot::channel();
-
-    let message = format!("{}_{}", req_body.cert_hash, req_body.timestamp);
-    if let Err(e) = verify_company_signature(
-        app_handle.get_ref().clone(),
-        &message,
-        &req_body.signed_certificate,
-    ) {
-        return HttpResponse::BadRequest().body(format!("Signature verification failed: {}", e));
+/// Writes the file at `path` with `added` next to it as
+/// `<name>-cosigned.<ext>` or `<name>-countersigned.<ext>`.
+fn write_added_signature(
+    app: AppHandle,
+    history: &SignatureHistory,
+    path: &Path,
+    signature: Option<usize>,
+    added: AddedSignature,
+    cert_id: &str,
+    pin: &str,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let is_pdf = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("pdf"));
+    if let (AddedSignature::Parallel(options), true) = (&added, is_pdf) {
+        // Every PDF signature covers the file up to its own /Contents, so
+        // another signer gets a signature field of their own.
+        let level = match options.level {
+            Some(level) => level,
+            None => match settings::load(&app_data_dir(&app)?)?.tsa_url {
+                Some(_) => SignatureLevel::Timestamped,
+                None => SignatureLevel::Basic,
+            },
+        };
+        let options = SignatureOptions::default();
+        let signed = signed_pdf(app, history, path, cert_id, pin, level, &options)?;
+        let output = suffixed_path(path, "cosigned");
+        std::fs::write(&output, signed)?;
+        return Ok(output);
     }
 
-    let raw_app_handle = app_handle.get_ref();
+    let original = std::fs::read(path)?;
+    let (file, cms) = SignedFile::locate(path, &original, signature)?;
+    let existing = cades::ExistingSignedData::parse(&cms)?;
+    let tsa_url = settings::load(&app_data_dir(&app)?)?.tsa_url;
+
+    let pkcs11 = get_pkcs_11(app.clone())?;
+    let (slot, cert_der) = find_certificate_by_id(&pkcs11, &hex::decode(cert_id)?)?;
+    require_valid_certificate(&app, &hex::encode(&cert_der))?;
+    let signer = CmsSigner::new(&cert_der)?;
+    let now = chrono::Utc::now();
+    let (digest, signed_attrs) = match &added {
+        AddedSignature::Parallel(options) => {
+            let content =
+                file.content(path, &original, &existing, options.content_path.as_deref())?;
+            existing.check_content(&content)?;
+            let digest = Sha256::digest(&content).to_vec();
+            let attrs = signer.parallel_attributes(existing.content_type(), &digest, Some(now))?;
+            (digest, attrs)
+        }
+        AddedSignature::Countersignature(index) => {
+            let value = existing.signature_value(*index)?;
+            let attrs = signer.countersignature_attributes(&value, Some(now))?;
+            (Sha256::digest(&value).to_vec(), attrs)
+        }
+    };
+    let tbs = signer
+        .key_algorithm()
+        .token_input(&cades::to_be_signed(&signed_attrs)?);
+    let raw_signature = sign_hash_with_cert(&pkcs11, slot, pin, &cert_der, &tbs);
+    record_local_signature(&app, &cert_der, &digest, &raw_signature);
+    let raw_signature = raw_signature?;
+    let signature_timestamp = match &tsa_url {
+        Some(url) => Some(timestamp_signature(&app, url, &signer, &raw_signature)?),
+        None => None,
+    };
+    let signer_info =
+        signer.signer_info(signed_attrs, &raw_signature, signature_timestamp.as_deref())?;
+    let (cms, suffix) = match added {
+        AddedSignature::Parallel(_) => (existing.add_signer(&signer, signer_info)?, "cosigned"),
+        AddedSignature::Countersignature(index) => (
+            existing.add_countersignature(index, &signer, signer_info)?,
+            "countersigned",
+        ),
+    };
 
-    {
-        let mut req_lock = data.current_request.lock().unwrap();
-        *req_lock = Some(SigningRequest {
-            cert_hash: req_body.cert_hash.clone(),
-            doc_hash: req_body.hash.clone(),
-            timestamp: req_body.timestamp.clone(),
-            signed_certificate: req_body.signed_certificate.clone(),
-            response_tx: tx,
-        });
-    }
+    let output = suffixed_path(path, suffix);
+    std::fs::write(&output, file.with_signature(&original, &cms)?)?;
+
+    let entry = NewHistoryEntry::new(
+        file_name(path),
+        &cert_der,
+        hex::encode(&digest),
+        DEFAULT_HASH_ALGORITHM.to_string(),
+        BASE64_STANDARD.encode(&cms),
+    );
+    remember_signature(history, entry);
+    Ok(output)
+}
 
-    let _ = tauri::WebviewWindowBuilder::new(
-        raw_app_handle,
-        "sign_popup",
-        tauri::WebviewUrl::App("popup.html".into()),
-    )
-    .title("Sign Document")
-    .build();
+/// Opens a session for the token certificate `cert_id` and hands `work` the
+/// certificate's key as an OpenPGP signing key. The outcome is audited
+/// against `doc_hash`, the SHA-256 digest of what is being signed, or for
+/// key certifications against the certificate's own digest.
+fn with_openpgp_key<T>(
+    app: &AppHandle,
+    cert_id: &str,
+    pin: &str,
+    doc_hash: Option<&[u8]>,
+    work: impl FnOnce(&openpgp::TokenKey, &[u8]) -> Result<T, Box<dyn Error>>,
+) -> Result<T, Box<dyn Error>> {
+    let pkcs11 = get_pkcs_11(app.clone())?;
+    let (slot, cert_der) = find_certificate_by_id(&pkcs11, &hex::decode(cert_id)?)?;
+    require_valid_certificate(app, &hex::encode(&cert_der))?;
+    let sign_with_token = || {
+        let session = open_signing_session(&pkcs11, slot, pin)?;
+        // OpenPGP hashes its own signature trailer, so RSA keys sign a
+        // `DigestInfo` built by `TokenKey` rather than the data.
+        let sign = |input: &[u8]| {
+            sign_in_session_with_padding(&session, &cert_der, input, RsaPadding::Pkcs1v15Raw)
+        };
+        let key = openpgp::TokenKey::new(&cert_der, &sign)?;
+        work(&key, &cert_der)
+    };
+    let result = sign_with_token();
+    let doc_hash = doc_hash.map_or_else(|| Sha256::digest(&cert_der).to_vec(), <[u8]>::to_vec);
+    record_local_signature(app, &cert_der, &doc_hash, &result);
+    result
+}
 
-    match rx.await {
-        Ok(result) => match result {
-            Ok(signature) => {
-                let response = serde_json::json!({ "signature": signature });
-                HttpResponse::Ok().json(response)
+/// Signs `data` and records the signature in the history.
+fn write_openpgp_signature(
+    app: &AppHandle,
+    history: &SignatureHistory,
+    data: &Path,
+    cert_id: &str,
+    pin: &str,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let digest = document::sha256_file(data)?;
+    let (armored, cert_der) =
+        with_openpgp_key(app, cert_id, pin, Some(&digest), |key, cert_der| {
+            let armored = key.detached_signature(std::fs::File::open(data)?)?;
+            Ok((armored, cert_der.to_vec()))
+        })?;
+    let mut output = data.as_os_str().to_owned();
+    output.push(".asc");
+    let output = PathBuf::from(output);
+    std::fs::write(&output, &armored)?;
+
+    let entry = NewHistoryEntry::new(
+        file_name(data),
+        &cert_der,
+        hex::encode(digest),
+        DEFAULT_HASH_ALGORITHM.to_string(),
+        armored,
+    );
+    remember_signature(history, entry);
+    Ok(output)
+}
+
+/// Writes an armored OpenPGP detached signature of the file at `path` to
+/// `<path>.asc`, as `gpg --armor --detach-sign` would. Returns its path.
+#[tauri::command]
+async fn sign_file_openpgp(
+    app: AppHandle,
+    history: tauri::State<'_, Arc<SignatureHistory>>,
+    path: String,
+    cert_id: String,
+    pin: String,
+) -> Result<String, String> {
+    let history = history.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        write_openpgp_signature(&app, &history, Path::new(&path), &cert_id, &pin)
+            .map(|output| output.to_string_lossy().into_owned())
+            .map_err(|e| AgentError::from_boxed(e).to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// A file the caller has already hashed, to list in `SHA256SUMS` without
+/// it being on this machine.
+#[derive(Deserialize)]
+struct ChecksumEntry {
+    name: String,
+    /// Hex-encoded SHA-256 digest.
+    sha256: String,
+}
+
+/// Writes a `SHA256SUMS` file listing the digests of `paths` and then of
+/// `digests`, and signs it to `SHA256SUMS.asc`, the usual layout for
+/// release artifacts. Both files go to `output_dir`, by default the
+/// directory of the first path. Returns the path of the signature.
+#[tauri::command]
+async fn sign_checksums_openpgp(
+    app: AppHandle,
+    history: tauri::State<'_, Arc<SignatureHistory>>,
+    paths: Vec<String>,
+    digests: Option<Vec<ChecksumEntry>>,
+    output_dir: Option<String>,
+    cert_id: String,
+    pin: String,
+) -> Result<String, String> {
+    let history = history.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let write_sums = || -> Result<PathBuf, Box<dyn Error>> {
+            let mut entries = Vec::new();
+            for path in &paths {
+                let path = Path::new(path);
+                let name =
+                    file_name(path).ok_or_else(|| format!("{} is not a file", path.display()))?;
+                entries.push((name, document::sha256_file(path)?));
             }
-            Err(err_msg) => HttpResponse::BadRequest().body(err_msg),
-        },
-        Err(_) => HttpResponse::InternalServerError().body("Failed to receive signing result"),
-    }
+            for entry in digests.into_iter().flatten() {
+                entries.push((entry.name, hex::decode(&entry.sha256)?));
+            }
+            let sums = openpgp::sha256sums(&entries)?;
+            let output_dir = match (&output_dir, paths.first()) {
+                (Some(output_dir), _) => PathBuf::from(output_dir),
+                (None, Some(first)) => Path::new(first)
+                    .parent()
+                    .ok_or("The first path has no directory")?
+                    .to_path_buf(),
+                (None, None) => return Err("An output directory is needed".into()),
+            };
+            let output = output_dir.join("SHA256SUMS");
+            std::fs::write(&output, sums)?;
+            write_openpgp_signature(&app, &history, &output, &cert_id, &pin)
+        };
+        write_sums()
+            .map(|output| output.to_string_lossy().into_owned())
+            .map_err(|e| AgentError::from_boxed(e).to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// The armored OpenPGP public key for a token certificate. The token
+/// self-signs the user ID, so the PIN is needed.
+#[tauri::command]
+async fn export_openpgp_public_key(
+    app: AppHandle,
+    cert_id: String,
+    pin: String,
+) -> Result<String, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        with_openpgp_key(&app, &cert_id, &pin, None, |key, cert_der| {
+            key.export(cert_der)
+        })
+        .map_err(|e| AgentError::from_boxed(e).to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
 }
 
 #[tauri::command]
@@ -336,14 +3159,14 @@ fn sign_hash(
     hash: String,
 ) -> Result<String, String> {
     let hash_bytes = hash.as_bytes();
-    match sign_hash_wrapper(app, &user_pin, cert_hash, &hash_bytes) {
+    match sign_hash_wrapper(app, &user_pin, cert_hash, hash_bytes) {
         Ok(signature) => Ok(signature),
-        Err(e) => Err(e.to_string()),
+        Err(e) => Err(AgentError::from_boxed(e).to_string()),
     }
 }
 
 pub fn get_public_key_str(app: AppHandle) -> Result<String, Box<dyn Error>> {
-    let resource_directory: PathBuf = app.path().resource_dir().unwrap();
+    let resource_directory: PathBuf = app.path().resource_dir()?;
 
     let mut pkcs11_lib_path = resource_directory.join("pcks11");
     pkcs11_lib_path = pkcs11_lib_path.join("gov_smart.pub");
@@ -357,8 +3180,17 @@ pub fn get_public_key_str(app: AppHandle) -> Result<String, Box<dyn Error>> {
     Ok(public_key)
 }
 
+/// The PKCS#11 library is initialized once and kept for the lifetime of the
+/// agent, so a signing session can outlive the request that opened it.
+static PKCS11: Mutex<Option<Pkcs11>> = Mutex::new(None);
+
 pub fn get_pkcs_11(app: AppHandle) -> Result<Pkcs11, Box<dyn Error>> {
-    let resource_directory: PathBuf = app.path().resource_dir().unwrap();
+    let mut cached = PKCS11.lock().map_err(|_| "PKCS#11 lock poisoned")?;
+    if let Some(pkcs11) = cached.as_ref() {
+        return Ok(pkcs11.clone());
+    }
+
+    let resource_directory: PathBuf = app.path().resource_dir()?;
 
     let mut pkcs11_lib_path = resource_directory.join("pcks11");
     if cfg!(target_os = "linux") {
@@ -375,6 +3207,7 @@ pub fn get_pkcs_11(app: AppHandle) -> Result<Pkcs11, Box<dyn Error>> {
 
     let pkcs11 = Pkcs11::new(&pkcs11_lib_path)?;
     pkcs11.initialize(CInitializeArgs::OsThreads)?;
+    *cached = Some(pkcs11.clone());
     Ok(pkcs11)
 }
 
@@ -386,7 +3219,7 @@ pub fn sign_hash_wrapper(
 ) -> Result<String, Box<dyn Error>> {
     let pkcs11 = get_pkcs_11(app)?;
     let slots = pkcs11.get_slots_with_token()?;
-    let slot = slots.get(0).ok_or("No token slot available")?;
+    let slot = slots.first().ok_or(AgentError::TokenAbsent)?;
     let cert_der = hex::decode(cert_hash)?;
     let signature = sign_hash_with_cert(&pkcs11, *slot, user_pin, &cert_der, hash)?;
     Ok(hex::encode(signature))
@@ -399,13 +3232,77 @@ fn sign_hash_with_cert(
     cert_der: &[u8],
     hash: &[u8],
 ) -> Result<Vec<u8>, Box<dyn Error>> {
+    let session = open_signing_session(pkcs11, slot, user_pin)?;
+    sign_in_session(&session, cert_der, hash)
+}
+
+/// Opens a session on `slot` and logs the user in.
+fn open_signing_session(
+    pkcs11: &Pkcs11,
+    slot: Slot,
+    user_pin: &str,
+) -> Result<Session, Box<dyn Error>> {
     let session = pkcs11.open_rw_session(slot)?;
-    session.login(UserType::User, Some(&AuthPin::new(user_pin.into())))?;
+    // PIN pad readers collect the PIN themselves and reject one sent by the
+    // host.
+    let pin = if pkcs11.get_token_info(slot)?.protected_authentication_path() {
+        None
+    } else {
+        Some(AuthPin::new(user_pin.into()))
+    };
+    match session.login(UserType::User, pin.as_ref()) {
+        // Login state is shared by every session on the token, so a kept
+        // signing session makes this one logged in already. Log out and in
+        // again so the PIN the user just entered is still checked; the kept
+        // session is dropped on its next use.
+        Err(Pkcs11Error::Pkcs11(RvError::UserAlreadyLoggedIn, _)) => {
+            session.logout()?;
+            session.login(UserType::User, pin.as_ref())?;
+        }
+        result => result?,
+    }
+    Ok(session)
+}
+
+/// Whether the connected token's PIN is entered on the reader rather than
+/// in the agent.
+fn token_has_pin_pad(app: AppHandle) -> bool {
+    let Ok(pkcs11) = get_pkcs_11(app) else {
+        return false;
+    };
+    pkcs11
+        .get_slots_with_token()
+        .ok()
+        .and_then(|slots| slots.first().copied())
+        .and_then(|slot| pkcs11.get_token_info(slot).ok())
+        .is_some_and(|info| info.protected_authentication_path())
+}
+
+#[tauri::command]
+fn uses_pin_pad(app: AppHandle) -> bool {
+    token_has_pin_pad(app)
+}
+
+/// Signs with the private key matching `cert_der` in a logged-in session.
+fn sign_in_session(
+    session: &Session,
+    cert_der: &[u8],
+    hash: &[u8],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    sign_in_session_with_padding(session, cert_der, hash, RsaPadding::Pkcs1v15)
+}
 
+/// Like `sign_in_session`, with RSA keys padding as `padding` says.
+fn sign_in_session_with_padding(
+    session: &Session,
+    cert_der: &[u8],
+    hash: &[u8],
+    padding: RsaPadding,
+) -> Result<Vec<u8>, Box<dyn Error>> {
     let cert_template = vec![Attribute::Class(ObjectClass::CERTIFICATE)];
     let cert_objs = session.find_objects(&cert_template)?;
     if cert_objs.is_empty() {
-        return Err("No certificate objects found on the token".into());
+        return Err(AgentError::CertificateNotFound.into());
     }
 
     let mut cert_id: Option<Vec<u8>> = None;
@@ -429,7 +3326,7 @@ fn sign_hash_with_cert(
         }
     }
 
-    let cert_id = cert_id.ok_or("Certificate matching provided DER not found or missing CKA_ID")?;
+    let cert_id = cert_id.ok_or(AgentError::CertificateNotFound)?;
 
     let priv_template = vec![
         Attribute::Class(ObjectClass::PRIVATE_KEY),
@@ -437,7 +3334,7 @@ fn sign_hash_with_cert(
     ];
     let priv_objs = session.find_objects(&priv_template)?;
     if priv_objs.is_empty() {
-        return Err("No matching private key found on the token".into());
+        return Err(AgentError::KeyNotFound.into());
     }
     let priv_handle = priv_objs[0];
 
@@ -454,9 +3351,15 @@ fn sign_hash_with_cert(
         .ok_or("Private key does not have a KeyType attribute")?;
 
     let mechanism = match key_type {
+        KeyType::RSA if padding == RsaPadding::Pss => Mechanism::Sha256RsaPkcsPss(PkcsPssParams {
+            hash_alg: MechanismType::SHA256,
+            mgf: PkcsMgfType::MGF1_SHA256,
+            s_len: 32.into(),
+        }),
+        KeyType::RSA if padding == RsaPadding::Pkcs1v15Raw => Mechanism::RsaPkcs,
         KeyType::RSA => Mechanism::Sha256RsaPkcs,
         KeyType::EC => Mechanism::Ecdsa,
-        _ => return Err("Unsupported key type for signing".into()),
+        _ => return Err(AgentError::UnsupportedKeyType.into()),
     };
 
     println!("Signing hash with mechanism: {:?}", mechanism);
@@ -467,42 +3370,337 @@ fn sign_hash_with_cert(
     Ok(signature)
 }
 
+/// Describes the signing request the PIN popup was opened for.
+#[tauri::command]
+fn get_pending_request(
+    app: AppHandle,
+    state: tauri::State<Arc<SigningState>>,
+) -> Result<Option<PendingRequest>, String> {
+    let session_minutes = app_data_dir(&app)
+        .and_then(|data_dir| settings::load(&data_dir))
+        .map(|settings| settings.session_minutes)
+        .unwrap_or(0);
+    let pin_pad = token_has_pin_pad(app);
+    let slot = lock_pending(&state.current_request).map_err(|e| e.to_string())?;
+    Ok(slot.as_ref().map(|req| PendingRequest {
+        origin: req.origin.clone(),
+        document_name: req.document_name.clone(),
+        doc_hash: req.doc_hash.clone(),
+        hash_algorithm: req.hash_algorithm.clone(),
+        certificate: hex::decode(&req.cert_hash)
+            .ok()
+            .and_then(|cert_der| CertificateSummary::from_der(&cert_der).ok()),
+        preview: req.preview.clone(),
+        session_minutes,
+        pin_pad,
+        chain: req.chain.clone(),
+    }))
+}
+
+/// Signs every digest of a batch in one logged-in session, handing the
+/// session back so it can be kept. Each envelope nonce is claimed only once
+/// the PIN has been accepted. Fails as a whole only if the token cannot be
+/// opened or the PIN is rejected.
+fn sign_batch_with_cert(
+    app: AppHandle,
+    state: &SigningState,
+    user_pin: &str,
+    cert_hash: &str,
+    items: &[PendingBatchItem],
+) -> Result<(BatchResults, Session), Box<dyn Error>> {
+    let pkcs11 = get_pkcs_11(app)?;
+    let slots = pkcs11.get_slots_with_token()?;
+    let slot = slots.first().ok_or(AgentError::TokenAbsent)?;
+    let cert_der = hex::decode(cert_hash)?;
+    let session = open_signing_session(&pkcs11, *slot, user_pin)?;
+    let results = items
+        .iter()
+        .map(|item| {
+            claim_envelope_nonce(state, item.envelope.as_ref())?;
+            sign_in_session(&session, &cert_der, item.doc_hash.as_bytes())
+                .map(hex::encode)
+                .map_err(AgentError::from_boxed)
+        })
+        .collect();
+    Ok((results, session))
+}
+
+/// Describes the documents the batch popup was opened for.
+#[tauri::command]
+fn get_pending_batch(
+    app: AppHandle,
+    state: tauri::State<Arc<SigningState>>,
+) -> Result<Option<PendingBatch>, String> {
+    let session_minutes = app_data_dir(&app)
+        .and_then(|data_dir| settings::load(&data_dir))
+        .map(|settings| settings.session_minutes)
+        .unwrap_or(0);
+    let pin_pad = token_has_pin_pad(app);
+    let slot = lock_pending(&state.current_batch).map_err(|e| e.to_string())?;
+    Ok(slot.as_ref().map(|req| PendingBatch {
+        origin: req.origin.clone(),
+        certificate: hex::decode(&req.cert_hash)
+            .ok()
+            .and_then(|cert_der| CertificateSummary::from_der(&cert_der).ok()),
+        items: req.items.clone(),
+        chain: req.chain.clone(),
+        session_minutes,
+        pin_pad,
+    }))
+}
+
 #[tauri::command]
-fn complete_signing(
+async fn complete_batch_signing(
     app: AppHandle,
     window: tauri::Window,
     pin: String,
-    state: tauri::State<Arc<SigningState>>,
+    keep_unlocked: Option<bool>,
+    state: tauri::State<'_, Arc<SigningState>>,
+    sessions: tauri::State<'_, Arc<SessionManager>>,
 ) -> Result<(), String> {
-    let maybe_data = {
-        let req_lock = state.current_request.lock().unwrap();
-        req_lock
-            .as_ref()
-            .map(|req| (req.cert_hash.clone(), req.doc_hash.clone()))
+    let (cert_hash, items, origin) = lock_pending(&state.current_batch)
+        .map_err(|e| e.to_string())?
+        .as_ref()
+        .map(|req| (req.cert_hash.clone(), req.items.clone(), req.origin.clone()))
+        .ok_or("No batch signing request pending")?;
+
+    let (results, session) = {
+        let (app, state, cert_hash) = (app.clone(), state.inner().clone(), cert_hash.clone());
+        tauri::async_runtime::spawn_blocking(move || {
+            sign_batch_with_cert(app, &state, &pin, &cert_hash, &items)
+                .map_err(|e| AgentError::from_boxed(e).to_string())
+        })
+        .await
+        .map_err(|e| e.to_string())??
     };
 
-    if let Some((cert_hash, doc_hash)) = maybe_data {
-        match sign_hash(app, pin, cert_hash, doc_hash) {
-            Ok(signature) => {
-                let req = {
-                    let mut req_lock = state.current_request.lock().unwrap();
-                    req_lock.take().expect("Request should be present")
-                };
-                req.response_tx
-                    .send(Ok(signature))
-                    .map_err(|_| "Failed to send signature response".to_string())?;
-                window
-                    .close()
-                    .map_err(|_| "Failed to close window".to_string())?;
-                Ok(())
-            }
-            Err(e) => Err(e.to_string()),
+    if keep_unlocked.unwrap_or(false) {
+        keep_session_unlocked(&app, &sessions, session, origin, cert_hash)?;
+    }
+
+    let req = lock_pending(&state.current_batch)
+        .map_err(|e| e.to_string())?
+        .take()
+        .ok_or("No batch signing request pending")?;
+    req.response_tx
+        .send(Ok(results))
+        .map_err(|_| "Failed to send batch signing response".to_string())?;
+    window
+        .close()
+        .map_err(|_| "Failed to close window".to_string())?;
+    Ok(())
+}
+
+/// Keeps a logged-in token session open for later requests from `origin`,
+/// if signing sessions are enabled.
+fn keep_session_unlocked(
+    app: &AppHandle,
+    sessions: &SessionManager,
+    session: Session,
+    origin: Option<String>,
+    cert_hash: String,
+) -> Result<(), String> {
+    let settings = app_data_dir(app)
+        .and_then(|data_dir| settings::load(&data_dir))
+        .map_err(|e| e.to_string())?;
+    if settings.session_minutes > 0 {
+        let session = SigningSession::new(
+            session,
+            origin,
+            cert_hash,
+            settings.session_minutes,
+            settings.session_max_signatures,
+            chrono::Utc::now(),
+        );
+        sessions.open(session).map_err(|e| e.to_string())?;
+        refresh_session_indicator(app, sessions);
+    }
+    Ok(())
+}
+
+/// Signs `hash` like `sign_hash_wrapper`, handing back the logged-in
+/// session so it can be kept for later requests.
+fn sign_hash_in_new_session(
+    app: AppHandle,
+    user_pin: &str,
+    cert_hash: &str,
+    token_input: &TokenInput,
+) -> Result<(String, Session), Box<dyn Error>> {
+    let pkcs11 = get_pkcs_11(app)?;
+    let slots = pkcs11.get_slots_with_token()?;
+    let slot = slots.first().ok_or(AgentError::TokenAbsent)?;
+    let cert_der = hex::decode(cert_hash)?;
+    let session = open_signing_session(&pkcs11, *slot, user_pin)?;
+    let signature =
+        sign_in_session_with_padding(&session, &cert_der, &token_input.data, token_input.padding)?;
+    Ok((hex::encode(signature), session))
+}
+
+#[tauri::command]
+async fn complete_signing(
+    app: AppHandle,
+    window: tauri::Window,
+    pin: String,
+    keep_unlocked: Option<bool>,
+    state: tauri::State<'_, Arc<SigningState>>,
+    sessions: tauri::State<'_, Arc<SessionManager>>,
+) -> Result<(), String> {
+    let (cert_hash, token_input, origin) = lock_pending(&state.current_request)
+        .map_err(|e| e.to_string())?
+        .as_ref()
+        .map(|req| {
+            (
+                req.cert_hash.clone(),
+                req.token_input.clone(),
+                req.origin.clone(),
+            )
+        })
+        .ok_or("No signing request pending")?;
+
+    let (signature, session) = {
+        let (app, cert_hash) = (app.clone(), cert_hash.clone());
+        tauri::async_runtime::spawn_blocking(move || {
+            sign_hash_in_new_session(app, &pin, &cert_hash, &token_input)
+                .map_err(|e| AgentError::from_boxed(e).to_string())
+        })
+        .await
+        .map_err(|e| e.to_string())??
+    };
+
+    if keep_unlocked.unwrap_or(false) {
+        keep_session_unlocked(&app, &sessions, session, origin, cert_hash)?;
+    }
+
+    // The request may have timed out while the user entered the PIN.
+    let req = lock_pending(&state.current_request)
+        .map_err(|e| e.to_string())?
+        .take()
+        .ok_or("No signing request pending")?;
+    req.response_tx
+        .send(Ok(signature))
+        .map_err(|_| "Failed to send signature response".to_string())?;
+    window
+        .close()
+        .map_err(|_| "Failed to close window".to_string())?;
+    Ok(())
+}
+
+const TRAY_ID: &str = "main";
+
+/// How often the active signing session is checked for expiry, token
+/// removal and screen lock.
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Shows in the tray whether a signing session keeps the token unlocked.
+fn refresh_session_indicator(app: &AppHandle, sessions: &SessionManager) {
+    let Some(tray) = app.tray_by_id(TRAY_ID) else {
+        return;
+    };
+    match sessions.status() {
+        Some(status) => {
+            let until = chrono::DateTime::parse_from_rfc3339(&status.expires_at)
+                .map(|time| {
+                    time.with_timezone(&chrono::Local)
+                        .format("%H:%M")
+                        .to_string()
+                })
+                .unwrap_or_default();
+            let _ = tray.set_tooltip(Some(format!(
+                "Gov-Smart: token unlocked for {} until {}",
+                status.origin.as_deref().unwrap_or("this computer"),
+                until
+            )));
+            let _ = tray.set_title(Some("Unlocked"));
+        }
+        None => {
+            let _ = tray.set_tooltip(Some("Gov-Smart"));
+            let _ = tray.set_title(None::<&str>);
+        }
+    }
+}
+
+/// Ends the signing session once it expires, its token is removed, the
+/// screen is locked or the computer wakes from sleep.
+async fn watch_signing_session(app: AppHandle, sessions: Arc<SessionManager>) {
+    let mut last_check = (Instant::now(), chrono::Utc::now());
+    loop {
+        tokio::time::sleep(SESSION_CHECK_INTERVAL).await;
+        let now = chrono::Utc::now();
+        // Monotonic time stands still while the computer sleeps, wall-clock
+        // time does not.
+        let wall_elapsed = (now - last_check.1).num_seconds();
+        let slept = wall_elapsed - last_check.0.elapsed().as_secs() as i64 > 30;
+        last_check = (Instant::now(), now);
+
+        if sessions.status().is_none() {
+            continue;
+        }
+        let force = slept || session::screen_locked();
+        if sessions.expire(now, force) {
+            refresh_session_indicator(&app, &sessions);
         }
-    } else {
-        Err("No signing request pending".into())
     }
 }
 
+/// Signs `hash` in the signing session `origin` opened for `cert_hash`, if
+/// there is one.
+fn sign_with_session(
+    app: &AppHandle,
+    sessions: &SessionManager,
+    origin: Option<&str>,
+    cert_hash: &str,
+    token_input: &TokenInput,
+) -> Option<Result<String, AgentError>> {
+    let cert_der = hex::decode(cert_hash).ok()?;
+    let result = sessions.sign(origin, cert_hash, chrono::Utc::now(), |session| {
+        sign_in_session_with_padding(session, &cert_der, &token_input.data, token_input.padding)
+    })?;
+    refresh_session_indicator(app, sessions);
+    Some(result.map(hex::encode).map_err(AgentError::from_boxed))
+}
+
+/// Reports the signing session, if the requesting origin is the one that
+/// opened it.
+#[get("/session")]
+async fn session_status_route(
+    http_req: HttpRequest,
+    sessions: web::Data<Arc<SessionManager>>,
+) -> HttpResponse {
+    match sessions.status_for(request_origin(&http_req)) {
+        Some(status) => {
+            HttpResponse::Ok().json(serde_json::json!({ "active": true, "session": status }))
+        }
+        None => HttpResponse::Ok().json(serde_json::json!({ "active": false })),
+    }
+}
+
+/// Ends the signing session, if the requesting origin is the one that
+/// opened it.
+#[delete("/session")]
+async fn lock_session_route(
+    http_req: HttpRequest,
+    sessions: web::Data<Arc<SessionManager>>,
+    app_handle: web::Data<AppHandle>,
+) -> HttpResponse {
+    let locked = sessions.lock_for(request_origin(&http_req));
+    refresh_session_indicator(&app_handle, &sessions);
+    HttpResponse::Ok().json(serde_json::json!({ "locked": locked }))
+}
+
+#[tauri::command]
+fn get_signing_session(
+    sessions: tauri::State<Arc<SessionManager>>,
+) -> Option<session::SessionStatus> {
+    sessions.status()
+}
+
+#[tauri::command]
+fn lock_signing_session(app: AppHandle, sessions: tauri::State<Arc<SessionManager>>) -> bool {
+    let locked = sessions.lock();
+    refresh_session_indicator(&app, &sessions);
+    locked
+}
+
 async fn update(app: tauri::AppHandle) -> tauri_plugin_updater::Result<()> {
     if let Some(update) = app.updater()?.check().await? {
         let mut downloaded = 0;
@@ -529,32 +3727,67 @@ async fn update(app: tauri::AppHandle) -> tauri_plugin_updater::Result<()> {
 pub fn run() {
     let signing_state = Arc::new(SigningState {
         current_request: Mutex::new(None),
+        current_batch: Mutex::new(None),
+        used_nonces: NonceCache::default(),
     });
 
     let certificate_state = Arc::new(CertificateState {
         current_request: Mutex::new(None),
     });
 
+    let sessions = Arc::new(SessionManager::default());
+
     tauri::Builder::default()
         .plugin(tauri_plugin_autostart::init(
             tauri_plugin_autostart::MacosLauncher::LaunchAgent,
             None,
         ))
         .plugin(tauri_plugin_updater::Builder::new().build())
+        .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_log::Builder::new().build())
         .manage(signing_state.clone())
         .manage(certificate_state.clone())
+        .manage(sessions.clone())
         .invoke_handler(tauri::generate_handler![
             sign_hash,
+            get_pending_request,
             complete_signing,
+            get_signing_session,
+            uses_pin_pad,
+            lock_signing_session,
+            get_pending_batch,
+            complete_batch_signing,
             complete_certificate,
+            get_settings,
+            update_settings,
+            get_audit_log,
+            verify_audit_log,
+            search_signature_history,
+            delete_signature_history_entry,
+            export_signature_history_entry,
+            choose_document,
+            verify_merkle_proof,
+            sign_file_detached,
+            sign_pdf,
+            create_asic_container,
+            sign_email,
+            sign_file_openpgp,
+            sign_checksums_openpgp,
+            export_openpgp_public_key,
+            list_signers,
+            cosign_file,
+            countersign_file,
         ])
         .on_window_event(|window, event| {
+            if let tauri::WindowEvent::Destroyed = event {
+                cancel_popup_request(window);
+            }
             if let tauri::WindowEvent::CloseRequested { api, .. } = event {
                 // only if it's the main window
                 if window.label() != "main" {
                     return;
                 }
-                window.hide().unwrap();
+                let _ = window.hide();
                 api.prevent_close();
             }
         })
@@ -564,13 +3797,27 @@ pub fn run() {
             let app_handle_data = web::Data::new(app_handle.clone());
             let certificate_state_data = certificate_state.clone();
 
+            let audit_log = Arc::new(AuditLog::new(&app_data_dir(app_handle)?));
+            app.manage(audit_log.clone());
+            let signature_history = Arc::new(SignatureHistory::open(&app_data_dir(app_handle)?)?);
+            app.manage(signature_history.clone());
+
             let quit = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
             let show = MenuItem::with_id(app, "show", "Show", true, None::<&str>)?;
-            let tray_menu = Menu::with_items(app, &[&quit, &show])?;
+            let lock = MenuItem::with_id(
+                app,
+                "lock_session",
+                "Lock signing session",
+                true,
+                None::<&str>,
+            )?;
+            let tray_menu = Menu::with_items(app, &[&quit, &show, &lock])?;
 
             let handle_clone = app.handle().clone();
             tauri::async_runtime::spawn(async move {
-                update(handle_clone).await.unwrap();
+                if let Err(e) = update(handle_clone).await {
+                    log::warn!("Update check failed: {}", e);
+                }
             });
 
             let _icon_image = if cfg!(target_os = "windows") {
@@ -591,7 +3838,7 @@ pub fn run() {
                 tauri::image::Image::new_owned(icon_data, 32, 32)
             };
 
-            let _ = TrayIconBuilder::new()
+            let _ = TrayIconBuilder::with_id(TRAY_ID)
                 .icon(app.default_window_icon().unwrap().clone())
                 .icon_as_template(false)
                 .menu(&tray_menu)
@@ -604,30 +3851,69 @@ pub fn run() {
                     "show" => {
                         println!("show menu item was clicked");
                         if let Some(window) = app.get_webview_window("main") {
-                            window.show().unwrap();
+                            let _ = window.show();
                         }
                     }
+                    "lock_session" => {
+                        let sessions = app.state::<Arc<SessionManager>>();
+                        sessions.lock();
+                        refresh_session_indicator(app, &sessions);
+                    }
                     _ => {}
                 })
                 .build(app);
 
+            tauri::async_runtime::spawn(watch_signing_session(
+                app.handle().clone(),
+                sessions.clone(),
+            ));
+            let sessions_data = sessions.clone();
+
             tauri::async_runtime::spawn(async move {
-                HttpServer::new(move || {
+                let server = match HttpServer::new(move || {
                     let cors = Cors::permissive();
                     App::new()
                         .app_data(web::Data::new(signing_state_data.clone()))
                         .app_data(web::Data::new(certificate_state_data.clone()))
+                        .app_data(web::Data::new(audit_log.clone()))
+                        .app_data(web::Data::new(signature_history.clone()))
+                        .app_data(web::Data::new(sessions_data.clone()))
                         .app_data(app_handle_data.clone())
+                        .app_data(web::JsonConfig::default().error_handler(error::invalid_request))
+                        .app_data(web::PathConfig::default().error_handler(error::invalid_request))
+                        .app_data(web::QueryConfig::default().error_handler(error::invalid_request))
                         .service(sign_document)
+                        .service(sign_batch)
+                        .service(sign_merkle)
+                        .service(sign_content)
+                        .service(sign_local_file)
+                        .service(prepare_pdf_route)
+                        .service(complete_pdf_route)
+                        .service(create_asic_route)
+                        .service(sign_jws_route)
+                        .service(verify_jws_route)
+                        .service(sign_cose_route)
+                        .service(verify_cose_route)
                         .service(get_certificate_route)
                         .service(list_certificates_route)
+                        .service(update_keyring_route)
+                        .service(audit_route)
+                        .service(validate_certificate_route)
+                        .service(session_status_route)
+                        .service(lock_session_route)
                         .wrap(cors)
                 })
                 .bind("127.0.0.1:8811")
-                .expect("Cannot bind to 127.0.0.1:8811")
-                .run()
-                .await
-                .expect("HTTP server failed");
+                {
+                    Ok(server) => server.run(),
+                    Err(e) => {
+                        log::error!("Cannot bind to 127.0.0.1:8811: {}", e);
+                        return;
+                    }
+                };
+                if let Err(e) = server.await {
+                    log::error!("HTTP server failed: {}", e);
+                }
             });
             Ok(())
         })
@@ -0,0 +1,199 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+
+/// Most digests a single Merkle batch may contain.
+pub const MAX_LEAVES: usize = 100_000;
+
+type Node = [u8; 32];
+
+/// Which side of the running hash a proof step's sibling sits on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProofStep {
+    pub side: Side,
+    /// Hex-encoded sibling node.
+    pub hash: String,
+}
+
+// Leaves and inner nodes are hashed with distinct prefixes, as in RFC 6962,
+// so an inner node can never be passed off as a leaf.
+fn leaf_hash(digest: &[u8]) -> Node {
+    Sha256::new()
+        .chain_update([0x00])
+        .chain_update(digest)
+        .finalize()
+        .into()
+}
+
+fn node_hash(left: &Node, right: &Node) -> Node {
+    Sha256::new()
+        .chain_update([0x01])
+        .chain_update(left)
+        .chain_update(right)
+        .finalize()
+        .into()
+}
+
+/// SHA-256 Merkle tree over document digests. A node without a sibling is
+/// carried up to the next level unchanged.
+pub struct MerkleTree {
+    levels: Vec<Vec<Node>>,
+}
+
+impl MerkleTree {
+    pub fn build(digests: &[Vec<u8>]) -> Result<Self, Box<dyn Error>> {
+        if digests.is_empty() || digests.len() > MAX_LEAVES {
+            return Err(format!(
+                "A Merkle batch must contain between 1 and {} digests",
+                MAX_LEAVES
+            )
+            .into());
+        }
+
+        let mut levels = vec![digests
+            .iter()
+            .map(|digest| leaf_hash(digest))
+            .collect::<Vec<_>>()];
+        while levels[levels.len() - 1].len() > 1 {
+            let next = levels[levels.len() - 1]
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => node_hash(left, right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+        Ok(MerkleTree { levels })
+    }
+
+    pub fn root(&self) -> Node {
+        self.levels[self.levels.len() - 1][0]
+    }
+
+    /// Sibling nodes from the leaf at `index` up to the root.
+    pub fn proof(&self, mut index: usize) -> Vec<ProofStep> {
+        let mut proof = Vec::new();
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = index ^ 1;
+            if let Some(node) = level.get(sibling) {
+                proof.push(ProofStep {
+                    side: if sibling < index {
+                        Side::Left
+                    } else {
+                        Side::Right
+                    },
+                    hash: hex::encode(node),
+                });
+            }
+            index /= 2;
+        }
+        proof
+    }
+}
+
+/// Recomputes the root a leaf digest and its proof lead to.
+pub fn root_from_proof(digest: &[u8], proof: &[ProofStep]) -> Result<Node, Box<dyn Error>> {
+    let mut node = leaf_hash(digest);
+    for step in proof {
+        let sibling: Node = hex::decode(&step.hash)?
+            .try_into()
+            .map_err(|_| "Proof hashes must be 32 bytes")?;
+        node = match step.side {
+            Side::Left => node_hash(&sibling, &node),
+            Side::Right => node_hash(&node, &sibling),
+        };
+    }
+    Ok(node)
+}
+
+/// A leaf's digest with the proof linking it to the signed root.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeafProof {
+    pub index: usize,
+    /// Hex-encoded document digest.
+    pub digest: String,
+    pub proof: Vec<ProofStep>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digests(count: usize) -> Vec<Vec<u8>> {
+        (0..count)
+            .map(|i| Sha256::digest(i.to_be_bytes()).to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn single_leaf_is_its_own_root() {
+        let leaves = digests(1);
+        let tree = MerkleTree::build(&leaves).unwrap();
+        assert_eq!(tree.root(), leaf_hash(&leaves[0]));
+        assert!(tree.proof(0).is_empty());
+    }
+
+    #[test]
+    fn root_of_two_leaves() {
+        let leaves = digests(2);
+        let tree = MerkleTree::build(&leaves).unwrap();
+        let expected = node_hash(&leaf_hash(&leaves[0]), &leaf_hash(&leaves[1]));
+        assert_eq!(tree.root(), expected);
+    }
+
+    #[test]
+    fn odd_leaf_is_carried_up() {
+        let leaves = digests(3);
+        let tree = MerkleTree::build(&leaves).unwrap();
+        let left = node_hash(&leaf_hash(&leaves[0]), &leaf_hash(&leaves[1]));
+        assert_eq!(tree.root(), node_hash(&left, &leaf_hash(&leaves[2])));
+
+        let proof = tree.proof(2);
+        assert_eq!(proof.len(), 1);
+        assert_eq!(proof[0].side, Side::Left);
+        assert_eq!(proof[0].hash, hex::encode(left));
+    }
+
+    #[test]
+    fn every_proof_leads_to_the_root() {
+        for count in [1, 2, 3, 5, 8, 13] {
+            let leaves = digests(count);
+            let tree = MerkleTree::build(&leaves).unwrap();
+            for (index, digest) in leaves.iter().enumerate() {
+                let root = root_from_proof(digest, &tree.proof(index)).unwrap();
+                assert_eq!(root, tree.root(), "leaf {} of {}", index, count);
+            }
+        }
+    }
+
+    #[test]
+    fn proof_does_not_fit_another_leaf() {
+        let leaves = digests(4);
+        let tree = MerkleTree::build(&leaves).unwrap();
+        let root = root_from_proof(&leaves[1], &tree.proof(0)).unwrap();
+        assert_ne!(root, tree.root());
+    }
+
+    #[test]
+    fn rejects_empty_batch() {
+        assert!(MerkleTree::build(&[]).is_err());
+    }
+
+    #[test]
+    fn rejects_short_proof_hash() {
+        let proof = [ProofStep {
+            side: Side::Right,
+            hash: "00".to_string(),
+        }];
+        assert!(root_from_proof(&[0; 32], &proof).is_err());
+    }
+}
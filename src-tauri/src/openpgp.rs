@@ -0,0 +1,406 @@
+use crate::certificate;
+use chrono::{DateTime, SubsecRound, Utc};
+use pgp::crypto::ecc_curve::ECCCurve;
+use pgp::crypto::hash::HashAlgorithm;
+use pgp::crypto::public_key::PublicKeyAlgorithm;
+use pgp::packet::{
+    self, KeyFlags, SignatureConfig, SignatureType, Subpacket, SubpacketData, UserId,
+};
+use pgp::types::{
+    EcdsaPublicParams, EskType, Fingerprint, KeyId, KeyVersion, Mpi, PkeskBytes, PublicKeyTrait,
+    PublicParams, SecretKeyTrait, SignatureBytes, SignedUser, Tag, Version,
+};
+use pgp::{ArmorOptions, SignedKeyDetails, SignedPublicKey, StandaloneSignature};
+use rand::{CryptoRng, Rng};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::traits::PublicKeyParts;
+use std::error::Error;
+use std::io::Read;
+use x509_cert::der::oid::db::rfc5912;
+use x509_cert::der::{Decode, Encode};
+use x509_cert::ext::pkix::name::GeneralName;
+use x509_cert::ext::pkix::SubjectAltName;
+use x509_cert::Certificate;
+
+/// DER prefix of a SHA-256 `DigestInfo`, which the token's raw PKCS#1 v1.5
+/// mechanism needs in front of an OpenPGP signature hash.
+const SHA256_DIGEST_INFO: &[u8] = &[
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05,
+    0x00, 0x04, 0x20,
+];
+
+/// Signs on behalf of a `TokenKey`: given the bytes the token should sign
+/// (a `DigestInfo` for RSA, the bare digest for ECDSA), returns the raw
+/// signature.
+pub type TokenSigner<'a> = dyn Fn(&[u8]) -> Result<Vec<u8>, Box<dyn Error>> + 'a;
+
+/// An OpenPGP v4 signing key whose private half stays on the token. The
+/// key's creation time is the certificate's `notBefore`, so the same
+/// certificate always yields the same fingerprint.
+pub struct TokenKey<'a> {
+    public: packet::PublicKey,
+    not_after: DateTime<Utc>,
+    sign: &'a TokenSigner<'a>,
+}
+
+fn certificate_time(time: x509_cert::time::Time) -> Result<DateTime<Utc>, Box<dyn Error>> {
+    DateTime::from_timestamp(time.to_unix_duration().as_secs() as i64, 0)
+        .ok_or_else(|| "Certificate time out of range".into())
+}
+
+fn public_params(cert: &Certificate) -> Result<(PublicKeyAlgorithm, PublicParams), Box<dyn Error>> {
+    let spki = &cert.tbs_certificate.subject_public_key_info;
+    let key = spki.subject_public_key.raw_bytes();
+    if spki.algorithm.oid == rfc5912::RSA_ENCRYPTION {
+        let key = rsa::RsaPublicKey::from_public_key_der(&spki.to_der()?)?;
+        let params = PublicParams::RSA {
+            n: Mpi::from_slice(&key.n().to_bytes_be()),
+            e: Mpi::from_slice(&key.e().to_bytes_be()),
+        };
+        return Ok((PublicKeyAlgorithm::RSA, params));
+    }
+    if spki.algorithm.oid == rfc5912::ID_EC_PUBLIC_KEY {
+        let curve = spki
+            .algorithm
+            .parameters
+            .as_ref()
+            .ok_or("Elliptic curve parameters are missing")?
+            .decode_as()?;
+        let curve = match curve {
+            rfc5912::SECP_256_R_1 => ECCCurve::P256,
+            rfc5912::SECP_384_R_1 => ECCCurve::P384,
+            _ => return Err(format!("Unsupported elliptic curve {}", curve).into()),
+        };
+        let point = Mpi::from_slice(key);
+        let params = EcdsaPublicParams::try_from_mpi(point.as_ref(), curve)?;
+        return Ok((PublicKeyAlgorithm::ECDSA, PublicParams::ECDSA(params)));
+    }
+    Err(format!(
+        "Unsupported certificate key algorithm {}",
+        spki.algorithm.oid
+    )
+    .into())
+}
+
+/// The `Name <email>` user ID for the certificate's subject.
+fn user_id(cert_der: &[u8], cert: &Certificate) -> Result<String, Box<dyn Error>> {
+    let name = certificate::subject_name(cert_der)?;
+    let email = cert
+        .tbs_certificate
+        .get::<SubjectAltName>()?
+        .and_then(|(_, names)| {
+            names.0.into_iter().find_map(|name| match name {
+                GeneralName::Rfc822Name(email) => Some(email),
+                _ => None,
+            })
+        });
+    Ok(match email {
+        Some(email) => format!("{} <{}>", name, email),
+        None => name,
+    })
+}
+
+/// A `SHA256SUMS` file listing `(name, SHA-256 digest)` pairs in the
+/// format `sha256sum --check` reads. Names `sha256sum` would have to
+/// escape are rejected.
+pub fn sha256sums(entries: &[(String, Vec<u8>)]) -> Result<String, Box<dyn Error>> {
+    if entries.is_empty() {
+        return Err("No files to sign".into());
+    }
+    let mut sums = String::new();
+    for (name, digest) in entries {
+        if name.is_empty() || name.contains(['\n', '\r', '\\']) {
+            return Err(format!("{:?} cannot be listed in SHA256SUMS", name).into());
+        }
+        if digest.len() != 32 {
+            return Err(format!("The digest of {} is not a SHA-256 digest", name).into());
+        }
+        sums.push_str(&format!("{}  {}\n", hex::encode(digest), name));
+    }
+    Ok(sums)
+}
+
+impl<'a> TokenKey<'a> {
+    pub fn new(cert_der: &[u8], sign: &'a TokenSigner<'a>) -> Result<Self, Box<dyn Error>> {
+        let cert = Certificate::from_der(cert_der)?;
+        let validity = &cert.tbs_certificate.validity;
+        let (algorithm, params) = public_params(&cert)?;
+        let public = packet::PublicKey::new(
+            Version::New,
+            KeyVersion::V4,
+            algorithm,
+            certificate_time(validity.not_before)?,
+            None,
+            params,
+        )?;
+        Ok(TokenKey {
+            public,
+            not_after: certificate_time(validity.not_after)?,
+            sign,
+        })
+    }
+
+    fn issuer_subpackets(&self) -> (Vec<Subpacket>, Vec<Subpacket>) {
+        let hashed = vec![
+            Subpacket::regular(SubpacketData::SignatureCreationTime(
+                Utc::now().trunc_subsecs(0),
+            )),
+            Subpacket::regular(SubpacketData::IssuerFingerprint(self.fingerprint())),
+        ];
+        let unhashed = vec![Subpacket::regular(SubpacketData::Issuer(self.key_id()))];
+        (hashed, unhashed)
+    }
+
+    /// An armored detached signature over `data`, as `gpg --detach-sign
+    /// --armor` would make it.
+    pub fn detached_signature(&self, data: impl Read) -> Result<String, Box<dyn Error>> {
+        let mut config =
+            SignatureConfig::v4(SignatureType::Binary, self.algorithm(), self.hash_alg());
+        (config.hashed_subpackets, config.unhashed_subpackets) = self.issuer_subpackets();
+        let signature = config.sign(self, String::new, data)?;
+        Ok(StandaloneSignature::new(signature).to_armored_string(ArmorOptions::default())?)
+    }
+
+    /// The armored public key, with the certificate subject as its user ID
+    /// self-signed by the token and expiring with the certificate.
+    pub fn export(&self, cert_der: &[u8]) -> Result<String, Box<dyn Error>> {
+        let cert = Certificate::from_der(cert_der)?;
+        let id = UserId::from_str(Version::New, &user_id(cert_der, &cert)?);
+
+        let mut flags = KeyFlags::default();
+        flags.set_certify(true);
+        flags.set_sign(true);
+        let lifetime = self.not_after - *self.created_at();
+        let mut config = SignatureConfig::v4(
+            SignatureType::CertPositive,
+            self.algorithm(),
+            self.hash_alg(),
+        );
+        let (mut hashed, unhashed) = self.issuer_subpackets();
+        hashed.extend([
+            Subpacket::regular(SubpacketData::KeyFlags(flags.into())),
+            Subpacket::regular(SubpacketData::KeyExpirationTime(lifetime)),
+            Subpacket::regular(SubpacketData::IsPrimary(true)),
+        ]);
+        (config.hashed_subpackets, config.unhashed_subpackets) = (hashed, unhashed);
+        let signature = config.sign_certification(self, String::new, Tag::UserId, &id)?;
+
+        let details = SignedKeyDetails::new(
+            Vec::new(),
+            Vec::new(),
+            vec![SignedUser::new(id, vec![signature])],
+            Vec::new(),
+        );
+        let key = SignedPublicKey::new(self.public.clone(), details, Vec::new());
+        Ok(key.to_armored_string(ArmorOptions::default())?)
+    }
+}
+
+impl std::fmt::Debug for TokenKey<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenKey")
+            .field("public", &self.public)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PublicKeyTrait for TokenKey<'_> {
+    fn version(&self) -> KeyVersion {
+        self.public.version()
+    }
+
+    fn fingerprint(&self) -> Fingerprint {
+        self.public.fingerprint()
+    }
+
+    fn key_id(&self) -> KeyId {
+        self.public.key_id()
+    }
+
+    fn algorithm(&self) -> PublicKeyAlgorithm {
+        self.public.algorithm()
+    }
+
+    fn created_at(&self) -> &DateTime<Utc> {
+        self.public.created_at()
+    }
+
+    fn expiration(&self) -> Option<u16> {
+        self.public.expiration()
+    }
+
+    fn verify_signature(
+        &self,
+        hash: HashAlgorithm,
+        data: &[u8],
+        sig: &SignatureBytes,
+    ) -> pgp::errors::Result<()> {
+        self.public.verify_signature(hash, data, sig)
+    }
+
+    fn encrypt<R: CryptoRng + Rng>(
+        &self,
+        rng: R,
+        plain: &[u8],
+        typ: EskType,
+    ) -> pgp::errors::Result<PkeskBytes> {
+        self.public.encrypt(rng, plain, typ)
+    }
+
+    fn serialize_for_hashing(&self, writer: &mut impl std::io::Write) -> pgp::errors::Result<()> {
+        self.public.serialize_for_hashing(writer)
+    }
+
+    fn public_params(&self) -> &PublicParams {
+        self.public.public_params()
+    }
+}
+
+impl SecretKeyTrait for TokenKey<'_> {
+    type PublicKey = packet::PublicKey;
+    type Unlocked = ();
+
+    fn unlock<F, G, T>(&self, _pw: F, _work: G) -> pgp::errors::Result<T>
+    where
+        F: FnOnce() -> String,
+        G: FnOnce(&Self::Unlocked) -> pgp::errors::Result<T>,
+    {
+        Err(pgp::errors::Error::Message(
+            "The private key never leaves the token".into(),
+        ))
+    }
+
+    fn create_signature<F>(
+        &self,
+        _key_pw: F,
+        hash: HashAlgorithm,
+        data: &[u8],
+    ) -> pgp::errors::Result<SignatureBytes>
+    where
+        F: FnOnce() -> String,
+    {
+        let token_error = |e: Box<dyn Error>| pgp::errors::Error::Message(e.to_string());
+        match self.public_params() {
+            PublicParams::RSA { .. } => {
+                if hash != HashAlgorithm::SHA2_256 {
+                    return Err(pgp::errors::Error::Message(format!(
+                        "Unsupported hash algorithm {:?} for RSA token keys",
+                        hash
+                    )));
+                }
+                let input = [SHA256_DIGEST_INFO, data].concat();
+                let signature = (self.sign)(&input).map_err(token_error)?;
+                Ok(SignatureBytes::Mpis(vec![Mpi::from_slice(&signature)]))
+            }
+            _ => {
+                let signature = (self.sign)(data).map_err(token_error)?;
+                if signature.is_empty() || !signature.len().is_multiple_of(2) {
+                    return Err(pgp::errors::Error::Message(
+                        "Malformed ECDSA signature from token".into(),
+                    ));
+                }
+                let (r, s) = signature.split_at(signature.len() / 2);
+                Ok(SignatureBytes::Mpis(vec![
+                    Mpi::from_slice(r),
+                    Mpi::from_slice(s),
+                ]))
+            }
+        }
+    }
+
+    fn public_key(&self) -> Self::PublicKey {
+        self.public.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_certs;
+    use p256::ecdsa::signature::hazmat::PrehashSigner;
+    use pgp::Deserializable;
+    use sha2::{Digest, Sha256};
+
+    const DATA: &[u8] = b"release-1.2.3.tar.gz contents";
+
+    /// Exports the key and signs `DATA`, then checks the signature against
+    /// the exported key and the key's self-signature.
+    fn check_round_trip(cert_der: &[u8], sign: &TokenSigner) {
+        let key = TokenKey::new(cert_der, sign).unwrap();
+        let (public, _) = SignedPublicKey::from_string(&key.export(cert_der).unwrap()).unwrap();
+        public.verify().unwrap();
+        assert_eq!(public.fingerprint(), key.fingerprint());
+        assert!(public.details.users[0].id.id().starts_with(b"Test Signer"));
+
+        let (signature, _) =
+            StandaloneSignature::from_string(&key.detached_signature(DATA).unwrap()).unwrap();
+        signature.verify(&public, DATA).unwrap();
+        assert!(signature.verify(&public, b"tampered").is_err());
+    }
+
+    #[test]
+    fn ecdsa_signature_verifies_against_exported_key() {
+        let signing_key = test_certs::p256_key();
+        let cert = test_certs::p256_certificate(&signing_key);
+        let sign = |digest: &[u8]| -> Result<Vec<u8>, Box<dyn Error>> {
+            let signature: p256::ecdsa::Signature = signing_key.sign_prehash(digest)?;
+            Ok(signature.to_bytes().to_vec())
+        };
+        check_round_trip(&cert, &sign);
+    }
+
+    #[test]
+    fn rsa_signature_verifies_against_exported_key() {
+        let signing_key = test_certs::rsa_key();
+        let cert = test_certs::rsa_certificate(&signing_key);
+        // The token's raw PKCS#1 v1.5 mechanism pads the DigestInfo as is.
+        let sign = |digest_info: &[u8]| -> Result<Vec<u8>, Box<dyn Error>> {
+            Ok(signing_key.sign(rsa::Pkcs1v15Sign::new_unprefixed(), digest_info)?)
+        };
+        check_round_trip(&cert, &sign);
+    }
+
+    #[test]
+    fn same_certificate_gives_same_fingerprint() {
+        let cert = test_certs::p256_certificate(&test_certs::p256_key());
+        let sign = |_: &[u8]| -> Result<Vec<u8>, Box<dyn Error>> { Err("unused".into()) };
+        let first = TokenKey::new(&cert, &sign).unwrap();
+        let second = TokenKey::new(&cert, &sign).unwrap();
+        assert_eq!(first.fingerprint(), second.fingerprint());
+    }
+
+    #[test]
+    fn token_errors_fail_the_signature() {
+        let cert = test_certs::p256_certificate(&test_certs::p256_key());
+        let sign = |_: &[u8]| -> Result<Vec<u8>, Box<dyn Error>> { Ok(vec![1, 2, 3]) };
+        let key = TokenKey::new(&cert, &sign).unwrap();
+        assert!(key.detached_signature(DATA).is_err());
+    }
+
+    #[test]
+    fn sha256sums_lists_digests() {
+        let entries = vec![
+            ("a.tar.gz".to_string(), Sha256::digest(b"a").to_vec()),
+            ("dist/b.zip".to_string(), Sha256::digest(b"b").to_vec()),
+        ];
+        let sums = sha256sums(&entries).unwrap();
+        assert_eq!(
+            sums,
+            format!(
+                "{}  a.tar.gz\n{}  dist/b.zip\n",
+                hex::encode(Sha256::digest(b"a")),
+                hex::encode(Sha256::digest(b"b"))
+            )
+        );
+    }
+
+    #[test]
+    fn sha256sums_rejects_bad_entries() {
+        let digest = Sha256::digest(b"a").to_vec();
+        assert!(sha256sums(&[]).is_err());
+        for name in ["", "a\nb", "a\rb", "a\\b"] {
+            assert!(sha256sums(&[(name.to_string(), digest.clone())]).is_err());
+        }
+        assert!(sha256sums(&[("a".to_string(), digest[..20].to_vec())]).is_err());
+    }
+}
@@ -0,0 +1,718 @@
+use crate::appearance::{SignatureAppearance, StampText};
+use chrono::{DateTime, Local, Utc};
+use lopdf::{Dictionary, Document, IncrementalDocument, Object, ObjectId, Stream, StringFormat};
+use serde::Deserialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+
+/// Bytes reserved in `/Contents` for the CMS signature or timestamp token.
+const CONTENTS_SIZE: usize = 16 * 1024;
+
+/// `/ByteRange` value written before the real offsets are known. Wide
+/// enough that the real offsets always fit in its place.
+const BYTE_RANGE_PLACEHOLDER: &[u8] = b"[0 9999999999 9999999999 9999999999]";
+
+/// PAdES baseline levels a PDF can be signed at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+pub enum SignatureLevel {
+    /// Signature only.
+    #[serde(rename = "B-B")]
+    Basic,
+    /// Signature with a signature timestamp.
+    #[serde(rename = "B-T")]
+    Timestamped,
+    /// B-T plus the chain and revocation data in the document security
+    /// store.
+    #[serde(rename = "B-LT")]
+    LongTerm,
+    /// B-LT sealed with a document timestamp.
+    #[serde(rename = "B-LTA")]
+    LongTermArchival,
+}
+
+impl SignatureLevel {
+    pub fn needs_timestamp(self) -> bool {
+        self >= SignatureLevel::Timestamped
+    }
+
+    pub fn needs_validation_data(self) -> bool {
+        self >= SignatureLevel::LongTerm
+    }
+
+    pub fn needs_document_timestamp(self) -> bool {
+        self == SignatureLevel::LongTermArchival
+    }
+}
+
+/// What a signature says about itself besides the signature value.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SignatureOptions {
+    pub reason: Option<String>,
+    pub location: Option<String>,
+    /// Makes the signature visible as a stamp on the page.
+    pub appearance: Option<SignatureAppearance>,
+}
+
+/// A PDF with an empty signature appended, waiting for its `/Contents`.
+pub struct PreparedPdf {
+    bytes: Vec<u8>,
+    /// Offsets of the hex string `/Contents` holds, delimiters included.
+    contents: (usize, usize),
+}
+
+impl PreparedPdf {
+    /// The bytes the signature covers: the whole file except `/Contents`.
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        let (start, end) = self.contents;
+        [&self.bytes[..start], &self.bytes[end..]].concat()
+    }
+
+    /// SHA-256 digest of `signed_bytes`.
+    pub fn digest(&self) -> Vec<u8> {
+        let (start, end) = self.contents;
+        Sha256::new()
+            .chain_update(&self.bytes[..start])
+            .chain_update(&self.bytes[end..])
+            .finalize()
+            .to_vec()
+    }
+
+    /// Writes a DER signature or timestamp token into `/Contents`.
+    pub fn embed(mut self, der: &[u8]) -> Result<SignedPdf, Box<dyn Error>> {
+        if der.len() > CONTENTS_SIZE {
+            return Err(format!(
+                "The signature is {} bytes, only {} fit in the PDF",
+                der.len(),
+                CONTENTS_SIZE
+            )
+            .into());
+        }
+        let mut contents = der.to_vec();
+        contents.resize(CONTENTS_SIZE, 0);
+        let (start, _) = self.contents;
+        let hex = hex::encode_upper(&contents);
+        self.bytes[start + 1..start + 1 + hex.len()].copy_from_slice(hex.as_bytes());
+        Ok(SignedPdf {
+            bytes: self.bytes,
+            contents,
+        })
+    }
+}
+
+/// A PDF with its newest signature in place.
+pub struct SignedPdf {
+    pub bytes: Vec<u8>,
+    /// `/Contents` of that signature as written, zero padding included.
+    pub contents: Vec<u8>,
+}
+
+/// Certificates and DER revocation data to store in the document security
+/// store for verifiers that cannot go online.
+#[derive(Default)]
+pub struct ValidationData {
+    pub certificates: Vec<Vec<u8>>,
+    pub ocsp_responses: Vec<Vec<u8>>,
+    pub crls: Vec<Vec<u8>>,
+}
+
+impl ValidationData {
+    pub fn add_certificate(&mut self, der: Vec<u8>) {
+        if !self.certificates.contains(&der) {
+            self.certificates.push(der);
+        }
+    }
+}
+
+fn load(pdf: &[u8]) -> Result<IncrementalDocument, Box<dyn Error>> {
+    let document = Document::load_mem(pdf)?;
+    if document.is_encrypted() {
+        return Err("Encrypted PDFs cannot be signed".into());
+    }
+    Ok(IncrementalDocument::create_from(pdf.to_vec(), document))
+}
+
+fn catalog_id(document: &IncrementalDocument) -> Result<ObjectId, Box<dyn Error>> {
+    Ok(document
+        .get_prev_documents()
+        .trailer
+        .get(b"Root")?
+        .as_reference()?)
+}
+
+/// An array entry of `dict`, following a reference to it if there is one.
+fn array_entry(
+    document: &Document,
+    dict: &Dictionary,
+    key: &[u8],
+) -> Result<Vec<Object>, Box<dyn Error>> {
+    match dict.get(key) {
+        Ok(value) => Ok(document.dereference(value)?.1.as_array()?.clone()),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+/// A dictionary entry of `dict`, following a reference to it if there is one.
+fn dictionary_entry(
+    document: &Document,
+    dict: &Dictionary,
+    key: &[u8],
+) -> Result<Option<Dictionary>, Box<dyn Error>> {
+    match dict.get(key) {
+        Ok(value) => Ok(Some(document.dereference(value)?.1.as_dict()?.clone())),
+        Err(_) => Ok(None),
+    }
+}
+
+/// `name` followed by the first number not already taken by a field.
+fn unused_field_name(
+    document: &Document,
+    fields: &[Object],
+    name: &str,
+) -> Result<String, Box<dyn Error>> {
+    let mut taken = Vec::new();
+    for field in fields {
+        if let Ok(title) = document
+            .dereference(field)?
+            .1
+            .as_dict()
+            .and_then(|field| field.get(b"T"))
+            .and_then(Object::as_str)
+        {
+            taken.push(title.to_vec());
+        }
+    }
+    Ok((1..)
+        .map(|n| format!("{}{}", name, n))
+        .find(|candidate| !taken.contains(&candidate.as_bytes().to_vec()))
+        .unwrap_or_default())
+}
+
+/// A PDF date in UTC.
+fn pdf_date(time: DateTime<Utc>) -> Object {
+    Object::string_literal(time.format("D:%Y%m%d%H%M%SZ").to_string())
+}
+
+fn name(value: &str) -> Object {
+    Object::Name(value.as_bytes().to_vec())
+}
+
+/// A PDF text string, UTF-16BE unless `text` is plain ASCII.
+fn text_string(text: &str) -> Object {
+    if text.is_ascii() {
+        return Object::string_literal(text);
+    }
+    let mut bytes = vec![0xfe, 0xff];
+    bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+    Object::String(bytes, StringFormat::Hexadecimal)
+}
+
+/// Appends `signature` as the value of a new signature field, with room
+/// left in `/Contents` for the signature itself. The field is an invisible
+/// one on the first page unless it gets a stamp.
+fn prepare(
+    pdf: &[u8],
+    signature: Dictionary,
+    field_name: &str,
+    stamp: Option<(&SignatureAppearance, StampText)>,
+) -> Result<PreparedPdf, Box<dyn Error>> {
+    let mut document = load(pdf)?;
+    let catalog_id = catalog_id(&document)?;
+    let prev = document.get_prev_documents();
+    let page_number = stamp.as_ref().map_or(1, |(appearance, _)| appearance.page);
+    let page_id = *prev
+        .get_pages()
+        .get(&page_number)
+        .ok_or_else(|| format!("The PDF has no page {}", page_number))?;
+    let catalog = prev.get_dictionary(catalog_id)?.clone();
+    let mut acro_form = dictionary_entry(prev, &catalog, b"AcroForm")?.unwrap_or_default();
+    let mut fields = array_entry(prev, &acro_form, b"Fields")?;
+    let field_name = unused_field_name(prev, &fields, field_name)?;
+    let mut page = prev.get_dictionary(page_id)?.clone();
+    let mut annots = array_entry(prev, &page, b"Annots")?;
+
+    let new = &mut document.new_document;
+    let signature_id = new.add_object(signature);
+    let mut widget = Dictionary::new();
+    widget.set("Type", name("Annot"));
+    widget.set("Subtype", name("Widget"));
+    widget.set("FT", name("Sig"));
+    widget.set("T", Object::string_literal(field_name));
+    widget.set("V", signature_id);
+    match &stamp {
+        Some((appearance, text)) => {
+            let stream = appearance.render(new, text)?;
+            let mut normal = Dictionary::new();
+            normal.set("N", new.add_object(stream));
+            widget.set("AP", normal);
+            widget.set(
+                "Rect",
+                appearance
+                    .rect
+                    .iter()
+                    .map(|&corner| corner.into())
+                    .collect::<Vec<Object>>(),
+            );
+        }
+        None => widget.set("Rect", vec![0.into(), 0.into(), 0.into(), 0.into()]),
+    }
+    // Print and locked.
+    widget.set("F", 132);
+    widget.set("P", page_id);
+    let widget_id = new.add_object(widget);
+
+    annots.push(widget_id.into());
+    page.set("Annots", annots);
+    new.set_object(page_id, page);
+
+    fields.push(widget_id.into());
+    acro_form.set("Fields", fields);
+    // Signatures exist, and the file must only be appended to.
+    acro_form.set("SigFlags", 3);
+    let mut catalog = catalog;
+    catalog.set("AcroForm", acro_form);
+    new.set_object(catalog_id, catalog);
+
+    let mut bytes = Vec::new();
+    document.save_to(&mut bytes)?;
+    fill_byte_range(bytes, pdf.len())
+}
+
+/// Finds the placeholders `prepare` wrote after `appended_from` and puts
+/// the real `/ByteRange` in place.
+fn fill_byte_range(
+    mut bytes: Vec<u8>,
+    appended_from: usize,
+) -> Result<PreparedPdf, Box<dyn Error>> {
+    let find = |bytes: &[u8], needle: &[u8]| {
+        bytes[appended_from..]
+            .windows(needle.len())
+            .position(|window| window == needle)
+            .map(|position| appended_from + position)
+    };
+    let mut contents_placeholder = vec![b'<'];
+    contents_placeholder.extend(std::iter::repeat_n(b'0', CONTENTS_SIZE * 2));
+    contents_placeholder.push(b'>');
+    let start =
+        find(&bytes, &contents_placeholder).ok_or("The signature placeholder is missing")?;
+    let end = start + contents_placeholder.len();
+    let range_start =
+        find(&bytes, BYTE_RANGE_PLACEHOLDER).ok_or("The byte range placeholder is missing")?;
+
+    let range = format!("[0 {} {} {}", start, end, bytes.len() - end);
+    let mut range = range.into_bytes();
+    range.resize(BYTE_RANGE_PLACEHOLDER.len() - 1, b' ');
+    range.push(b']');
+    bytes[range_start..range_start + range.len()].copy_from_slice(&range);
+    Ok(PreparedPdf {
+        bytes,
+        contents: (start, end),
+    })
+}
+
+fn placeholder_signature(kind: &str, sub_filter: &str) -> Dictionary {
+    let mut signature = Dictionary::new();
+    signature.set("Type", name(kind));
+    signature.set("Filter", name("Adobe.PPKLite"));
+    signature.set("SubFilter", name(sub_filter));
+    signature.set(
+        "ByteRange",
+        vec![
+            0.into(),
+            9_999_999_999i64.into(),
+            9_999_999_999i64.into(),
+            9_999_999_999i64.into(),
+        ],
+    );
+    signature.set(
+        "Contents",
+        Object::String(vec![0; CONTENTS_SIZE], StringFormat::Hexadecimal),
+    );
+    signature
+}
+
+/// Prepares a PAdES signature whose CMS goes in `/Contents`. The signing
+/// time goes in the signature dictionary, not the CMS signed attributes.
+pub fn prepare_signature(
+    pdf: &[u8],
+    signing_time: DateTime<Utc>,
+    signer_name: &str,
+    options: &SignatureOptions,
+) -> Result<PreparedPdf, Box<dyn Error>> {
+    let mut signature = placeholder_signature("Sig", "ETSI.CAdES.detached");
+    signature.set("M", pdf_date(signing_time));
+    signature.set("Name", text_string(signer_name));
+    if let Some(reason) = &options.reason {
+        signature.set("Reason", text_string(reason));
+    }
+    if let Some(location) = &options.location {
+        signature.set("Location", text_string(location));
+    }
+
+    let date = signing_time
+        .with_timezone(&Local)
+        .format("%Y-%m-%d %H:%M:%S %:z")
+        .to_string();
+    let stamp = options.appearance.as_ref().map(|appearance| {
+        let text = StampText {
+            name: signer_name,
+            date: &date,
+            reason: options.reason.as_deref(),
+            location: options.location.as_deref(),
+        };
+        (appearance, text)
+    });
+    prepare(pdf, signature, "Signature", stamp)
+}
+
+/// Prepares a document timestamp whose RFC 3161 token goes in `/Contents`.
+pub fn prepare_document_timestamp(pdf: &[u8]) -> Result<PreparedPdf, Box<dyn Error>> {
+    prepare(
+        pdf,
+        placeholder_signature("DocTimeStamp", "ETSI.RFC3161"),
+        "Timestamp",
+        None,
+    )
+}
+
+/// Adds `data` to the document security store in a new revision, recorded
+/// in the VRI entry of `signed`'s newest signature.
+pub fn add_validation_data(
+    signed: &SignedPdf,
+    data: &ValidationData,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut document = load(&signed.bytes)?;
+    let catalog_id = catalog_id(&document)?;
+    let prev = document.get_prev_documents();
+    let mut catalog = prev.get_dictionary(catalog_id)?.clone();
+    let mut dss = dictionary_entry(prev, &catalog, b"DSS")?.unwrap_or_default();
+    let mut vri = dictionary_entry(prev, &dss, b"VRI")?.unwrap_or_default();
+    let mut entries = Vec::new();
+    for key in [b"Certs".as_slice(), b"OCSPs", b"CRLs"] {
+        entries.push(array_entry(prev, &dss, key)?);
+    }
+
+    let new = &mut document.new_document;
+    let mut add_streams = |items: &[Vec<u8>]| -> Vec<Object> {
+        items
+            .iter()
+            .map(|der| {
+                new.add_object(Stream::new(Dictionary::new(), der.clone()))
+                    .into()
+            })
+            .collect()
+    };
+    let added = [
+        add_streams(&data.certificates),
+        add_streams(&data.ocsp_responses),
+        add_streams(&data.crls),
+    ];
+
+    let mut signature_vri = Dictionary::new();
+    for (((key, vri_key), existing), added) in [b"Certs".as_slice(), b"OCSPs", b"CRLs"]
+        .into_iter()
+        .zip(["Cert", "OCSP", "CRL"])
+        .zip(entries)
+        .zip(added)
+    {
+        if !added.is_empty() {
+            signature_vri.set(vri_key, added.clone());
+        }
+        let mut all = existing;
+        all.extend(added);
+        if !all.is_empty() {
+            dss.set(key, all);
+        }
+    }
+    vri.set(
+        hex::encode_upper(Sha1::digest(&signed.contents)),
+        signature_vri,
+    );
+    dss.set("VRI", vri);
+    catalog.set("DSS", dss);
+    new.set_object(catalog_id, catalog);
+
+    let mut bytes = Vec::new();
+    document.save_to(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// A CMS signature already in a PDF, located through its `/ByteRange`.
+pub struct EmbeddedSignature {
+    byte_range: [usize; 4],
+    /// Whether a later signature or document timestamp covers this one's
+    /// `/Contents`, so that rewriting it would break that signature.
+    covered: bool,
+}
+
+/// The CMS signatures in `pdf`, oldest first. Document timestamps are left
+/// out.
+pub fn embedded_signatures(pdf: &[u8]) -> Result<Vec<EmbeddedSignature>, Box<dyn Error>> {
+    let document = Document::load_mem(pdf)?;
+    let mut ranges = Vec::new();
+    for object in document.objects.values() {
+        let Ok(dict) = object.as_dict() else {
+            continue;
+        };
+        let Ok(byte_range) = dict.get(b"ByteRange").and_then(Object::as_array) else {
+            continue;
+        };
+        let byte_range = byte_range
+            .iter()
+            .map(|value| Ok(usize::try_from(value.as_i64()?)?))
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+        let byte_range: [usize; 4] = byte_range
+            .try_into()
+            .map_err(|_| "A /ByteRange has four numbers")?;
+        let [start, first, second, length] = byte_range;
+        let matches_file = start == 0
+            && first + 2 <= second
+            && second
+                .checked_add(length)
+                .is_some_and(|end| end <= pdf.len())
+            && pdf[first] == b'<'
+            && pdf[second - 1] == b'>';
+        if !matches_file {
+            return Err("A signature's /ByteRange does not match the file".into());
+        }
+        let is_timestamp = dict
+            .get(b"SubFilter")
+            .and_then(Object::as_name)
+            .is_ok_and(|sub_filter| sub_filter == b"ETSI.RFC3161");
+        ranges.push((byte_range, is_timestamp));
+    }
+    ranges.sort_by_key(|(byte_range, _)| byte_range[1]);
+
+    Ok(ranges
+        .iter()
+        .filter(|(_, is_timestamp)| !is_timestamp)
+        .map(|&(byte_range, _)| EmbeddedSignature {
+            byte_range,
+            covered: ranges.iter().any(|(other, _)| {
+                other[1] != byte_range[1] && other[2] + other[3] >= byte_range[2]
+            }),
+        })
+        .collect())
+}
+
+impl EmbeddedSignature {
+    /// `/Contents` decoded, zero padding included.
+    pub fn contents(&self, pdf: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let [_, first, second, _] = self.byte_range;
+        let mut hex: Vec<u8> = pdf[first + 1..second - 1]
+            .iter()
+            .copied()
+            .filter(|byte| !byte.is_ascii_whitespace())
+            .collect();
+        // A missing last digit is read as zero.
+        if hex.len() % 2 == 1 {
+            hex.push(b'0');
+        }
+        Ok(hex::decode(hex)?)
+    }
+
+    /// A copy of `pdf` with `/Contents` replaced by `der`. The byte range
+    /// stays the same, so the signature must fit in the space the signer
+    /// reserved.
+    pub fn replace_contents(&self, pdf: &[u8], der: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        if self.covered {
+            return Err(
+                "A later signature or document timestamp covers this signature, so it cannot be changed"
+                    .into(),
+            );
+        }
+        let [_, first, second, _] = self.byte_range;
+        let space = (second - first - 2) / 2;
+        if der.len() > space {
+            return Err(format!(
+                "The signature is {} bytes, only {} fit in the PDF",
+                der.len(),
+                space
+            )
+            .into());
+        }
+        let mut hex = hex::encode_upper(der).into_bytes();
+        hex.resize(second - first - 2, b'0');
+        let mut bytes = pdf.to_vec();
+        bytes[first + 1..second - 1].copy_from_slice(&hex);
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::dictionary;
+
+    fn minimal_pdf() -> Vec<u8> {
+        let mut document = Document::with_version("1.7");
+        let pages_id = document.new_object_id();
+        let page_id = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+        });
+        document.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+            }),
+        );
+        let catalog_id = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        document.trailer.set("Root", catalog_id);
+        let mut bytes = Vec::new();
+        document.save_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn signed_pdf(pdf: &[u8], der: &[u8]) -> SignedPdf {
+        prepare_signature(pdf, Utc::now(), "Test Signer", &SignatureOptions::default())
+            .unwrap()
+            .embed(der)
+            .unwrap()
+    }
+
+    /// The `/ByteRange` of every signature dictionary in `pdf`.
+    fn byte_ranges(pdf: &[u8]) -> Vec<[usize; 4]> {
+        let document = Document::load_mem(pdf).unwrap();
+        let mut ranges: Vec<[usize; 4]> = document
+            .objects
+            .values()
+            .filter_map(|object| object.as_dict().ok()?.get(b"ByteRange").ok())
+            .map(|range| {
+                let range: Vec<usize> = range
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|value| value.as_i64().unwrap() as usize)
+                    .collect();
+                range.try_into().unwrap()
+            })
+            .collect();
+        ranges.sort();
+        ranges
+    }
+
+    #[test]
+    fn byte_range_covers_everything_but_contents() {
+        let pdf = minimal_pdf();
+        let prepared = prepare_signature(
+            &pdf,
+            Utc::now(),
+            "Test Signer",
+            &SignatureOptions::default(),
+        )
+        .unwrap();
+        let signed_bytes = prepared.signed_bytes();
+        let digest = prepared.digest();
+        let der = [0x30, 0x03, 0x02, 0x01, 0x01];
+        let signed = prepared.embed(&der).unwrap();
+        let bytes = &signed.bytes;
+
+        assert!(bytes.starts_with(&pdf));
+        let [start, first, second, length] = byte_ranges(bytes)[0];
+        assert_eq!(start, 0);
+        assert_eq!(second + length, bytes.len());
+        assert_eq!(bytes[first], b'<');
+        assert_eq!(bytes[second - 1], b'>');
+        assert_eq!(second - first, CONTENTS_SIZE * 2 + 2);
+
+        let covered = [&bytes[..first], &bytes[second..]].concat();
+        assert_eq!(covered, signed_bytes);
+        assert_eq!(Sha256::digest(&covered).to_vec(), digest);
+
+        let hex = std::str::from_utf8(&bytes[first + 1..second - 1]).unwrap();
+        assert!(hex.starts_with(&hex::encode_upper(der)));
+        assert!(hex[der.len() * 2..].bytes().all(|digit| digit == b'0'));
+        assert_eq!(signed.contents.len(), CONTENTS_SIZE);
+        assert!(signed.contents.starts_with(&der));
+    }
+
+    #[test]
+    fn oversized_signature_is_refused() {
+        let prepared = prepare_signature(
+            &minimal_pdf(),
+            Utc::now(),
+            "Test Signer",
+            &SignatureOptions::default(),
+        )
+        .unwrap();
+        assert!(prepared.embed(&vec![1; CONTENTS_SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn embedded_signature_is_found_and_replaced() {
+        let signed = signed_pdf(&minimal_pdf(), &[1, 2, 3]);
+        let signatures = embedded_signatures(&signed.bytes).unwrap();
+        assert_eq!(signatures.len(), 1);
+        assert_eq!(
+            signatures[0].contents(&signed.bytes).unwrap(),
+            signed.contents
+        );
+
+        let replaced = signatures[0]
+            .replace_contents(&signed.bytes, &[4, 5, 6, 7])
+            .unwrap();
+        assert_eq!(replaced.len(), signed.bytes.len());
+        assert_eq!(byte_ranges(&replaced), byte_ranges(&signed.bytes));
+        let contents = embedded_signatures(&replaced).unwrap()[0]
+            .contents(&replaced)
+            .unwrap();
+        assert!(contents.starts_with(&[4, 5, 6, 7]));
+        assert!(contents[4..].iter().all(|&byte| byte == 0));
+
+        assert!(signatures[0]
+            .replace_contents(&signed.bytes, &vec![1; CONTENTS_SIZE + 1])
+            .is_err());
+    }
+
+    #[test]
+    fn later_signature_covers_earlier_one() {
+        let first = signed_pdf(&minimal_pdf(), &[1]);
+        let second = signed_pdf(&first.bytes, &[2]);
+        let signatures = embedded_signatures(&second.bytes).unwrap();
+        assert_eq!(signatures.len(), 2);
+        assert!(signatures[0]
+            .contents(&second.bytes)
+            .unwrap()
+            .starts_with(&[1]));
+        assert!(signatures[0].replace_contents(&second.bytes, &[3]).is_err());
+        assert!(signatures[1].replace_contents(&second.bytes, &[3]).is_ok());
+    }
+
+    #[test]
+    fn document_timestamp_is_not_a_signature() {
+        let signed = signed_pdf(&minimal_pdf(), &[1]);
+        let stamped = prepare_document_timestamp(&signed.bytes)
+            .unwrap()
+            .embed(&[2])
+            .unwrap();
+        assert_eq!(byte_ranges(&stamped.bytes).len(), 2);
+        let signatures = embedded_signatures(&stamped.bytes).unwrap();
+        assert_eq!(signatures.len(), 1);
+        assert!(signatures[0]
+            .replace_contents(&stamped.bytes, &[3])
+            .is_err());
+    }
+
+    #[test]
+    fn byte_range_outside_the_file_is_rejected() {
+        let signed = signed_pdf(&minimal_pdf(), &[1]);
+        let [_, first, second, length] = byte_ranges(&signed.bytes)[0];
+        let written = format!("[0 {} {} {}", first, second, length);
+        let forged = format!("[0 {} {} {}", first, second, length + 1);
+        let position = signed
+            .bytes
+            .windows(written.len())
+            .position(|window| window == written.as_bytes())
+            .unwrap();
+        let mut bytes = signed.bytes.clone();
+        bytes[position..position + forged.len()].copy_from_slice(forged.as_bytes());
+        assert!(embedded_signatures(&bytes).is_err());
+    }
+}
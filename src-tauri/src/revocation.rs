@@ -0,0 +1,490 @@
+use crate::certificate;
+use chrono::{DateTime, Utc};
+use der::asn1::{Null, OctetString};
+use der::oid::db::{rfc5280, rfc5912, rfc6960};
+use der::{Decode, Encode};
+use reqwest::header::CONTENT_TYPE;
+use serde::Serialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use x509_cert::crl::CertificateList;
+use x509_cert::ext::pkix::name::{DistributionPointName, GeneralName};
+use x509_cert::ext::pkix::{AuthorityInfoAccessSyntax, CrlDistributionPoints, ExtendedKeyUsage};
+use x509_cert::spki::AlgorithmIdentifierOwned;
+use x509_cert::Certificate;
+use x509_ocsp::{
+    BasicOcspResponse, CertId, CertStatus, OcspRequest, OcspResponse, OcspResponseStatus, Request,
+    ResponderId, TbsRequest,
+};
+
+/// How long to wait for an OCSP responder or CRL server.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Clock difference tolerated between the agent and a responder.
+const CLOCK_SKEW_SECONDS: i64 = 300;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RevocationSource {
+    Ocsp,
+    Crl,
+}
+
+/// Whether a certificate has been revoked, and how that was established.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum RevocationStatus {
+    Good {
+        source: RevocationSource,
+        /// RFC 3339 time until which the answer is current, if the
+        /// responder gave one.
+        next_update: Option<String>,
+    },
+    Revoked {
+        source: RevocationSource,
+        /// RFC 3339 revocation time.
+        revoked_at: String,
+    },
+    /// Neither OCSP nor a CRL gave an answer.
+    Unknown { reason: String },
+}
+
+/// A status and the DER OCSP response or CRL it was read from.
+type Answer = (RevocationStatus, Vec<u8>);
+
+/// DER revocation data a status was established from.
+#[derive(Debug, Clone)]
+pub enum RevocationEvidence {
+    Ocsp(Vec<u8>),
+    Crl(Vec<u8>),
+}
+
+fn rfc3339(seconds: u64) -> String {
+    DateTime::from_timestamp(seconds as i64, 0)
+        .map(|time| time.to_rfc3339())
+        .unwrap_or_default()
+}
+
+fn http_client() -> Result<reqwest::blocking::Client, Box<dyn Error>> {
+    Ok(reqwest::blocking::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .build()?)
+}
+
+fn uri(name: &GeneralName) -> Option<String> {
+    match name {
+        GeneralName::UniformResourceIdentifier(uri) if uri.as_str().starts_with("http") => {
+            Some(uri.to_string())
+        }
+        _ => None,
+    }
+}
+
+/// OCSP responder named in the certificate's authority information access.
+fn ocsp_url(cert: &Certificate) -> Option<String> {
+    let (_, access) = cert
+        .tbs_certificate
+        .get::<AuthorityInfoAccessSyntax>()
+        .ok()??;
+    access
+        .0
+        .iter()
+        .filter(|description| description.access_method == rfc5280::ID_AD_OCSP)
+        .find_map(|description| uri(&description.access_location))
+}
+
+/// HTTP CRL distribution points named in the certificate.
+fn crl_urls(cert: &Certificate) -> Vec<String> {
+    let Ok(Some((_, points))) = cert.tbs_certificate.get::<CrlDistributionPoints>() else {
+        return Vec::new();
+    };
+    points
+        .0
+        .iter()
+        .filter_map(|point| match &point.distribution_point {
+            Some(DistributionPointName::FullName(names)) => Some(names),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(uri)
+        .collect()
+}
+
+/// SHA-1 `CertID`, the form every OCSP responder understands.
+fn cert_id(cert: &Certificate, issuer: &Certificate) -> Result<CertId, Box<dyn Error>> {
+    let issuer_key = issuer
+        .tbs_certificate
+        .subject_public_key_info
+        .subject_public_key
+        .raw_bytes();
+    Ok(CertId {
+        hash_algorithm: AlgorithmIdentifierOwned {
+            oid: rfc5912::ID_SHA_1,
+            parameters: Some(Null.into()),
+        },
+        issuer_name_hash: OctetString::new(
+            Sha1::digest(issuer.tbs_certificate.subject.to_der()?).to_vec(),
+        )?,
+        issuer_key_hash: OctetString::new(Sha1::digest(issuer_key).to_vec())?,
+        serial_number: cert.tbs_certificate.serial_number.clone(),
+    })
+}
+
+fn same_cert_id(a: &CertId, b: &CertId) -> bool {
+    a.hash_algorithm.oid == b.hash_algorithm.oid
+        && a.issuer_name_hash == b.issuer_name_hash
+        && a.issuer_key_hash == b.issuer_key_hash
+        && a.serial_number == b.serial_number
+}
+
+/// The certificate that signed an OCSP response: the issuer itself or a
+/// responder the issuer delegated OCSP signing to.
+fn ocsp_signer<'a>(
+    basic: &'a BasicOcspResponse,
+    issuer: &'a Certificate,
+) -> Result<&'a Certificate, Box<dyn Error>> {
+    let names = |cert: &Certificate| match &basic.tbs_response_data.responder_id {
+        ResponderId::ByName(name) => cert.tbs_certificate.subject == *name,
+        ResponderId::ByKey(hash) => {
+            let key = cert
+                .tbs_certificate
+                .subject_public_key_info
+                .subject_public_key
+                .raw_bytes();
+            hash.as_bytes() == Sha1::digest(key).as_slice()
+        }
+    };
+    if names(issuer) {
+        return Ok(issuer);
+    }
+
+    let responder = basic
+        .certs
+        .iter()
+        .flatten()
+        .find(|cert| names(cert))
+        .ok_or("The OCSP responder certificate is missing")?;
+    let tbs = &responder.tbs_certificate;
+    certificate::verify_signed_by(
+        issuer,
+        responder.signature_algorithm.oid,
+        &tbs.to_der()?,
+        &responder.signature,
+    )
+    .map_err(|_| "The OCSP responder is not authorized by the issuer")?;
+    let delegated = matches!(
+        tbs.get::<ExtendedKeyUsage>()?,
+        Some((_, usage)) if usage.0.contains(&rfc5280::ID_KP_OCSP_SIGNING)
+    );
+    if !delegated {
+        return Err("The OCSP responder certificate is not authorized for OCSP signing".into());
+    }
+    Ok(responder)
+}
+
+/// Checks a DER OCSP response for `id` and returns the status it gives,
+/// with the time the answer stays current until.
+fn read_ocsp_response(
+    response: &[u8],
+    id: &CertId,
+    issuer: &Certificate,
+    now: i64,
+) -> Result<(RevocationStatus, Option<u64>), Box<dyn Error>> {
+    let response = OcspResponse::from_der(response)?;
+    if response.response_status != OcspResponseStatus::Successful {
+        return Err(format!("The OCSP responder answered {:?}", response.response_status).into());
+    }
+    let bytes = response
+        .response_bytes
+        .ok_or("The OCSP response is empty")?;
+    if bytes.response_type != rfc6960::ID_PKIX_OCSP_BASIC {
+        return Err("Unsupported OCSP response type".into());
+    }
+    let basic = BasicOcspResponse::from_der(bytes.response.as_bytes())?;
+    let signer = ocsp_signer(&basic, issuer)?;
+    certificate::verify_signed_by(
+        signer,
+        basic.signature_algorithm.oid,
+        &basic.tbs_response_data.to_der()?,
+        &basic.signature,
+    )?;
+
+    let single = basic
+        .tbs_response_data
+        .responses
+        .iter()
+        .find(|single| same_cert_id(&single.cert_id, id))
+        .ok_or("The OCSP response does not cover the certificate")?;
+    let this_update = single.this_update.0.to_unix_duration().as_secs();
+    let next_update = single
+        .next_update
+        .map(|time| time.0.to_unix_duration().as_secs());
+    if this_update as i64 > now + CLOCK_SKEW_SECONDS {
+        return Err("The OCSP response is from the future".into());
+    }
+    if next_update.is_some_and(|next| (next as i64) < now - CLOCK_SKEW_SECONDS) {
+        return Err("The OCSP response is out of date".into());
+    }
+
+    let status = match &single.cert_status {
+        CertStatus::Good(_) => RevocationStatus::Good {
+            source: RevocationSource::Ocsp,
+            next_update: next_update.map(rfc3339),
+        },
+        CertStatus::Revoked(info) => RevocationStatus::Revoked {
+            source: RevocationSource::Ocsp,
+            revoked_at: rfc3339(info.revocation_time.0.to_unix_duration().as_secs()),
+        },
+        CertStatus::Unknown(_) => {
+            return Err("The OCSP responder does not know the certificate".into())
+        }
+    };
+    Ok((status, next_update))
+}
+
+/// Checks a DER CRL issued by `issuer` and looks `cert` up in it.
+fn read_crl(
+    crl: &[u8],
+    cert: &Certificate,
+    issuer: &Certificate,
+    now: i64,
+) -> Result<(RevocationStatus, Option<u64>), Box<dyn Error>> {
+    let crl = CertificateList::from_der(crl)?;
+    let tbs = &crl.tbs_cert_list;
+    if tbs.issuer != issuer.tbs_certificate.subject {
+        return Err("The CRL was not issued by the certificate's issuer".into());
+    }
+    certificate::verify_signed_by(
+        issuer,
+        crl.signature_algorithm.oid,
+        &tbs.to_der()?,
+        &crl.signature,
+    )?;
+    let next_update = tbs
+        .next_update
+        .map(|time| time.to_unix_duration().as_secs());
+    if next_update.is_some_and(|next| (next as i64) < now - CLOCK_SKEW_SECONDS) {
+        return Err("The CRL is out of date".into());
+    }
+
+    let serial = &cert.tbs_certificate.serial_number;
+    let status = match tbs
+        .revoked_certificates
+        .iter()
+        .flatten()
+        .find(|revoked| revoked.serial_number == *serial)
+    {
+        Some(revoked) => RevocationStatus::Revoked {
+            source: RevocationSource::Crl,
+            revoked_at: rfc3339(revoked.revocation_date.to_unix_duration().as_secs()),
+        },
+        None => RevocationStatus::Good {
+            source: RevocationSource::Crl,
+            next_update: next_update.map(rfc3339),
+        },
+    };
+    Ok((status, next_update))
+}
+
+/// The response cached at `path`, if it still checks out and is current.
+fn read_cached(
+    path: &Path,
+    read: impl Fn(&[u8]) -> Result<(RevocationStatus, Option<u64>), Box<dyn Error>>,
+    now: i64,
+) -> Option<Answer> {
+    let cached = std::fs::read(path).ok()?;
+    match read(&cached) {
+        Ok((status, Some(next_update))) if next_update as i64 >= now => Some((status, cached)),
+        _ => None,
+    }
+}
+
+/// Checks certificates against OCSP, falling back to CRLs, and keeps the
+/// responses on disk so checks still succeed offline while they are current.
+pub struct RevocationChecker {
+    cache_dir: PathBuf,
+    /// Used instead of the OCSP responder a certificate names.
+    ocsp_url: Option<String>,
+    /// Used instead of the CRL distribution points a certificate names.
+    crl_url: Option<String>,
+}
+
+impl RevocationChecker {
+    pub fn new(cache_dir: PathBuf, ocsp_url: Option<String>, crl_url: Option<String>) -> Self {
+        RevocationChecker {
+            cache_dir,
+            ocsp_url,
+            crl_url,
+        }
+    }
+
+    pub fn check(
+        &self,
+        cert: &Certificate,
+        issuer: &Certificate,
+        now: DateTime<Utc>,
+    ) -> RevocationStatus {
+        self.check_with_evidence(cert, issuer, now).0
+    }
+
+    /// Like `check`, also handing back the OCSP response or CRL the status
+    /// came from so it can be embedded in long-term signatures.
+    pub fn check_with_evidence(
+        &self,
+        cert: &Certificate,
+        issuer: &Certificate,
+        now: DateTime<Utc>,
+    ) -> (RevocationStatus, Option<RevocationEvidence>) {
+        let now = now.timestamp();
+        let mut problems = Vec::new();
+
+        match self.check_ocsp(cert, issuer, now) {
+            Ok(Some((status, response))) => {
+                return (status, Some(RevocationEvidence::Ocsp(response)))
+            }
+            Ok(None) => {}
+            Err(e) => problems.push(format!("OCSP: {}", e)),
+        }
+        match self.check_crl(cert, issuer, now) {
+            Ok(Some((status, crl))) => return (status, Some(RevocationEvidence::Crl(crl))),
+            Ok(None) => {}
+            Err(e) => problems.push(format!("CRL: {}", e)),
+        }
+
+        let status = RevocationStatus::Unknown {
+            reason: if problems.is_empty() {
+                "The certificate names no OCSP responder or CRL".to_string()
+            } else {
+                problems.join("; ")
+            },
+        };
+        (status, None)
+    }
+
+    /// The status from a cached OCSP response or CRL that is still current,
+    /// without going to the network.
+    pub fn cached(
+        &self,
+        cert: &Certificate,
+        issuer: &Certificate,
+        now: DateTime<Utc>,
+    ) -> Option<RevocationStatus> {
+        let now = now.timestamp();
+        if let Ok(id) = cert_id(cert, issuer) {
+            let path = self.cache_path("ocsp", &cert.to_der().ok()?);
+            let read = |response: &[u8]| read_ocsp_response(response, &id, issuer, now);
+            if let Some((status, _)) = read_cached(&path, read, now) {
+                return Some(status);
+            }
+        }
+        let urls = match &self.crl_url {
+            Some(url) => vec![url.clone()],
+            None => crl_urls(cert),
+        };
+        urls.iter().find_map(|url| {
+            let path = self.cache_path("crl", url.as_bytes());
+            let read = |crl: &[u8]| read_crl(crl, cert, issuer, now);
+            read_cached(&path, read, now).map(|(status, _)| status)
+        })
+    }
+
+    fn cache_path(&self, kind: &str, key: &[u8]) -> PathBuf {
+        self.cache_dir
+            .join(format!("{}-{}.der", kind, hex::encode(Sha256::digest(key))))
+    }
+
+    /// Uses the cached response at `path` if it still checks out, otherwise
+    /// fetches a new one and caches it if it says how long it is current.
+    /// Returns the status with the response it came from.
+    fn cached_or_fetch(
+        &self,
+        path: &Path,
+        fetch: impl FnOnce() -> Result<Vec<u8>, Box<dyn Error>>,
+        read: impl Fn(&[u8]) -> Result<(RevocationStatus, Option<u64>), Box<dyn Error>>,
+        now: i64,
+    ) -> Result<Answer, Box<dyn Error>> {
+        if let Some(cached) = read_cached(path, &read, now) {
+            return Ok(cached);
+        }
+
+        let response = fetch()?;
+        let (status, next_update) = read(&response)?;
+        if next_update.is_some() {
+            std::fs::create_dir_all(&self.cache_dir)?;
+            std::fs::write(path, &response)?;
+        }
+        Ok((status, response))
+    }
+
+    fn check_ocsp(
+        &self,
+        cert: &Certificate,
+        issuer: &Certificate,
+        now: i64,
+    ) -> Result<Option<Answer>, Box<dyn Error>> {
+        let Some(url) = self.ocsp_url.clone().or_else(|| ocsp_url(cert)) else {
+            return Ok(None);
+        };
+        let id = cert_id(cert, issuer)?;
+        let request = OcspRequest {
+            tbs_request: TbsRequest {
+                request_list: vec![Request {
+                    req_cert: id.clone(),
+                    single_request_extensions: None,
+                }],
+                ..Default::default()
+            },
+            optional_signature: None,
+        };
+
+        let path = self.cache_path("ocsp", &cert.to_der()?);
+        let fetch = || -> Result<Vec<u8>, Box<dyn Error>> {
+            Ok(http_client()?
+                .post(&url)
+                .header(CONTENT_TYPE, "application/ocsp-request")
+                .body(request.to_der()?)
+                .send()?
+                .error_for_status()?
+                .bytes()?
+                .to_vec())
+        };
+        let read = |response: &[u8]| read_ocsp_response(response, &id, issuer, now);
+        self.cached_or_fetch(&path, fetch, read, now).map(Some)
+    }
+
+    fn check_crl(
+        &self,
+        cert: &Certificate,
+        issuer: &Certificate,
+        now: i64,
+    ) -> Result<Option<Answer>, Box<dyn Error>> {
+        let urls = match &self.crl_url {
+            Some(url) => vec![url.clone()],
+            None => crl_urls(cert),
+        };
+        let mut last_error = None;
+        for url in &urls {
+            let path = self.cache_path("crl", url.as_bytes());
+            let fetch = || -> Result<Vec<u8>, Box<dyn Error>> {
+                Ok(http_client()?
+                    .get(url)
+                    .send()?
+                    .error_for_status()?
+                    .bytes()?
+                    .to_vec())
+            };
+            let read = |crl: &[u8]| read_crl(crl, cert, issuer, now);
+            match self.cached_or_fetch(&path, fetch, read, now) {
+                Ok(found) => return Ok(Some(found)),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        match last_error {
+            Some(e) => Err(e),
+            None => Ok(None),
+        }
+    }
+}
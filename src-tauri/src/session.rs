@@ -0,0 +1,230 @@
+use chrono::{DateTime, Duration, Utc};
+use cryptoki::session::{Session, SessionState};
+use serde::Serialize;
+use std::error::Error;
+use std::sync::Mutex;
+
+/// A logged-in token session the user allowed to be reused, without a PIN
+/// prompt, by later requests from the origin that opened it.
+pub struct SigningSession {
+    session: Session,
+    origin: Option<String>,
+    cert_hash: String,
+    expires_at: DateTime<Utc>,
+    remaining_signatures: u32,
+}
+
+impl SigningSession {
+    pub fn new(
+        session: Session,
+        origin: Option<String>,
+        cert_hash: String,
+        minutes: u32,
+        max_signatures: u32,
+        now: DateTime<Utc>,
+    ) -> Self {
+        SigningSession {
+            session,
+            origin,
+            cert_hash,
+            expires_at: now + Duration::minutes(minutes.into()),
+            remaining_signatures: max_signatures,
+        }
+    }
+
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        now >= self.expires_at || self.remaining_signatures == 0
+    }
+
+    /// False once the token was removed or another login on it logged this
+    /// session out.
+    fn is_logged_in(&self) -> bool {
+        self.session.get_session_info().is_ok_and(|info| {
+            matches!(
+                info.session_state(),
+                SessionState::RoUser | SessionState::RwUser
+            )
+        })
+    }
+
+    fn covers(&self, origin: Option<&str>, cert_hash: &str, now: DateTime<Utc>) -> bool {
+        self.origin.as_deref() == origin
+            && self.cert_hash.eq_ignore_ascii_case(cert_hash)
+            && !self.is_expired(now)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionStatus {
+    pub origin: Option<String>,
+    /// RFC 3339 time the session locks at the latest.
+    pub expires_at: String,
+    pub remaining_signatures: u32,
+}
+
+/// Holds the single active signing session, if any.
+#[derive(Default)]
+pub struct SessionManager {
+    active: Mutex<Option<SigningSession>>,
+}
+
+impl SessionManager {
+    /// Replaces any active session with `session`.
+    pub fn open(&self, session: SigningSession) -> Result<(), Box<dyn Error>> {
+        *self.active.lock().map_err(|_| "Session lock poisoned")? = Some(session);
+        Ok(())
+    }
+
+    /// Ends the active session. Returns whether there was one.
+    pub fn lock(&self) -> bool {
+        match self.active.lock() {
+            Ok(mut active) => active.take().is_some(),
+            Err(_) => false,
+        }
+    }
+
+    /// Ends the active session if `origin` opened it. Returns whether it
+    /// did, so one site cannot see or end another site's session.
+    pub fn lock_for(&self, origin: Option<&str>) -> bool {
+        let Ok(mut active) = self.active.lock() else {
+            return false;
+        };
+        if active
+            .as_ref()
+            .is_some_and(|session| session.origin.as_deref() == origin)
+        {
+            *active = None;
+            return true;
+        }
+        false
+    }
+
+    /// The active session's status if `origin` opened it.
+    pub fn status_for(&self, origin: Option<&str>) -> Option<SessionStatus> {
+        self.status()
+            .filter(|status| status.origin.as_deref() == origin)
+    }
+
+    pub fn status(&self) -> Option<SessionStatus> {
+        let active = self.active.lock().ok()?;
+        active.as_ref().map(|active| SessionStatus {
+            origin: active.origin.clone(),
+            expires_at: active.expires_at.to_rfc3339(),
+            remaining_signatures: active.remaining_signatures,
+        })
+    }
+
+    /// Whether the active session was opened by `origin` for `cert_hash`
+    /// and can still make `signatures` more signatures.
+    pub fn covers(
+        &self,
+        origin: Option<&str>,
+        cert_hash: &str,
+        signatures: usize,
+        now: DateTime<Utc>,
+    ) -> bool {
+        let Ok(active) = self.active.lock() else {
+            return false;
+        };
+        active.as_ref().is_some_and(|session| {
+            session.covers(origin, cert_hash, now)
+                && session.remaining_signatures as usize >= signatures
+                && session.is_logged_in()
+        })
+    }
+
+    /// Signs with the active session if it was opened by `origin` for
+    /// `cert_hash` and is still valid. Returns `None` when the user has to
+    /// be prompted instead. A failed signature ends the session.
+    pub fn sign<F>(
+        &self,
+        origin: Option<&str>,
+        cert_hash: &str,
+        now: DateTime<Utc>,
+        sign: F,
+    ) -> Option<Result<Vec<u8>, Box<dyn Error>>>
+    where
+        F: FnOnce(&Session) -> Result<Vec<u8>, Box<dyn Error>>,
+    {
+        let mut active = self.active.lock().ok()?;
+        let session = active.as_mut()?;
+        if !session.covers(origin, cert_hash, now) {
+            return None;
+        }
+        if !session.is_logged_in() {
+            *active = None;
+            return None;
+        }
+        let result = sign(&session.session);
+        match result {
+            Ok(_) => session.remaining_signatures -= 1,
+            Err(_) => *active = None,
+        }
+        Some(result)
+    }
+
+    /// Ends the active session if it expired, its token went away or logged
+    /// it out, or `force` is set. Returns whether a session was ended.
+    pub fn expire(&self, now: DateTime<Utc>, force: bool) -> bool {
+        let Ok(mut active) = self.active.lock() else {
+            return false;
+        };
+        let ended = match active.as_ref() {
+            Some(session) => force || session.is_expired(now) || !session.is_logged_in(),
+            None => false,
+        };
+        if ended {
+            *active = None;
+        }
+        ended
+    }
+}
+
+/// Best-effort check whether the user's screen is locked. Uses logind on
+/// Linux, the console session state on macOS and the input desktop on
+/// Windows; always `false` elsewhere.
+pub fn screen_locked() -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        let Ok(session_id) = std::env::var("XDG_SESSION_ID") else {
+            return false;
+        };
+        std::process::Command::new("loginctl")
+            .args(["show-session", &session_id, "-p", "LockedHint", "--value"])
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim() == "yes")
+            .unwrap_or(false)
+    }
+    #[cfg(target_os = "macos")]
+    {
+        std::process::Command::new("ioreg")
+            .args(["-n", "Root", "-d1"])
+            .output()
+            .map(|output| {
+                String::from_utf8_lossy(&output.stdout).contains("\"CGSSessionScreenIsLocked\"=Yes")
+            })
+            .unwrap_or(false)
+    }
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::System::StationsAndDesktops::{
+            CloseDesktop, OpenInputDesktop, DESKTOP_SWITCHDESKTOP,
+        };
+        // While the workstation is locked the input desktop is Winlogon's,
+        // which a user process cannot open. The UAC prompt's secure desktop
+        // looks the same, which only ends the session early.
+        // SAFETY: the handle is checked and closed before returning.
+        unsafe {
+            let desktop = OpenInputDesktop(0, 0, DESKTOP_SWITCHDESKTOP);
+            if desktop.is_null() {
+                return true;
+            }
+            CloseDesktop(desktop);
+            false
+        }
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    {
+        false
+    }
+}
@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+const SETTINGS_FILE: &str = "settings.json";
+
+/// User-tunable agent settings, persisted as JSON in the app data directory.
+///
+/// Missing keys fall back to their defaults so older settings files keep
+/// loading after new options are added.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Accept `/sign-document` requests that carry the pre-envelope
+    /// `{cert_hash}_{timestamp}` company signature instead of a
+    /// `SigningEnvelope`. Off by default: legacy messages bind neither the
+    /// document nor the origin, so deployments still on the old platform
+    /// must opt in explicitly until it issues envelopes.
+    pub accept_legacy_requests: bool,
+    /// How long a signing session keeps the token unlocked after the PIN is
+    /// entered. Zero disables signing sessions.
+    pub session_minutes: u32,
+    /// Most signatures a signing session may produce before it locks.
+    pub session_max_signatures: u32,
+    /// RFC 3161 time-stamping authority that CMS signatures are sent to,
+    /// making them CAdES-T. Signatures are not timestamped when unset.
+    pub tsa_url: Option<String>,
+    /// OCSP responder queried instead of the one a certificate names.
+    pub ocsp_url: Option<String>,
+    /// CRL fetched instead of the distribution points a certificate names.
+    pub crl_url: Option<String>,
+    /// Refuse to sign when neither OCSP nor a CRL could confirm that the
+    /// certificate is not revoked. Revoked certificates are always refused.
+    pub require_revocation_status: bool,
+    /// Directory of root certificates, in DER or PEM form, trusted in
+    /// addition to the bundled trust store.
+    pub trust_anchor_dir: Option<String>,
+    /// Web origins allowed to read the audit log through `/audit`. Empty
+    /// by default, which leaves the log readable only inside the app.
+    pub audit_origins: Vec<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            accept_legacy_requests: false,
+            session_minutes: 5,
+            session_max_signatures: 50,
+            tsa_url: None,
+            ocsp_url: None,
+            crl_url: None,
+            require_revocation_status: false,
+            trust_anchor_dir: None,
+            audit_origins: Vec::new(),
+        }
+    }
+}
+
+fn settings_path(data_dir: &Path) -> PathBuf {
+    data_dir.join(SETTINGS_FILE)
+}
+
+/// Loads the settings from `data_dir`, returning the defaults when no
+/// settings file has been written yet.
+pub fn load(data_dir: &Path) -> Result<Settings, Box<dyn Error>> {
+    let path = settings_path(data_dir);
+    if !path.exists() {
+        return Ok(Settings::default());
+    }
+    let contents = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+pub fn save(data_dir: &Path, settings: &Settings) -> Result<(), Box<dyn Error>> {
+    std::fs::create_dir_all(data_dir)?;
+    let contents = serde_json::to_string_pretty(settings)?;
+    std::fs::write(settings_path(data_dir), contents)?;
+    Ok(())
+}
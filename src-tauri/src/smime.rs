@@ -0,0 +1,207 @@
+use base64::prelude::*;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+
+/// Longest line RFC 5322 allows, without the CRLF.
+const MAX_LINE_LENGTH: usize = 998;
+
+/// An RFC 5322 message split into the headers that stay on the outer
+/// message and the MIME entity the signature covers.
+pub struct PreparedMessage {
+    outer_headers: Vec<String>,
+    entity: Vec<u8>,
+}
+
+/// Splits raw message bytes at the first empty line. Headers keep their
+/// continuation lines.
+fn split_message(message: &[u8]) -> Result<(Vec<String>, Vec<u8>), Box<dyn Error>> {
+    let text = canonical_line_endings(message);
+    let (head, body) = match find(&text, b"\r\n\r\n") {
+        Some(end) => (&text[..end], text[end + 4..].to_vec()),
+        None => (text.strip_suffix(b"\r\n").unwrap_or(&text), Vec::new()),
+    };
+    let head = std::str::from_utf8(head).map_err(|_| "The message headers are not ASCII")?;
+
+    let mut headers: Vec<String> = Vec::new();
+    for line in head.split("\r\n") {
+        if line.starts_with([' ', '\t']) {
+            let last = headers
+                .last_mut()
+                .ok_or("The message starts with a continuation line")?;
+            last.push_str("\r\n");
+            last.push_str(line);
+        } else if line.contains(':') {
+            headers.push(line.to_string());
+        } else {
+            return Err(format!("Malformed header line {:?}", line).into());
+        }
+    }
+    Ok((headers, body))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Rewrites bare LF and CR line endings as CRLF, which is what S/MIME signs.
+fn canonical_line_endings(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut bytes = data.iter().peekable();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'\r' => {
+                if bytes.peek() == Some(&&b'\n') {
+                    bytes.next();
+                }
+                out.extend_from_slice(b"\r\n");
+            }
+            b'\n' => out.extend_from_slice(b"\r\n"),
+            _ => out.push(byte),
+        }
+    }
+    out
+}
+
+fn header_name(header: &str) -> String {
+    header
+        .split(':')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+fn header_value(headers: &[String], name: &str) -> Option<String> {
+    headers
+        .iter()
+        .find(|header| header_name(header) == name)
+        .and_then(|header| header.split_once(':'))
+        .map(|(_, value)| value.trim().to_ascii_lowercase())
+}
+
+/// Whether the body can be signed as is: S/MIME signed content must survive
+/// 7-bit transports unchanged.
+fn is_seven_bit(body: &[u8]) -> bool {
+    body.is_ascii()
+        && body
+            .split(|&byte| byte == b'\n')
+            .all(|line| line.len() <= MAX_LINE_LENGTH + 1)
+}
+
+fn wrap_base64(data: &[u8]) -> Vec<u8> {
+    let encoded = BASE64_STANDARD.encode(data);
+    let mut out = Vec::with_capacity(encoded.len() + encoded.len() / 38);
+    for line in encoded.as_bytes().chunks(76) {
+        out.extend_from_slice(line);
+        out.extend_from_slice(b"\r\n");
+    }
+    out
+}
+
+impl PreparedMessage {
+    /// Moves the `Content-*` headers and the body into the entity to be
+    /// signed, re-encoding 8-bit single-part bodies as base64.
+    pub fn new(message: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let (headers, body) = split_message(message)?;
+        let (mut content_headers, outer_headers): (Vec<String>, Vec<String>) = headers
+            .into_iter()
+            .filter(|header| header_name(header) != "mime-version")
+            .partition(|header| header_name(header).starts_with("content-"));
+        if header_value(&content_headers, "content-type").is_none() {
+            content_headers.insert(0, "Content-Type: text/plain; charset=us-ascii".into());
+        }
+
+        let body = if is_seven_bit(&body) {
+            body
+        } else {
+            let is_multipart = header_value(&content_headers, "content-type")
+                .is_some_and(|content_type| content_type.starts_with("multipart/"));
+            let encoding = header_value(&content_headers, "content-transfer-encoding");
+            if is_multipart {
+                return Err("Multipart messages with 8-bit content cannot be signed".into());
+            }
+            if matches!(encoding.as_deref(), Some("base64" | "quoted-printable")) {
+                return Err("The message body does not match its transfer encoding".into());
+            }
+            content_headers.retain(|header| header_name(header) != "content-transfer-encoding");
+            content_headers.push("Content-Transfer-Encoding: base64".into());
+            wrap_base64(&body)
+        };
+
+        let mut entity = content_headers.join("\r\n").into_bytes();
+        entity.extend_from_slice(b"\r\n\r\n");
+        entity.extend_from_slice(&body);
+        Ok(PreparedMessage {
+            outer_headers,
+            entity,
+        })
+    }
+
+    /// The canonical MIME entity the detached CMS signature is made over.
+    pub fn entity(&self) -> &[u8] {
+        &self.entity
+    }
+
+    /// The `Subject` of the message, if it has one.
+    pub fn subject(&self) -> Option<String> {
+        self.outer_headers
+            .iter()
+            .find(|header| header_name(header) == "subject")
+            .and_then(|header| header.split_once(':'))
+            .map(|(_, value)| value.replace("\r\n", "").trim().to_string())
+    }
+
+    /// Builds the `multipart/signed` message (RFC 8551, section 3.5.3)
+    /// carrying the entity and its detached CMS signature.
+    pub fn assemble(self, p7s: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let boundary = format!(
+            "----=_Signed_{}",
+            hex::encode(&Sha256::digest(&self.entity)[..12])
+        );
+        if find(&self.entity, boundary.as_bytes()).is_some() {
+            return Err("The message already contains the signature boundary".into());
+        }
+
+        let mut message = Vec::new();
+        for header in &self.outer_headers {
+            message.extend_from_slice(header.as_bytes());
+            message.extend_from_slice(b"\r\n");
+        }
+        message.extend_from_slice(
+            format!(
+                concat!(
+                    "MIME-Version: 1.0\r\n",
+                    "Content-Type: multipart/signed; protocol=\"application/pkcs7-signature\";",
+                    " micalg=sha-256;\r\n\tboundary=\"{boundary}\"\r\n",
+                    "\r\n",
+                    "This is an S/MIME signed message\r\n",
+                    "\r\n",
+                    "--{boundary}\r\n",
+                ),
+                boundary = boundary
+            )
+            .as_bytes(),
+        );
+        // The CRLF before a boundary belongs to the boundary, so the entity
+        // goes in exactly as signed.
+        message.extend_from_slice(&self.entity);
+        message.extend_from_slice(
+            format!(
+                concat!(
+                    "\r\n--{boundary}\r\n",
+                    "Content-Type: application/pkcs7-signature; name=\"smime.p7s\"\r\n",
+                    "Content-Transfer-Encoding: base64\r\n",
+                    "Content-Disposition: attachment; filename=\"smime.p7s\"\r\n",
+                    "\r\n",
+                ),
+                boundary = boundary
+            )
+            .as_bytes(),
+        );
+        message.extend_from_slice(&wrap_base64(p7s));
+        message.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+        Ok(message)
+    }
+}
@@ -0,0 +1,86 @@
+//! Self-signed certificates for keys generated in tests, standing in for
+//! the token's certificates.
+
+use rsa::pkcs8::EncodePublicKey;
+use rsa::signature::{SignatureEncoding, Signer};
+use sha2::Sha256;
+use std::str::FromStr;
+use std::time::Duration;
+use x509_cert::certificate::{TbsCertificate, Version};
+use x509_cert::der::asn1::{BitString, UtcTime};
+use x509_cert::der::oid::db::rfc5912;
+use x509_cert::der::oid::ObjectIdentifier;
+use x509_cert::der::{Decode, Encode};
+use x509_cert::name::Name;
+use x509_cert::serial_number::SerialNumber;
+use x509_cert::spki::{AlgorithmIdentifierOwned, SubjectPublicKeyInfoOwned};
+use x509_cert::time::{Time, Validity};
+use x509_cert::Certificate;
+
+pub fn p256_key() -> p256::ecdsa::SigningKey {
+    p256::ecdsa::SigningKey::random(&mut rand::thread_rng())
+}
+
+/// A small key, to keep key generation fast in debug builds.
+pub fn rsa_key() -> rsa::RsaPrivateKey {
+    rsa::RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap()
+}
+
+/// A DER certificate for `key`, self-signed with ECDSA and SHA-256.
+pub fn p256_certificate(key: &p256::ecdsa::SigningKey) -> Vec<u8> {
+    let spki = key.verifying_key().to_public_key_der().unwrap();
+    certificate(spki.as_bytes(), rfc5912::ECDSA_WITH_SHA_256, None, |tbs| {
+        let signature: p256::ecdsa::Signature = key.sign(tbs);
+        signature.to_der().as_bytes().to_vec()
+    })
+}
+
+/// A DER certificate for `key`, self-signed with PKCS #1 v1.5 and SHA-256.
+pub fn rsa_certificate(key: &rsa::RsaPrivateKey) -> Vec<u8> {
+    let spki = key.to_public_key().to_public_key_der().unwrap();
+    let signer = rsa::pkcs1v15::SigningKey::<Sha256>::new(key.clone());
+    certificate(
+        spki.as_bytes(),
+        rfc5912::SHA_256_WITH_RSA_ENCRYPTION,
+        Some(x509_cert::der::Any::null()),
+        |tbs| signer.sign(tbs).to_vec(),
+    )
+}
+
+fn certificate(
+    spki_der: &[u8],
+    signature_oid: ObjectIdentifier,
+    signature_parameters: Option<x509_cert::der::Any>,
+    sign: impl FnOnce(&[u8]) -> Vec<u8>,
+) -> Vec<u8> {
+    let algorithm = AlgorithmIdentifierOwned {
+        oid: signature_oid,
+        parameters: signature_parameters,
+    };
+    let name = Name::from_str("CN=Test Signer,O=Test").unwrap();
+    let time =
+        |seconds| Time::UtcTime(UtcTime::from_unix_duration(Duration::from_secs(seconds)).unwrap());
+    let tbs_certificate = TbsCertificate {
+        version: Version::V3,
+        serial_number: SerialNumber::new(&[1]).unwrap(),
+        signature: algorithm.clone(),
+        issuer: name.clone(),
+        validity: Validity {
+            not_before: time(1_700_000_000),
+            not_after: time(2_000_000_000),
+        },
+        subject: name,
+        subject_public_key_info: SubjectPublicKeyInfoOwned::from_der(spki_der).unwrap(),
+        issuer_unique_id: None,
+        subject_unique_id: None,
+        extensions: None,
+    };
+    let signature = sign(&tbs_certificate.to_der().unwrap());
+    Certificate {
+        tbs_certificate,
+        signature_algorithm: algorithm,
+        signature: BitString::from_bytes(&signature).unwrap(),
+    }
+    .to_der()
+    .unwrap()
+}
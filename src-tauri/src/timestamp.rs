@@ -0,0 +1,262 @@
+use crate::certificate;
+use crate::chain::{self, CertificatePool};
+use chrono::{DateTime, Utc};
+use cmpv2::status::PkiStatus;
+use cms::cert::CertificateChoices;
+use cms::content_info::ContentInfo;
+use cms::signed_data::{SignedData, SignerIdentifier};
+use der::asn1::{Int, OctetString};
+use der::oid::db::{rfc5280, rfc5911, rfc5912};
+use der::oid::ObjectIdentifier;
+use der::{Decode, Encode};
+use rand::Rng;
+use reqwest::header::CONTENT_TYPE;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::time::Duration;
+use x509_cert::ext::pkix::{ExtendedKeyUsage, SubjectKeyIdentifier};
+use x509_cert::spki::AlgorithmIdentifierOwned;
+use x509_cert::Certificate;
+use x509_tsp::{MessageImprint, TimeStampReq, TimeStampResp, TspVersion, TstInfo};
+
+/// `id-ct-TSTInfo`, the content type of a time-stamp token.
+const ID_CT_TST_INFO: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.16.1.4");
+
+/// How long to wait for the time-stamping authority to answer.
+const TSA_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// RFC 3161 client for a single time-stamping authority.
+pub struct TimestampClient {
+    url: String,
+    /// Where the TSA certificate's chain has to lead.
+    trust: CertificatePool,
+}
+
+impl TimestampClient {
+    pub fn new(url: impl Into<String>, trust: CertificatePool) -> Self {
+        TimestampClient {
+            url: url.into(),
+            trust,
+        }
+    }
+
+    /// Timestamps the SHA-256 digest of `data` and returns the DER
+    /// `TimeStampToken` once it has been checked to cover `data`.
+    pub fn timestamp(&self, data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let imprint = Sha256::digest(data).to_vec();
+        let nonce = request_nonce();
+        let request = TimeStampReq {
+            version: TspVersion::V1,
+            message_imprint: MessageImprint {
+                hash_algorithm: AlgorithmIdentifierOwned {
+                    oid: rfc5912::ID_SHA_256,
+                    parameters: None,
+                },
+                hashed_message: OctetString::new(imprint.clone())?,
+            },
+            req_policy: None,
+            nonce: Some(Int::new(&nonce)?),
+            cert_req: true,
+            extensions: None,
+        };
+
+        let response = reqwest::blocking::Client::builder()
+            .timeout(TSA_TIMEOUT)
+            .build()?
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/timestamp-query")
+            .body(request.to_der()?)
+            .send()?
+            .error_for_status()?
+            .bytes()?;
+        let response = TimeStampResp::from_der(&response)?;
+        if !matches!(
+            response.status.status,
+            PkiStatus::Accepted | PkiStatus::GrantedWithMods
+        ) {
+            return Err(format!(
+                "The time-stamping authority rejected the request: {:?}",
+                response.status.status
+            )
+            .into());
+        }
+        let token = response
+            .time_stamp_token
+            .ok_or("The time-stamping authority returned no token")?;
+        verify_token(&token, &imprint, Some(&nonce), &self.trust)?;
+        Ok(token.to_der()?)
+    }
+}
+
+/// A random positive 8-byte nonce that ties the response to this request.
+fn request_nonce() -> Vec<u8> {
+    let mut nonce = vec![0; 8];
+    rand::thread_rng().fill(&mut nonce[..]);
+    // Keep the INTEGER positive and minimally encoded.
+    nonce[0] = (nonce[0] & 0x7f) | 0x40;
+    nonce
+}
+
+/// Checks that `token` is a time-stamp over the SHA-256 digest `imprint`
+/// (and `nonce`, if one was sent), signed by a certificate authorized for
+/// time-stamping whose chain leads to an anchor in `trust` and was valid at
+/// the time stamped. Returns that time.
+pub fn verify_token(
+    token: &ContentInfo,
+    imprint: &[u8],
+    nonce: Option<&[u8]>,
+    trust: &CertificatePool,
+) -> Result<DateTime<Utc>, Box<dyn Error>> {
+    if token.content_type != rfc5911::ID_SIGNED_DATA {
+        return Err("The timestamp token is not signed data".into());
+    }
+    let signed_data = SignedData::from_der(&token.content.to_der()?)?;
+    let encap = &signed_data.encap_content_info;
+    if encap.econtent_type != ID_CT_TST_INFO {
+        return Err("The timestamp token does not contain TSTInfo".into());
+    }
+    let tst_der = encap
+        .econtent
+        .as_ref()
+        .ok_or("The timestamp token has no content")?
+        .decode_as::<OctetString>()?;
+    let tst_info = TstInfo::from_der(tst_der.as_bytes())?;
+
+    let message_imprint = &tst_info.message_imprint;
+    if message_imprint.hash_algorithm.oid != rfc5912::ID_SHA_256
+        || message_imprint.hashed_message.as_bytes() != imprint
+    {
+        return Err("The timestamp does not cover the signature".into());
+    }
+    if let Some(nonce) = nonce {
+        if tst_info.nonce.as_ref().map(|n| n.as_bytes()) != Some(nonce) {
+            return Err("The timestamp nonce does not match the request".into());
+        }
+    }
+
+    let signer_info = signed_data
+        .signer_infos
+        .0
+        .iter()
+        .next()
+        .ok_or("The timestamp token has no signer")?;
+    let tsa_certificate = find_signer_certificate(&signed_data, &signer_info.sid)?;
+    check_tsa_certificate(tsa_certificate, &tst_info)?;
+    let seconds = tst_info.gen_time.to_unix_duration().as_secs();
+    let gen_time = DateTime::from_timestamp(seconds as i64, 0).ok_or("Timestamp out of range")?;
+    check_tsa_chain(tsa_certificate, &signed_data, trust, gen_time)?;
+
+    let signed_attrs = signer_info
+        .signed_attrs
+        .as_ref()
+        .ok_or("The timestamp token has no signed attributes")?;
+    let content_digest = certificate::digest(signer_info.digest_alg.oid, tst_der.as_bytes())?;
+    let message_digest = signed_attrs
+        .iter()
+        .find(|attr| attr.oid == rfc5911::ID_MESSAGE_DIGEST)
+        .and_then(|attr| attr.values.iter().next())
+        .ok_or("The timestamp token has no message digest")?
+        .decode_as::<OctetString>()?;
+    if message_digest.as_bytes() != content_digest {
+        return Err("The timestamp token's message digest does not match".into());
+    }
+    certificate::verify_cms_signature(
+        tsa_certificate,
+        signer_info.digest_alg.oid,
+        &signed_attrs.to_der()?,
+        signer_info.signature.as_bytes(),
+    )?;
+
+    Ok(gen_time)
+}
+
+/// The TSA certificate a DER time-stamp token was signed with.
+pub fn token_certificate(token: &[u8]) -> Result<Certificate, Box<dyn Error>> {
+    let token = ContentInfo::from_der(token)?;
+    let signed_data = SignedData::from_der(&token.content.to_der()?)?;
+    let signer_info = signed_data
+        .signer_infos
+        .0
+        .iter()
+        .next()
+        .ok_or("The timestamp token has no signer")?;
+    Ok(find_signer_certificate(&signed_data, &signer_info.sid)?.clone())
+}
+
+fn find_signer_certificate<'a>(
+    signed_data: &'a SignedData,
+    sid: &SignerIdentifier,
+) -> Result<&'a Certificate, Box<dyn Error>> {
+    let certificates = signed_data
+        .certificates
+        .as_ref()
+        .ok_or("The timestamp token does not include the TSA certificate")?;
+    certificates
+        .0
+        .iter()
+        .filter_map(|choice| match choice {
+            CertificateChoices::Certificate(cert) => Some(cert),
+            _ => None,
+        })
+        .find(|cert| {
+            let tbs = &cert.tbs_certificate;
+            match sid {
+                SignerIdentifier::IssuerAndSerialNumber(id) => {
+                    tbs.issuer == id.issuer && tbs.serial_number == id.serial_number
+                }
+                SignerIdentifier::SubjectKeyIdentifier(id) => {
+                    matches!(tbs.get::<SubjectKeyIdentifier>(), Ok(Some((_, ski))) if ski == *id)
+                }
+            }
+        })
+        .ok_or_else(|| "The TSA certificate is missing from the timestamp token".into())
+}
+
+/// Builds the TSA certificate's chain from `trust` and the certificates in
+/// the token, as of the time stamped.
+fn check_tsa_chain(
+    tsa_certificate: &Certificate,
+    signed_data: &SignedData,
+    trust: &CertificatePool,
+    gen_time: DateTime<Utc>,
+) -> Result<(), Box<dyn Error>> {
+    let mut pool = trust.clone();
+    for choice in signed_data.certificates.iter().flat_map(|set| set.0.iter()) {
+        if let CertificateChoices::Certificate(cert) = choice {
+            pool.add_intermediate(cert.clone());
+        }
+    }
+    let validation = chain::validate(&tsa_certificate.to_der()?, &pool, gen_time)?;
+    if !validation.trusted {
+        return Err("The TSA certificate does not chain to a trusted root".into());
+    }
+    if !validation.is_valid() {
+        return Err(format!(
+            "The TSA certificate chain is invalid: {}",
+            validation.errors.join("; ")
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// RFC 3161 requires the TSA certificate to carry the time-stamping extended
+/// key usage.
+fn check_tsa_certificate(cert: &Certificate, tst_info: &TstInfo) -> Result<(), Box<dyn Error>> {
+    let tbs = &cert.tbs_certificate;
+    let authorized = match tbs.get::<ExtendedKeyUsage>()? {
+        Some((_, usage)) => usage.0.contains(&rfc5280::ID_KP_TIME_STAMPING),
+        None => false,
+    };
+    if !authorized {
+        return Err("The TSA certificate is not authorized for time-stamping".into());
+    }
+
+    let gen_time = tst_info.gen_time.to_unix_duration();
+    if gen_time < tbs.validity.not_before.to_unix_duration()
+        || gen_time > tbs.validity.not_after.to_unix_duration()
+    {
+        return Err("The TSA certificate was not valid at the time stamped".into());
+    }
+    Ok(())
+}
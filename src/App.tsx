@@ -1,6 +1,7 @@
 import { InitialScreen } from './components/InitialScreen'
 import SignPopup from './components/SignPopup'
 import CertPopup from './components/CertPopup'
+import BatchPopup from './components/BatchPopup'
 
 function App() {
   if (window.location.href.includes('popup.html')) {
@@ -11,6 +12,10 @@ function App() {
     return <CertPopup />
   }
 
+  if (window.location.href.includes('batch.html')) {
+    return <BatchPopup />
+  }
+
   return (
     <>
       <InitialScreen />
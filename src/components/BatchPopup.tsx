@@ -0,0 +1,179 @@
+import React, { useState, useEffect } from 'react'
+import { invoke } from '@tauri-apps/api/core'
+import { Icon } from '@iconify/react/dist/iconify.js'
+import { FormInput } from './FormInput'
+import { PinPadNotice } from './PinPadNotice'
+import govSmartLogo from '../assets/GovSmart_Logo_Black.svg'
+
+interface CertificateSummary {
+  subject: string
+  issuer: string
+  serial_number: string
+  not_before: string
+  not_after: string
+}
+
+interface PendingBatchItem {
+  document_name: string | null
+  doc_hash: string
+  hash_algorithm: string
+}
+
+interface PendingBatch {
+  origin: string | null
+  certificate: CertificateSummary | null
+  items: PendingBatchItem[]
+  chain: CertificateSummary[]
+  session_minutes: number
+  pin_pad: boolean
+}
+
+const BatchPopup = () => {
+  const [pin, setPin] = useState('')
+  const [error, setError] = useState<string | null>(null)
+  const [loading, setLoading] = useState(false)
+  const [keepUnlocked, setKeepUnlocked] = useState(false)
+  const [batch, setBatch] = useState<PendingBatch | null>(null)
+
+  useEffect(() => {
+    invoke<PendingBatch | null>('get_pending_batch')
+      .then(setBatch)
+      .catch((err) => {
+        console.error('Error invoking get_pending_batch:', err)
+        setError((err as unknown as Error).toString())
+      })
+  }, [])
+
+  const handleSubmit = async (e: React.FormEvent<HTMLFormElement>) => {
+    e.preventDefault()
+    setLoading(true)
+    setError(null)
+
+    try {
+      await invoke('complete_batch_signing', { pin, keepUnlocked })
+      window.close()
+    } catch (err) {
+      console.error('Error invoking complete_batch_signing:', err)
+      setError((err as unknown as Error).toString())
+    } finally {
+      setLoading(false)
+    }
+  }
+
+  const count = batch?.items.length ?? 0
+
+  return (
+    <div
+      className="relative overflow-hidden min-h-screen flex flex-col items-center justify-center"
+      style={
+        {
+          '--color-primary-ornament': '147 51 234',
+        } as React.CSSProperties
+      }
+    >
+      <div className="bg-primary-ornament transition-all duration-500 absolute top-0 left-1/2 -translate-x-1/2 -translate-y-1/2 w-[max(75vh,75vh)] h-[max(75vh,75vh)] rounded-full z-0 blur-[90px]"></div>
+
+      <img src={govSmartLogo} alt="GovSmart Logo" className="w-64 h-36 z-20 opacity-90 mb-6" />
+
+      <div
+        className="relative backdrop-blur-md p-8 rounded-3xl drop-shadow-md w-[36rem] z-10"
+        style={{
+          background:
+            'radial-gradient(circle at top left, rgba(233, 213, 255, 0.5), transparent 30%), radial-gradient(circle at bottom right, rgba(233, 213, 255, 0.5), transparent 30%), linear-gradient(to bottom right, rgba(255, 255, 255, 0.95), rgba(255, 255, 255, 0.85))',
+        }}
+      >
+        <h2 className="text-2xl font-bold mb-2 text-center text-purple-800">Sign {count} Documents</h2>
+
+        {batch && (
+          <div className="text-sm text-center text-gray-600 mb-4">
+            <p>Requested by {batch.origin ?? 'an unknown origin'}</p>
+            {batch.certificate && <p className="break-all">with {batch.certificate.subject}</p>}
+            {batch.chain.length > 1 && (
+              <p className="break-all">
+                issued through{' '}
+                {batch.chain
+                  .slice(1)
+                  .map((cert) => cert.subject)
+                  .join(' → ')}
+              </p>
+            )}
+          </div>
+        )}
+
+        {batch && (
+          <ul className="bg-white/70 border border-purple-200 rounded-lg divide-y divide-purple-100 max-h-60 overflow-y-auto mb-6">
+            {batch.items.map((item, index) => (
+              <li key={index} className="flex items-center p-2">
+                <Icon icon="mdi:file-document-outline" className="h-5 w-5 mr-2 text-purple-600 shrink-0" />
+                <div className="text-left min-w-0">
+                  <p className="text-sm font-medium text-gray-900 truncate">
+                    {item.document_name ?? 'Untitled document'}
+                  </p>
+                  <p className="text-xs text-gray-500 truncate">
+                    {item.hash_algorithm}: {item.doc_hash}
+                  </p>
+                </div>
+              </li>
+            ))}
+          </ul>
+        )}
+
+        {error && (
+          <div className="text-red-500 text-center mb-4">
+            <Icon icon="mdi:alert-circle" className="inline-block mr-2 h-5 w-5" />
+            {error.toString()}
+          </div>
+        )}
+
+        <form onSubmit={handleSubmit}>
+          {batch?.pin_pad ? (
+            <PinPadNotice waiting={loading} />
+          ) : (
+            <FormInput
+              id="pin"
+              type="password"
+              value={pin}
+              onChange={(e) => setPin(e.target.value)}
+              placeholder="Enter PIN"
+              label="Enter your PIN once to sign all documents"
+            />
+          )}
+
+          {batch && batch.session_minutes > 0 && (
+            <label className="flex items-center gap-2 mt-4 text-sm text-gray-700">
+              <input
+                type="checkbox"
+                checked={keepUnlocked}
+                onChange={(e) => setKeepUnlocked(e.target.checked)}
+                className="accent-purple-600"
+              />
+              Keep the token unlocked for {batch.origin ?? 'this site'} for {batch.session_minutes} minutes
+            </label>
+          )}
+
+          <div className="flex justify-center mt-6">
+            <button
+              type="submit"
+              disabled={loading || (!pin && !batch?.pin_pad) || count === 0}
+              className="flex items-center gap-2 bg-purple-600 hover:bg-purple-700 text-white px-6 py-3 rounded-lg transition-all duration-300 shadow hover:shadow-md disabled:opacity-50"
+            >
+              {loading ? (
+                <>
+                  <Icon icon="svg-spinners:180-ring" className="animate-spin h-5 w-5" />
+                  Signing...
+                </>
+              ) : (
+                <>
+                  <Icon icon="mdi:file-sign" className="h-5 w-5" />
+                  Sign All
+                </>
+              )}
+            </button>
+          </div>
+        </form>
+      </div>
+    </div>
+  )
+}
+
+export default BatchPopup
@@ -0,0 +1,8 @@
+import { Icon } from '@iconify/react/dist/iconify.js'
+
+export const PinPadNotice: React.FC<{ waiting: boolean }> = ({ waiting }) => (
+  <div className="flex items-center gap-3 bg-white/70 border border-purple-200 rounded-lg p-4 text-gray-700">
+    <Icon icon="mdi:dialpad" className="h-6 w-6 text-purple-600 shrink-0" />
+    <span>{waiting ? 'Enter your PIN on the card reader now.' : 'Your PIN will be entered on the card reader.'}</span>
+  </div>
+)
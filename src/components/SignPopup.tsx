@@ -1,13 +1,71 @@
-import React, { useState } from 'react'
+import React, { useState, useEffect } from 'react'
 import { invoke } from '@tauri-apps/api/core'
 import { Icon } from '@iconify/react/dist/iconify.js'
 import { FormInput } from './FormInput'
+import { PinPadNotice } from './PinPadNotice'
 import govSmartLogo from '../assets/GovSmart_Logo_Black.svg'
 
+interface CertificateSummary {
+  subject: string
+  issuer: string
+  serial_number: string
+  not_before: string
+  not_after: string
+}
+
+interface DocumentPreview {
+  media_type: string
+  data: string
+}
+
+interface PendingRequest {
+  origin: string | null
+  document_name: string | null
+  doc_hash: string
+  hash_algorithm: string
+  certificate: CertificateSummary | null
+  preview: DocumentPreview | null
+  session_minutes: number
+  pin_pad: boolean
+  chain: CertificateSummary[]
+}
+
+const DocumentPreviewFrame: React.FC<{ preview: DocumentPreview }> = ({ preview }) => {
+  const src = `data:${preview.media_type};base64,${preview.data}`
+  if (preview.media_type === 'application/pdf') {
+    return <iframe src={src} title="Document preview" className="w-full h-80 rounded-lg border border-gray-200 mb-6" />
+  }
+  return (
+    <img
+      src={src}
+      alt="Document preview"
+      className="w-full max-h-80 object-contain rounded-lg border border-gray-200 mb-6"
+    />
+  )
+}
+
+const DetailRow: React.FC<{ label: string; value: string }> = ({ label, value }) => (
+  <div className="flex flex-col sm:flex-row sm:gap-2 text-sm">
+    <span className="font-medium text-gray-700 shrink-0">{label}:</span>
+    <span className="text-gray-600 break-all">{value}</span>
+  </div>
+)
+
 const SignPopup = () => {
   const [pin, setPin] = useState('')
   const [error, setError] = useState<string | null>(null)
   const [loading, setLoading] = useState(false)
+  const [keepUnlocked, setKeepUnlocked] = useState(false)
+  const [request, setRequest] = useState<PendingRequest | null>(null)
+
+  useEffect(() => {
+    invoke<PendingRequest | null>('get_pending_request')
+      .then(setRequest)
+      .catch((err) => {
+        console.error('Error invoking get_pending_request:', err)
+        setError((err as unknown as Error).toString())
+      })
+  }, [])
 
   const handleSubmit = async (e: React.FormEvent<HTMLFormElement>) => {
     e.preventDefault()
@@ -15,7 +73,7 @@ const SignPopup = () => {
     setError(null)
 
     try {
-      await invoke('complete_signing', { pin })
+      await invoke('complete_signing', { pin, keepUnlocked })
       window.close()
     } catch (err) {
       console.error('Error invoking complete_signing:', err)
@@ -47,6 +105,37 @@ const SignPopup = () => {
       >
         <h2 className="text-2xl font-bold mb-6 text-center text-purple-800">Sign Document</h2>
 
+        {request?.preview && <DocumentPreviewFrame preview={request.preview} />}
+
+        {request && (
+          <div className="bg-white/70 border border-purple-200 rounded-lg p-4 mb-6 space-y-1">
+            <DetailRow label="Requested by" value={request.origin ?? 'Unknown origin'} />
+            <DetailRow label="Document" value={request.document_name ?? 'Untitled document'} />
+            <DetailRow label={`Hash (${request.hash_algorithm})`} value={request.doc_hash} />
+            {request.certificate ? (
+              <>
+                <DetailRow label="Certificate" value={request.certificate.subject} />
+                <DetailRow label="Issued by" value={request.certificate.issuer} />
+                <DetailRow
+                  label="Valid until"
+                  value={new Date(request.certificate.not_after).toLocaleString()}
+                />
+                {request.chain.length > 1 && (
+                  <DetailRow
+                    label="Verified chain"
+                    value={request.chain
+                      .slice(1)
+                      .map((cert) => cert.subject)
+                      .join(' → ')}
+                  />
+                )}
+              </>
+            ) : (
+              <DetailRow label="Certificate" value="Unknown certificate" />
+            )}
+          </div>
+        )}
+
         {error && (
           <div className="text-red-500 text-center mb-4">
             <Icon icon="mdi:alert-circle" className="inline-block mr-2 h-5 w-5" />
@@ -55,19 +144,35 @@ const SignPopup = () => {
         )}
 
         <form onSubmit={handleSubmit}>
-          <FormInput
-            id="pin"
-            type="password"
-            value={pin}
-            onChange={(e) => setPin(e.target.value)}
-            placeholder="Enter PIN"
-            label="Enter your PIN"
-          />
+          {request?.pin_pad ? (
+            <PinPadNotice waiting={loading} />
+          ) : (
+            <FormInput
+              id="pin"
+              type="password"
+              value={pin}
+              onChange={(e) => setPin(e.target.value)}
+              placeholder="Enter PIN"
+              label="Enter your PIN"
+            />
+          )}
+
+          {request && request.session_minutes > 0 && (
+            <label className="flex items-center gap-2 mt-4 text-sm text-gray-700">
+              <input
+                type="checkbox"
+                checked={keepUnlocked}
+                onChange={(e) => setKeepUnlocked(e.target.checked)}
+                className="accent-purple-600"
+              />
+              Keep the token unlocked for {request.origin ?? 'this site'} for {request.session_minutes} minutes
+            </label>
+          )}
 
           <div className="flex justify-center mt-6">
             <button
               type="submit"
-              disabled={loading || !pin}
+              disabled={loading || (!pin && !request?.pin_pad)}
               className="flex items-center gap-2 bg-purple-600 hover:bg-purple-700 text-white px-6 py-3 rounded-lg transition-all duration-300 shadow hover:shadow-md disabled:opacity-50"
             >
               {loading ? (
@@ -0,0 +1,249 @@
+import React, { useState, useEffect } from 'react'
+import { invoke } from '@tauri-apps/api/core'
+import { Icon } from '@iconify/react/dist/iconify.js'
+import { ResponsiveLayout } from '../ResponsiveLayout'
+import { FormInput } from '../FormInput'
+import useCurrentLanguage from '../../hooks/useCurrentLanguage'
+
+const translationsObject = {
+  en: {
+    title: 'Signature History',
+    searchPlaceholder: 'Search by document name, hash, origin or certificate',
+    noEntries: 'No signatures found.',
+    loading: 'Loading history...',
+    untitledDocument: 'Untitled document',
+    documentHash: 'Document hash',
+    certificate: 'Certificate fingerprint',
+    origin: 'Requested by',
+    signature: 'Signature',
+    saveButton: 'Save',
+    deleteButton: 'Delete',
+    saved: 'Signature saved to',
+    secondaryContent: {
+      title: 'Past Signatures',
+      description:
+        'Every signature produced with your token is kept on this computer. If the Gov-Smart platform did not receive a signature, for example because the browser tab was closed, find it here and copy or save it again.',
+    },
+  },
+  ro: {
+    title: 'Istoric Semnături',
+    searchPlaceholder: 'Căutați după nume document, hash, origine sau certificat',
+    noEntries: 'Nu s-au găsit semnături.',
+    loading: 'Se încarcă istoricul...',
+    untitledDocument: 'Document fără nume',
+    documentHash: 'Hash document',
+    certificate: 'Amprentă certificat',
+    origin: 'Solicitat de',
+    signature: 'Semnătură',
+    saveButton: 'Salvează',
+    deleteButton: 'Șterge',
+    saved: 'Semnătura a fost salvată în',
+    secondaryContent: {
+      title: 'Semnături Anterioare',
+      description:
+        'Fiecare semnătură realizată cu token-ul dvs. este păstrată pe acest computer. Dacă platforma Gov-Smart nu a primit o semnătură, de exemplu pentru că fila browserului a fost închisă, o puteți găsi aici pentru a o copia sau salva din nou.',
+    },
+  },
+}
+
+interface HistoryEntry {
+  id: number
+  signed_at: string
+  origin: string | null
+  document_name: string | null
+  doc_hash: string
+  hash_algorithm: string
+  certificate: string
+  cert_fingerprint: string
+  signature: string
+}
+
+export const SignatureHistory: React.FC<{
+  active: boolean
+}> = ({ active }) => {
+  const currentLanguage = useCurrentLanguage()
+  const t = translationsObject[currentLanguage]
+
+  const [query, setQuery] = useState('')
+  const [entries, setEntries] = useState<HistoryEntry[]>([])
+  const [loading, setLoading] = useState(false)
+  const [expandedId, setExpandedId] = useState<number | null>(null)
+  const [message, setMessage] = useState<string | null>(null)
+  const [error, setError] = useState<string | null>(null)
+
+  const loadEntries = async (search: string) => {
+    setLoading(true)
+    setError(null)
+    try {
+      const result = await invoke<HistoryEntry[]>('search_signature_history', {
+        query: search || null,
+      })
+      setEntries(result)
+    } catch (err) {
+      console.error('Error loading signature history:', err)
+      setError((err as string).toString())
+    } finally {
+      setLoading(false)
+    }
+  }
+
+  useEffect(() => {
+    if (!active) {
+      return
+    }
+    const timeout = setTimeout(() => loadEntries(query), 250)
+    return () => clearTimeout(timeout)
+  }, [query, active])
+
+  const handleSave = async (id: number) => {
+    setMessage(null)
+    setError(null)
+    try {
+      const path = await invoke<string | null>('export_signature_history_entry', { id })
+      if (path) {
+        setMessage(`${t.saved} ${path}`)
+      }
+    } catch (err) {
+      setError((err as string).toString())
+    }
+  }
+
+  const handleDelete = async (id: number) => {
+    setError(null)
+    try {
+      await invoke('delete_signature_history_entry', { id })
+      setEntries((current) => current.filter((entry) => entry.id !== id))
+    } catch (err) {
+      setError((err as string).toString())
+    }
+  }
+
+  return (
+    <div
+      className="relative overflow-hidden min-h-screen flex items-center justify-center"
+      style={
+        {
+          '--color-primary-ornament': '147 51 234',
+        } as React.CSSProperties
+      }
+    >
+      <div className="bg-primary-ornament transition-all duration-500 absolute top-0 left-1/2 -translate-x-1/2 -translate-y-1/2 w-[max(75vh,75vh)] h-[max(75vh,75vh)] rounded-full z-0 blur-[90px]"></div>
+
+      <ResponsiveLayout
+        primaryContent={
+          <div className="w-full z-10 relative p-6">
+            <h2 className="text-2xl font-bold mb-6 text-center text-purple-800">{t.title}</h2>
+
+            <div className="relative mb-4">
+              <Icon icon="mdi:magnify" className="absolute left-3 top-1/2 -translate-y-1/2 h-5 w-5 text-gray-400" />
+              <input
+                type="text"
+                value={query}
+                onChange={(e) => setQuery(e.target.value)}
+                placeholder={t.searchPlaceholder}
+                className="w-full pl-10 pr-4 py-2 border border-gray-300 rounded-lg focus:outline-none focus:ring-2 focus:ring-purple-500"
+              />
+            </div>
+
+            {message && <div className="text-green-600 text-center mb-4">{message}</div>}
+            {error && <div className="text-red-500 text-center mb-4">{error}</div>}
+
+            {loading ? (
+              <div className="flex flex-col items-center justify-center p-4">
+                <Icon icon="svg-spinners:180-ring" className="animate-spin h-8 w-8 text-purple-600 mb-2" />
+                <p className="text-gray-700">{t.loading}</p>
+              </div>
+            ) : entries.length === 0 ? (
+              <p className="text-center text-gray-500">{t.noEntries}</p>
+            ) : (
+              <ul className="space-y-3 xl:max-h-[50vh] overflow-y-auto">
+                {entries.map((entry) => (
+                  <li
+                    key={entry.id}
+                    className={`bg-white border ${
+                      expandedId === entry.id ? 'border-purple-500' : 'border-gray-200'
+                    } rounded-lg p-3 shadow-sm hover:shadow-md transition-all cursor-pointer`}
+                    onClick={() => setExpandedId(expandedId === entry.id ? null : entry.id)}
+                  >
+                    <div className="flex items-center justify-between">
+                      <div className="flex items-center">
+                        <Icon icon="mdi:file-sign" className="h-7 w-7 mr-3 text-purple-600" />
+                        <div className="text-left">
+                          <h3 className="font-medium text-gray-900">
+                            {entry.document_name || t.untitledDocument}
+                          </h3>
+                          <p className="text-xs text-gray-500">
+                            {new Date(entry.signed_at).toLocaleString()} · {entry.doc_hash.substring(0, 16)}...
+                          </p>
+                        </div>
+                      </div>
+                      <div className="flex gap-2">
+                        <button
+                          type="button"
+                          className="px-3 py-1 rounded text-sm font-medium bg-gray-100 text-gray-700 hover:bg-purple-100"
+                          onClick={(e) => {
+                            e.stopPropagation()
+                            handleSave(entry.id)
+                          }}
+                        >
+                          {t.saveButton}
+                        </button>
+                        <button
+                          type="button"
+                          className="px-3 py-1 rounded text-sm font-medium bg-gray-100 text-red-600 hover:bg-red-100"
+                          onClick={(e) => {
+                            e.stopPropagation()
+                            handleDelete(entry.id)
+                          }}
+                        >
+                          {t.deleteButton}
+                        </button>
+                      </div>
+                    </div>
+
+                    {expandedId === entry.id && (
+                      <div className="mt-4 text-left text-sm" onClick={(e) => e.stopPropagation()}>
+                        <p className="text-gray-700 break-all">
+                          <span className="font-medium">{t.documentHash}:</span> {entry.doc_hash} (
+                          {entry.hash_algorithm})
+                        </p>
+                        <p className="text-gray-700 break-all">
+                          <span className="font-medium">{t.certificate}:</span> {entry.cert_fingerprint}
+                        </p>
+                        {entry.origin && (
+                          <p className="text-gray-700 break-all mb-2">
+                            <span className="font-medium">{t.origin}:</span> {entry.origin}
+                          </p>
+                        )}
+                        <FormInput
+                          label={t.signature}
+                          id={`signature-${entry.id}`}
+                          value={entry.signature}
+                          type="textarea"
+                          readOnly
+                          minRows={3}
+                          maxRows={5}
+                          copyToClipboard
+                        />
+                      </div>
+                    )}
+                  </li>
+                ))}
+              </ul>
+            )}
+          </div>
+        }
+        secondaryContent={
+          <div className="w-full rounded z-10">
+            <div className="mb-4">
+              <p className="font-semibold transition-all text-lg text-purple-600">{t.secondaryContent.title}</p>
+            </div>
+            <p className="text-gray-500 mb-4">{t.secondaryContent.description}</p>
+          </div>
+        }
+        progressBar={null}
+        navigation={null}
+      />
+    </div>
+  )
+}
@@ -2,6 +2,7 @@ import React, { useState, useRef, useEffect } from 'react'
 import { useForm } from 'react-hook-form'
 import { valibotResolver } from '@hookform/resolvers/valibot'
 import * as v from 'valibot'
+import { invoke } from '@tauri-apps/api/core'
 import { Icon } from '@iconify/react/dist/iconify.js'
 import { ResponsiveLayout } from '../ResponsiveLayout'
 import { Stepper } from '../Stepper'
@@ -11,23 +12,24 @@ import useCurrentLanguage from '../../hooks/useCurrentLanguage'
 const translationsObject = {
   en: {
     title: 'Sign Document with Token',
-    fileHash: 'Document Hash',
-    enterFileHash: 'Enter the document hash to sign',
+    document: 'Document',
+    chooseFile: 'Choose File',
+    noFileChosen: 'No file chosen',
     signButton: 'Sign Document',
     signing: 'Signing...',
-    signedHash: 'Signed Hash',
+    signatureFile: 'Signature saved to',
     backButton: 'Back',
     nextButton: 'Next',
     steps: {
-      enterHash: 'Enter Document Hash',
+      chooseFile: 'Choose Document',
       signDocument: 'Sign Document',
-      result: 'View Signed Hash',
+      result: 'View Signature',
     },
     secondaryContent: {
-      enterHash: {
-        title: 'Document Hash Information',
+      chooseFile: {
+        title: 'Document Selection',
         description:
-          'The document hash is a unique identifier for the document you want to sign. Copy this hash from the Gov-Smart platform and paste it here to proceed with signing.',
+          'Choose the file you want to sign from your computer. The file is read and hashed locally and never leaves this computer, so files of any size can be signed.',
       },
       signDocument: {
         title: 'Certificate Selection',
@@ -37,7 +39,7 @@ const translationsObject = {
       result: {
         title: 'Signature Verification',
         description:
-          'Your document has been successfully signed. Copy the signature hash and paste it back into the Gov-Smart platform to complete the signing process.',
+          'Your document has been successfully signed. A detached signature (.p7s) was saved next to the original file. Keep both files together and send them to whoever needs to verify your signature.',
       },
     },
     errors: {
@@ -51,26 +53,40 @@ const translationsObject = {
     selectButton: 'Select',
     enterPin: 'Enter PIN',
     pinPlaceholder: 'Enter your token PIN',
+    pinPad: 'Enter your PIN on the card reader when it asks for it.',
+    signatureLevel: 'PDF signature level',
+    signatureLevels: {
+      'B-B': 'Basic (B-B)',
+      'B-T': 'With timestamp (B-T)',
+      'B-LT': 'Long-term (B-LT)',
+      'B-LTA': 'Long-term archival (B-LTA)',
+    },
+    visibleSignature: 'Show a signature stamp on the first page',
+    reason: 'Reason',
+    location: 'Location',
+    signedPdfDescription:
+      'Your document has been successfully signed. The signed PDF was saved next to the original file and can be sent as is.',
   },
   ro: {
     title: 'Semnează Document cu Token',
-    fileHash: 'Hash Document',
-    enterFileHash: 'Introduceți hash-ul documentului pentru semnare',
+    document: 'Document',
+    chooseFile: 'Alegeți Fișierul',
+    noFileChosen: 'Niciun fișier ales',
     signButton: 'Semnează Document',
     signing: 'Se semnează...',
-    signedHash: 'Hash Semnat',
+    signatureFile: 'Semnătura a fost salvată în',
     backButton: 'Înapoi',
     nextButton: 'Următorul',
     steps: {
-      enterHash: 'Introduceți Hash Document',
+      chooseFile: 'Alegeți Documentul',
       signDocument: 'Semnează Document',
-      result: 'Vizualizează Hash Semnat',
+      result: 'Vizualizează Semnătura',
     },
     secondaryContent: {
-      enterHash: {
-        title: 'Informații Hash Document',
+      chooseFile: {
+        title: 'Selectarea Documentului',
         description:
-          'Hash-ul documentului este un identificator unic pentru documentul pe care doriți să îl semnați. Copiați acest hash de pe platforma Gov-Smart și lipiți-l aici pentru a continua cu semnarea.',
+          'Alegeți fișierul pe care doriți să îl semnați de pe computerul dvs. Fișierul este citit și procesat local și nu părăsește acest computer, astfel încât pot fi semnate fișiere de orice dimensiune.',
       },
       signDocument: {
         title: 'Selectarea Certificatului',
@@ -80,7 +96,7 @@ const translationsObject = {
       result: {
         title: 'Verificare Semnătură',
         description:
-          'Documentul dvs. a fost semnat cu succes. Copiați hash-ul semnăturii și lipiți-l înapoi în platforma Gov-Smart pentru a finaliza procesul de semnare.',
+          'Documentul dvs. a fost semnat cu succes. O semnătură detașată (.p7s) a fost salvată lângă fișierul original. Păstrați ambele fișiere împreună și trimiteți-le celor care trebuie să verifice semnătura.',
       },
     },
     errors: {
@@ -94,13 +110,35 @@ const translationsObject = {
     selectButton: 'Selectează',
     enterPin: 'Introduceți PIN-ul',
     pinPlaceholder: 'Introduceți PIN-ul token-ului',
+    pinPad: 'Introduceți PIN-ul pe cititorul de card când vi se cere.',
+    signatureLevel: 'Nivelul semnăturii PDF',
+    signatureLevels: {
+      'B-B': 'De bază (B-B)',
+      'B-T': 'Cu marcă temporală (B-T)',
+      'B-LT': 'Pe termen lung (B-LT)',
+      'B-LTA': 'Arhivare pe termen lung (B-LTA)',
+    },
+    visibleSignature: 'Afișează o ștampilă de semnătură pe prima pagină',
+    reason: 'Motiv',
+    location: 'Locație',
+    signedPdfDescription:
+      'Documentul dvs. a fost semnat cu succes. PDF-ul semnat a fost salvat lângă fișierul original și poate fi trimis ca atare.',
   },
 }
 
-type StepType = 'enterHash' | 'signDocument' | 'result'
+type StepType = 'chooseFile' | 'signDocument' | 'result'
+
+type SignatureLevel = 'B-B' | 'B-T' | 'B-LT' | 'B-LTA'
+
+const signatureLevels: SignatureLevel[] = ['B-B', 'B-T', 'B-LT', 'B-LTA']
+
+const isPdf = (path: string) => path.toLowerCase().endsWith('.pdf')
+
+// Bottom-left corner of the page, in PDF points.
+const STAMP_RECT = [36, 36, 266, 106]
 
 interface FormData {
-  fileHash: string
+  filePath: string
   pin: string
 }
 
@@ -110,7 +148,7 @@ interface Certificate {
 }
 
 const validationSchema = v.object({
-  fileHash: v.string(),
+  filePath: v.string(),
   pin: v.optional(v.string()),
 })
 
@@ -124,18 +162,23 @@ export const TokenSignatureTool: React.FC = () => {
   } = useForm<FormData>({
     resolver: valibotResolver(validationSchema),
     defaultValues: {
-      fileHash: '',
+      filePath: '',
       pin: '',
     },
   })
 
-  const [step, setStep] = useState<StepType>('enterHash')
+  const [step, setStep] = useState<StepType>('chooseFile')
   const [loading, setLoading] = useState(false)
-  const [signedHash, setSignedHash] = useState<string | null>(null)
+  const [signaturePath, setSignaturePath] = useState<string | null>(null)
   const [error, setError] = useState<string | null>(null)
   const [certificates, setCertificates] = useState<Certificate[]>([])
   const [loadingCertificates, setLoadingCertificates] = useState(false)
   const [selectedCertId, setSelectedCertId] = useState<string | null>(null)
+  const [pinPad, setPinPad] = useState(false)
+  const [signatureLevel, setSignatureLevel] = useState<SignatureLevel>('B-B')
+  const [visibleSignature, setVisibleSignature] = useState(false)
+  const [reason, setReason] = useState('')
+  const [location, setLocation] = useState('')
 
   const parentDivRef = useRef<HTMLDivElement>(null)
   const contentRef = useRef<HTMLDivElement>(null)
@@ -153,6 +196,9 @@ export const TokenSignatureTool: React.FC = () => {
   useEffect(() => {
     if (step === 'signDocument') {
       fetchCertificates()
+      invoke<boolean>('uses_pin_pad')
+        .then(setPinPad)
+        .catch(() => setPinPad(false))
     }
   }, [step])
 
@@ -182,12 +228,25 @@ export const TokenSignatureTool: React.FC = () => {
     }
   }
 
+  const handleChooseFile = async () => {
+    setError(null)
+    try {
+      const path = await invoke<string | null>('choose_document')
+      if (path) {
+        setValue('filePath', path)
+      }
+    } catch (err) {
+      console.error('Error choosing document:', err)
+      setError((err as string).toString())
+    }
+  }
+
   const handleSelectCertificate = (certId: string) => {
     setSelectedCertId(certId)
   }
 
   const onSubmit = async (data: FormData) => {
-    if (step === 'enterHash') {
+    if (step === 'chooseFile') {
       setStep('signDocument')
     } else if (step === 'signDocument') {
       if (!selectedCertId) {
@@ -199,30 +258,29 @@ export const TokenSignatureTool: React.FC = () => {
       setError(null)
 
       try {
-        const response = await fetch('http://localhost:8811/sign-document', {
-          method: 'POST',
-          headers: {
-            'Content-Type': 'application/json',
-          },
-          body: JSON.stringify({
-            cert_hash: selectedCertId,
-            hash: data.fileHash,
-            timestamp: new Date().toISOString(),
-            signed_certificate: selectedCertId,
-            pin: data.pin,
-          }),
-        })
-
-        if (!response.ok) {
-          throw new Error(`Error: ${response.status}`)
-        }
-
-        const result = await response.json()
-        setSignedHash(result.signature)
+        const path = isPdf(data.filePath)
+          ? await invoke<string>('sign_pdf', {
+              path: data.filePath,
+              certId: selectedCertId,
+              pin: data.pin,
+              level: signatureLevel,
+              options: {
+                reason: reason || null,
+                location: location || null,
+                appearance: visibleSignature ? { page: 1, rect: STAMP_RECT } : null,
+              },
+            })
+          : await invoke<string>('sign_file_detached', {
+              path: data.filePath,
+              certId: selectedCertId,
+              pin: data.pin,
+            })
+        setSignaturePath(path)
+        setValue('pin', '')
         setStep('result')
       } catch (err) {
         console.error('Error signing document:', err)
-        setError((err as Error).message)
+        setError((err as string).toString())
       } finally {
         setLoading(false)
       }
@@ -231,7 +289,7 @@ export const TokenSignatureTool: React.FC = () => {
 
   const goBack = () => {
     if (step === 'signDocument') {
-      setStep('enterHash')
+      setStep('chooseFile')
       setSelectedCertId(null)
     } else if (step === 'result') {
       setStep('signDocument')
@@ -240,8 +298,8 @@ export const TokenSignatureTool: React.FC = () => {
 
   const stepsList = [
     {
-      stepId: 'enterHash',
-      label: translationsObject[currentLanguage].steps.enterHash,
+      stepId: 'chooseFile',
+      label: translationsObject[currentLanguage].steps.chooseFile,
     },
     {
       stepId: 'signDocument',
@@ -292,21 +350,69 @@ export const TokenSignatureTool: React.FC = () => {
                   </h2>
 
                   {/* <form onSubmit={handleSubmit(onSubmit)}> */}
-                  {step === 'enterHash' && (
-                    <FormInput
-                      label={translationsObject[currentLanguage].fileHash}
-                      id="fileHash"
-                      value={watch('fileHash')}
-                      type="textarea"
-                      placeholder={translationsObject[currentLanguage].enterFileHash}
-                      error={errors.fileHash?.message}
-                      onChange={(e) => setValue('fileHash', e.target.value)}
-                    />
+                  {step === 'chooseFile' && (
+                    <div className="flex flex-col items-center gap-4">
+                      <button
+                        type="button"
+                        onClick={handleChooseFile}
+                        className="flex items-center gap-2 bg-purple-600 hover:bg-purple-700 text-white px-6 py-3 rounded-lg transition-all duration-300 shadow hover:shadow-md"
+                      >
+                        <Icon icon="mdi:file-upload" className="h-5 w-5" />
+                        {translationsObject[currentLanguage].chooseFile}
+                      </button>
+                      <p className="text-gray-700 break-all text-center">
+                        <span className="font-medium">{translationsObject[currentLanguage].document}:</span>{' '}
+                        {watch('filePath') || translationsObject[currentLanguage].noFileChosen}
+                      </p>
+                    </div>
                   )}
 
                   {step === 'signDocument' && (
                     <div className="text-center">
-                      <p className="mb-4 text-gray-700">{watch('fileHash')}</p>
+                      <p className="mb-4 text-gray-700 break-all">{watch('filePath')}</p>
+
+                      {isPdf(watch('filePath')) && (
+                        <div className="mb-4 text-left">
+                          <label htmlFor="signatureLevel" className="block text-sm font-medium text-gray-700 mb-1">
+                            {translationsObject[currentLanguage].signatureLevel}
+                          </label>
+                          <select
+                            id="signatureLevel"
+                            value={signatureLevel}
+                            onChange={(e) => setSignatureLevel(e.target.value as SignatureLevel)}
+                            className="w-full border border-gray-200 rounded-lg p-2 bg-white"
+                          >
+                            {signatureLevels.map((level) => (
+                              <option key={level} value={level}>
+                                {translationsObject[currentLanguage].signatureLevels[level]}
+                              </option>
+                            ))}
+                          </select>
+                          <label className="flex items-center gap-2 mt-4 mb-4 text-sm text-gray-700">
+                            <input
+                              type="checkbox"
+                              checked={visibleSignature}
+                              onChange={(e) => setVisibleSignature(e.target.checked)}
+                              className="accent-purple-600"
+                            />
+                            {translationsObject[currentLanguage].visibleSignature}
+                          </label>
+                          <FormInput
+                            label={translationsObject[currentLanguage].reason}
+                            id="reason"
+                            value={reason}
+                            type="text"
+                            onChange={(e) => setReason(e.target.value)}
+                          />
+                          <FormInput
+                            label={translationsObject[currentLanguage].location}
+                            id="location"
+                            value={location}
+                            type="text"
+                            onChange={(e) => setLocation(e.target.value)}
+                          />
+                        </div>
+                      )}
 
                       <div className="bg-purple-50 p-4 rounded-lg mb-6">
                         <div className="flex items-center">
@@ -384,7 +490,13 @@ export const TokenSignatureTool: React.FC = () => {
                         </div>
                       )}
 
-                      {selectedCertId && (
+                      {selectedCertId && pinPad && (
+                        <div className="mb-4 text-gray-700">
+                          {translationsObject[currentLanguage].pinPad}
+                        </div>
+                      )}
+
+                      {selectedCertId && !pinPad && (
                         <div className="mb-4">
                           <FormInput
                             label={translationsObject[currentLanguage].enterPin}
@@ -400,15 +512,15 @@ export const TokenSignatureTool: React.FC = () => {
                     </div>
                   )}
 
-                  {step === 'result' && signedHash && (
+                  {step === 'result' && signaturePath && (
                     <FormInput
-                      label={translationsObject[currentLanguage].signedHash}
-                      id="signedHash"
-                      value={signedHash}
+                      label={translationsObject[currentLanguage].signatureFile}
+                      id="signaturePath"
+                      value={signaturePath}
                       type="textarea"
                       readOnly
-                      minRows={5}
-                      maxRows={5}
+                      minRows={2}
+                      maxRows={3}
                       copyToClipboard
                     />
                   )}
@@ -420,15 +532,15 @@ export const TokenSignatureTool: React.FC = () => {
           }
           secondaryContent={
             <div className="w-full rounded z-10">
-              {step === 'enterHash' && (
+              {step === 'chooseFile' && (
                 <div>
                   <div className="mb-4">
                     <p className="font-semibold transition-all text-lg text-purple-600">
-                      {translationsObject[currentLanguage].secondaryContent.enterHash.title}
+                      {translationsObject[currentLanguage].secondaryContent.chooseFile.title}
                     </p>
                   </div>
                   <p className="text-gray-500 mb-4">
-                    {translationsObject[currentLanguage].secondaryContent.enterHash.description}
+                    {translationsObject[currentLanguage].secondaryContent.chooseFile.description}
                   </p>
                 </div>
               )}
@@ -454,13 +566,15 @@ export const TokenSignatureTool: React.FC = () => {
                     </p>
                   </div>
                   <p className="text-gray-500 mb-4">
-                    {translationsObject[currentLanguage].secondaryContent.result.description}
+                    {isPdf(watch('filePath'))
+                      ? translationsObject[currentLanguage].signedPdfDescription
+                      : translationsObject[currentLanguage].secondaryContent.result.description}
                   </p>
                 </div>
               )}
 
               <div className="flex justify-start mt-6 gap-4">
-                {step !== 'enterHash' && (
+                {step !== 'chooseFile' && (
                   <button
                     type="button"
                     onClick={goBack}
@@ -475,8 +589,8 @@ export const TokenSignatureTool: React.FC = () => {
                     type="submit"
                     disabled={
                       loading ||
-                      (step === 'enterHash' && !watch('fileHash')) ||
-                      (step === 'signDocument' && (!selectedCertId || !watch('pin')))
+                      (step === 'chooseFile' && !watch('filePath')) ||
+                      (step === 'signDocument' && (!selectedCertId || (!pinPad && !watch('pin'))))
                     }
                     className="text-white h-12 w-12 rounded-full flex items-center justify-center transition-all bg-purple-600 hover:bg-purple-700 hover:opacity-90 active:opacity-80 disabled:opacity-30 disabled:pointer-events-none"
                   >
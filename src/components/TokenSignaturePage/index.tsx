@@ -4,23 +4,26 @@ import govSmartLogo from '../../assets/GovSmart_Logo_Black.svg'
 import useCurrentLanguage from '../../hooks/useCurrentLanguage'
 import { TokenSearch } from './TokenSearch'
 import { TokenSignatureTool } from './TokenSignatureTool'
+import { SignatureHistory } from './SignatureHistory'
 
 const translationsObject = {
   en: {
     searchToken: 'Search for Token',
     signDocument: 'Sign Document',
+    signatureHistory: 'Signature History',
     govSmartSignatureTool:
       'The Gov-Smart Token Signature Tool allows you to sign documents using hardware security tokens. This advanced security method provides enhanced protection and compliance with the highest security standards. Search for available tokens, select the certificate you want to use, and sign your documents securely.',
   },
   ro: {
     searchToken: 'Căutare Token',
     signDocument: 'Semnează Document',
+    signatureHistory: 'Istoric Semnături',
     govSmartSignatureTool:
       'Instrumentul de Semnătură cu Token Gov-Smart vă permite să semnați documente folosind token-uri hardware de securitate. Această metodă avansată de securitate oferă protecție îmbunătățită și conformitate cu cele mai înalte standarde de securitate. Căutați token-urile disponibile, selectați certificatul pe care doriți să îl utilizați și semnați documentele în siguranță.',
   },
 }
 
-type ToolType = 'search-token' | 'sign-document'
+type ToolType = 'search-token' | 'sign-document' | 'signature-history'
 
 export const TokenSignaturePage: React.FC<{
   onBack: () => void
@@ -71,13 +74,22 @@ export const TokenSignaturePage: React.FC<{
         </button>
         <button
           onClick={() => handleButtonClick('sign-document')}
-          className={`w-auto flex items-center justify-center transition-all duration-500 pr-6 pl-4 py-2 rounded-l-none gap-1 font-semibold h-12 ${
+          className={`w-auto flex items-center justify-center transition-all duration-500 px-4 py-2 rounded-none gap-1 font-semibold h-12 ${
             selectedComponent === 'sign-document' ? 'bg-purple-700 text-white' : 'bg-gray-200 text-gray-800'
           }`}
         >
           <Icon icon="hugeicons:blockchain-06" className="h-8 w-8" />
           {translationsObject[currentLanguage].signDocument}
         </button>
+        <button
+          onClick={() => handleButtonClick('signature-history')}
+          className={`w-auto flex items-center justify-center transition-all duration-500 pr-6 pl-4 py-2 rounded-l-none gap-1 font-semibold h-12 ${
+            selectedComponent === 'signature-history' ? 'bg-purple-700 text-white' : 'bg-gray-200 text-gray-800'
+          }`}
+        >
+          <Icon icon="mdi:history" className="h-7 w-7" />
+          {translationsObject[currentLanguage].signatureHistory}
+        </button>
       </div>
 
       <div
@@ -136,6 +148,14 @@ export const TokenSignaturePage: React.FC<{
           </div>
         </div>
 
+        <button
+          onClick={() => handleButtonClick('signature-history')}
+          className="w-auto mt-8 flex items-center justify-center transition-all duration-500 px-4 py-2 gap-1 font-semibold h-12 bg-purple-50 text-purple-800 rounded-xl hover:bg-purple-100"
+        >
+          <Icon icon="mdi:history" className="h-5 w-5" />
+          {translationsObject[currentLanguage].signatureHistory}
+        </button>
+
         <button
           onClick={() => onBack()}
           className="w-auto mt-8 flex items-center justify-center transition-all duration-500 px-4 py-2 mr-2 gap-1 font-semibold h-12 bg-gray-100 text-gray-800 rounded-xl hover:bg-gray-200"
@@ -152,7 +172,8 @@ export const TokenSignaturePage: React.FC<{
         className={`${
           selectedComponent !== null &&
           selectedComponent !== 'search-token' &&
-          selectedComponent !== 'sign-document'
+          selectedComponent !== 'sign-document' &&
+          selectedComponent !== 'signature-history'
             ? 'opacity-100 scale-100'
             : 'opacity-0 scale-0 pointer-events-none'
         } duration-500 transition-all absolute top-14 left-1/2 -translate-x-1/2 z-50 hidden xl:flex drop-shadow-3xl rounded-3xl overflow-hidden items-center justify-center`}
@@ -203,6 +224,16 @@ export const TokenSignaturePage: React.FC<{
       >
         <TokenSignatureTool />
       </div>
+
+      <div
+        className={`absolute bg-white min-h-screen min-w-screen transition-all duration-500 top-0 left-0 w-full ${
+          selectedComponent === 'signature-history'
+            ? 'opacity-100 scale-100'
+            : 'opacity-0 scale-125 pointer-events-none'
+        }`}
+      >
+        <SignatureHistory active={selectedComponent === 'signature-history'} />
+      </div>
     </div>
   )
 }